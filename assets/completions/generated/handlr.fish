@@ -0,0 +1,196 @@
+# Print an optspec for argparse to handle cmd's options that are independent of any subcommand.
+function __fish_handlr_global_optspecs
+    string join \n quiet errors-json h/help V/version
+end
+
+function __fish_handlr_needs_command
+    # Figure out if the current invocation already has a command.
+    set -l cmd (commandline -opc)
+    set -e cmd[1]
+    argparse -s (__fish_handlr_global_optspecs) -- $cmd 2>/dev/null
+    or return
+    if set -q argv[1]
+        # Also print the command, so this can be used to figure out what it is.
+        echo $argv[1]
+        return 1
+    end
+    return 0
+end
+
+function __fish_handlr_using_subcommand
+    set -l cmd (__fish_handlr_needs_command)
+    test -z "$cmd"
+    and return 1
+    contains -- $cmd[1] $argv
+end
+
+complete -c handlr -n "__fish_handlr_needs_command" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_needs_command" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_needs_command" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_needs_command" -s V -l version -d 'Print version'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "list" -d 'List default apps and the associated handlers'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "open" -d 'Open a path/URL with its default handler'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "pick" -d 'Pick any handler that could open a path/URL with the selector and open it'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "set" -d 'Set the default handler for mime/extension'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "unset" -d 'Unset the default handler for mime/extension'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "launch" -d 'Launch the handler for specified extension/mime with optional arguments'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "get" -d 'Get handler for this mime/extension'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "info" -d 'Show every handler that could open a mime/extension and where it comes from'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "add" -d 'Add a handler for given mime/extension'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "remove" -d 'Remove a given handler from a given mime/extension'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "purge" -d 'Remove a handler from every mimetype in mimeapps.list'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "rename-handler" -d 'Replace a handler with another in every mimetype in mimeapps.list'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "mime" -d 'Get the mimetype of a given file/URL'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "update-db" -d 'Rebuild the cache of installed desktop entries'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "doctor" -d 'Check mimeapps.list and handlr.toml for common problems'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "status" -d 'Show whether handlr.toml, mimeapps.list and the installed desktop entries could be loaded'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "watch" -d 'Open files/URLs, then open them again whenever mimeapps.list gives them a different handler'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "recent" -d 'Show or clear the handlers last picked with the selector'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "log" -d 'Show the end of handlr\'s log'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "completions" -d 'Print a shell completion script'
+complete -c handlr -n "__fish_handlr_needs_command" -f -a "autocomplete" -d 'Helper subcommand for autocompletion scripts; should be hidden'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l sort-by -d 'What to sort mimetypes by' -r -f -a "mime\t'Alphabetically by mimetype'
+handler\t'By the first handler, grouping mimetypes opened with the same app'"
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l filter -d 'Only show mimetypes where the regex matches the mimetype or a handler' -r
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l category -d 'Only show installed apps in a desktop entry category, e.g. Office or Graphics' -r
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l json -d 'Output handler info as json'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l jsonl -d 'Output handler info as json lines, one mimetype per line'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l tsv -d 'Output each mimetype and its handlers separated by a tab, one mimetype per line'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -s a -l all -d 'Expand wildcards in mimetypes and show global defaults'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l added -d 'Only show added associations'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l system-only -d 'Only show associations from installed desktop entries'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l count -d 'Show how many handlers each mimetype has'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l orphans -d 'Only show associations with handlers that are not installed'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l fix -d 'Remove handlers that are not installed from associations shown by `--orphans`'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand list" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l action -d 'Desktop entry action to open the paths with' -r
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l with -d 'Desktop file or command to open the paths with instead' -r
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l max-depth -d 'How many directories deep to go, where 1 is only the directory\'s own files' -r
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l include -d 'Only open files in directories whose relative path matches this glob' -r
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l exclude -d 'Skip files in directories whose relative path matches this glob' -r
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l env -d 'Set an environment variable for the handlers, may be given multiple times' -r
+complete -c handlr -n "__fish_handlr_using_subcommand open" -s r -l recursive -d 'Open the files in directories instead of the directories themselves'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -s y -l yes -d 'Open more files than the safety limit for `--recursive`'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l confirm -d 'Ask before opening each path with its handler'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l log-cmd -d 'Print each command to stderr before running it'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l no-term-wrap -d 'Never start a terminal emulator for handlers that need a terminal'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l no-gpu-offload -d 'Do not run handlers that prefer it on the non-default GPU'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l split -d 'Run each handler once per path, even if it can open several at once'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l wait -d 'Wait for the handlers to exit'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l print-pid -d 'Print the pid of each handler that is started'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l portal -d 'Open paths with the desktop portal\'s dialog instead of the selector'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l force -d 'Open paths even if they do not exist, i.e. for editors that create them'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand open" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand pick" -l selector -d 'Selector command to use instead of `selector` from ~/.config/handlr/handlr.toml' -r
+complete -c handlr -n "__fish_handlr_using_subcommand pick" -l save -d 'Also make the picked handler the default for the path\'s mimetype'
+complete -c handlr -n "__fish_handlr_using_subcommand pick" -l portal -d 'Open the path with the desktop portal\'s dialog instead of the selector'
+complete -c handlr -n "__fish_handlr_using_subcommand pick" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand pick" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand pick" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l force -d 'Set the handler even if its desktop file cannot be found'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l sync-equivalents -d 'Also set the handler for equivalent mimetypes'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l install -d 'Link a desktop file given by its path into ~/.local/share/applications'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l glob -d 'Set every known mimetype a wildcard matches one by one'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l dry-run -d 'List the mimetypes that would be set without changing anything'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -s y -l yes -d 'Change more than `expand_confirm_threshold` mimetypes without asking'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l no-hooks -d 'Do not run `post_set_hooks` from handlr.toml'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand set" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand unset" -l no-hooks -d 'Do not run `post_set_hooks` from handlr.toml'
+complete -c handlr -n "__fish_handlr_using_subcommand unset" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand unset" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand unset" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l with -d 'Desktop file or command to launch instead' -r
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l env -d 'Set an environment variable for the handler, may be given multiple times' -r
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l action -d 'Desktop entry action to launch the handler with' -r
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l wait -d 'Wait for the handler to exit'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l detach -d 'Detach the handler from the terminal so it keeps running after it is closed'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l no-term-wrap -d 'Never start a terminal emulator for handlers that need a terminal'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l no-gpu-offload -d 'Do not run the handler on the non-default GPU even if it prefers it'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l print-pid -d 'Print the pid of the handler once it is started'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand launch" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand get" -l json -d 'Output handler info as json'
+complete -c handlr -n "__fish_handlr_using_subcommand get" -l actions -d 'List the handler\'s desktop entry actions'
+complete -c handlr -n "__fish_handlr_using_subcommand get" -l installed-only -d 'Skip handlers whose desktop file or program is not installed'
+complete -c handlr -n "__fish_handlr_using_subcommand get" -s v -l verbose -d 'Show every source and mimetype checked to find the handler'
+complete -c handlr -n "__fish_handlr_using_subcommand get" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand get" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand get" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand info" -l mime -d 'Mimetype or file extension to show the handlers of' -r
+complete -c handlr -n "__fish_handlr_using_subcommand info" -l json -d 'Output as json'
+complete -c handlr -n "__fish_handlr_using_subcommand info" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand info" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand info" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand add" -l added -d 'Add to the added associations instead of the default apps'
+complete -c handlr -n "__fish_handlr_using_subcommand add" -l force -d 'Add the handler even if its desktop file cannot be found'
+complete -c handlr -n "__fish_handlr_using_subcommand add" -s y -l yes -d 'Change more than `expand_confirm_threshold` mimetypes without asking'
+complete -c handlr -n "__fish_handlr_using_subcommand add" -l no-hooks -d 'Do not run `post_set_hooks` from handlr.toml'
+complete -c handlr -n "__fish_handlr_using_subcommand add" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand add" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand add" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand remove" -l added -d 'Remove from the added associations instead of the default apps'
+complete -c handlr -n "__fish_handlr_using_subcommand remove" -l wildcard -d 'Also remove the handler from the wildcard for the mimetype, e.g. `video/*` for `video/mp4`'
+complete -c handlr -n "__fish_handlr_using_subcommand remove" -l no-hooks -d 'Do not run `post_set_hooks` from handlr.toml'
+complete -c handlr -n "__fish_handlr_using_subcommand remove" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand remove" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand remove" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand purge" -l dry-run -d 'Show what would be removed without changing anything'
+complete -c handlr -n "__fish_handlr_using_subcommand purge" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand purge" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand purge" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand rename-handler" -l force -d 'Do not check that the new desktop file exists'
+complete -c handlr -n "__fish_handlr_using_subcommand rename-handler" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand rename-handler" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand rename-handler" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand mime" -l json -d 'Output mimetype info as json'
+complete -c handlr -n "__fish_handlr_using_subcommand mime" -l jsonl -d 'Output mimetype info as json lines, one path per line'
+complete -c handlr -n "__fish_handlr_using_subcommand mime" -l hypothetical -l no-stat -d 'Go by names alone, without touching the filesystem'
+complete -c handlr -n "__fish_handlr_using_subcommand mime" -l details -d 'Show the glob that matched each name'
+complete -c handlr -n "__fish_handlr_using_subcommand mime" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand mime" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand mime" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand update-db" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand update-db" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand update-db" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -l json -d 'Output the report as json'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -l regex -d 'Only check regex handlers from handlr.toml'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -l wildcards -d 'Only check for wildcards in mimeapps.list that match the same mimetypes'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -l fix -d 'Remove handlers that cannot be used from mimeapps.list'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -s y -l yes -d 'Remove without asking for confirmation'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand doctor" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand status" -l json -d 'Output the status as json'
+complete -c handlr -n "__fish_handlr_using_subcommand status" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand status" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand status" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand watch" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand watch" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand watch" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand recent" -l clear -d 'Forget every recorded selection'
+complete -c handlr -n "__fish_handlr_using_subcommand recent" -l json -d 'Output recorded selections as json'
+complete -c handlr -n "__fish_handlr_using_subcommand recent" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand recent" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand recent" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand log" -s n -l lines -d 'How many lines to show' -r
+complete -c handlr -n "__fish_handlr_using_subcommand log" -s f -l follow -d 'Keep printing new entries as they are logged'
+complete -c handlr -n "__fish_handlr_using_subcommand log" -l clear -d 'Empty the current log'
+complete -c handlr -n "__fish_handlr_using_subcommand log" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand log" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand log" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand completions" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand completions" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand completions" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c handlr -n "__fish_handlr_using_subcommand autocomplete" -s d -d 'Autocomplete for desktop files present on system'
+complete -c handlr -n "__fish_handlr_using_subcommand autocomplete" -s m -d 'Autocomplete for mimetypes/file extensions'
+complete -c handlr -n "__fish_handlr_using_subcommand autocomplete" -s c -l configured -d 'Autocomplete for mimetypes with an association, along with their handler'
+complete -c handlr -n "__fish_handlr_using_subcommand autocomplete" -l quiet -d 'Never show errors as desktop notifications'
+complete -c handlr -n "__fish_handlr_using_subcommand autocomplete" -l errors-json -d 'Print errors to stderr as json instead'
+complete -c handlr -n "__fish_handlr_using_subcommand autocomplete" -s h -l help -d 'Print help (see more with \'--help\')'
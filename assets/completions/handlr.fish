@@ -24,7 +24,7 @@ function __handlr_autocomplete
   _set_add
   complete -f -c handlr -n '__fish_seen_subcommand_from get' -a '(handlr autocomplete -m)'
   complete -f -c handlr -n '__fish_seen_subcommand_from get' -l 'json'
-  complete -f -c handlr -n '__fish_seen_subcommand_from unset' -a '(handlr autocomplete -m)'
+  complete -f -c handlr -n '__fish_seen_subcommand_from unset' -a '(handlr autocomplete -c)'
   complete -f -c handlr -n '__fish_seen_subcommand_from launch; __fish_prev_arg_in launch' -a '(handlr autocomplete -m)'
 
 end
@@ -0,0 +1,55 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use handlr_regex::{Config, MimeApps};
+use mime::Mime;
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// How many synthetic associations mimeapps.list gets
+const ASSOCIATIONS: usize = 5000;
+
+/// Write a mimeapps.list with `ASSOCIATIONS` exact mimes and a wildcard for each type
+fn synthetic_mimeapps(path: &Path) {
+    let types = ["application", "audio", "image", "text", "video"];
+    let mut raw_conf = String::from("[Default Applications]\n");
+    for i in 0..ASSOCIATIONS {
+        writeln!(
+            raw_conf,
+            "{}/x-synthetic-{}=cmus.desktop;",
+            types[i % types.len()],
+            i
+        )
+        .unwrap();
+    }
+    for type_ in types.iter() {
+        writeln!(raw_conf, "{}/*=emacsclient.desktop;", type_).unwrap();
+    }
+    std::fs::write(path, raw_conf).unwrap();
+}
+
+fn get_handler(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("mimeapps.list");
+    synthetic_mimeapps(&path);
+    let apps = MimeApps::from_paths(
+        &path,
+        &[PathBuf::from("tests")],
+        &Config::default(),
+    )
+    .unwrap();
+
+    let exact = Mime::from_str("text/x-synthetic-2503").unwrap();
+    let wildcard = Mime::from_str("video/x-not-listed").unwrap();
+
+    c.bench_function("get_handler exact", |b| {
+        b.iter(|| apps.get_handler(&exact).unwrap())
+    });
+    c.bench_function("get_handler wildcard", |b| {
+        b.iter(|| apps.get_handler(&wildcard).unwrap())
+    });
+}
+
+criterion_group!(benches, get_handler);
+criterion_main!(benches);
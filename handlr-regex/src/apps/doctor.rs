@@ -0,0 +1,443 @@
+use crate::{
+    apps::{
+        user::read_if_exists, ConfigHandler, MimeApps, MimeappsRule, RegexApps,
+        RegexValidationError,
+    },
+    common::{render_table, split_exec, DesktopEntry, Handler},
+    utils, Config, ErrorKind, Result,
+};
+use itertools::Itertools;
+use mime::Mime;
+use pest::{iterators::Pairs, Parser};
+use serde::Serialize;
+use std::{path::Path, str::FromStr};
+use tabled::Tabled;
+
+/// How bad a problem found by `handlr doctor` is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Something is broken
+    Error,
+    /// Something probably does not do what was intended
+    Warning,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        })
+    }
+}
+
+/// A problem found by `handlr doctor`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Tabled)]
+pub struct Finding {
+    #[tabled(display_with = "ToString::to_string")]
+    pub severity: Severity,
+    pub problem: String,
+    pub fix: String,
+}
+
+impl Finding {
+    fn error(problem: String, fix: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            problem,
+            fix,
+        }
+    }
+
+    fn warning(problem: String, fix: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            problem,
+            fix,
+        }
+    }
+}
+
+/// Check mimeapps.list and handlr.toml for common mistakes,
+/// most severe findings first
+pub fn diagnose(apps: &MimeApps, config: &Config) -> Result<Vec<Finding>> {
+    diagnose_from(apps, config, std::env::var("TERMINAL").ok())
+}
+
+/// Like `diagnose`, but with the `$TERMINAL` given
+fn diagnose_from(
+    apps: &MimeApps,
+    config: &Config,
+    env_terminal: Option<String>,
+) -> Result<Vec<Finding>> {
+    let location = apps.location()?;
+    let raw_conf = read_if_exists(&location)?;
+
+    let mut findings = check_mimeapps(apps, &location, &raw_conf);
+    findings.extend(check_regex_handlers(&config.handlers));
+    findings.extend(check_terminal(apps, config, env_terminal));
+    findings.extend(check_wildcards(apps));
+    findings.sort_by_key(|finding| finding.severity);
+
+    Ok(findings)
+}
+
+/// Print findings as a table or json
+pub fn print_findings(findings: &[Finding], output_json: bool) -> Result<()> {
+    if output_json {
+        println!("{}", serde_json::to_string(findings)?);
+    } else if findings.is_empty() {
+        println!("No problems found");
+    } else {
+        println!("{}", render_table(&findings.to_vec()));
+    }
+
+    Ok(())
+}
+
+/// A handler `handlr doctor --fix` would remove
+#[derive(Debug, Clone, PartialEq, Eq, Tabled)]
+struct BrokenHandler {
+    mime: String,
+    handler: String,
+}
+
+/// Remove handlers whose desktop entries cannot be loaded from mimeapps.list
+///
+/// They are listed first, and the user is asked before anything is removed
+/// unless `yes` is set. Fails with `Cancelled` if the user declines.
+pub fn fix(apps: &mut MimeApps, yes: bool) -> Result<()> {
+    let raw_conf = read_if_exists(&apps.location()?)?;
+    let broken = broken_handlers(apps, &raw_conf);
+    if broken.is_empty() {
+        println!("No broken handlers found");
+        return Ok(());
+    }
+
+    println!("Handlers to remove:");
+    println!("{}", render_table(&broken));
+
+    if !yes && !utils::confirm(&format!("Remove {} handler(s)?", broken.len()))?
+    {
+        return Err(ErrorKind::Cancelled.into());
+    }
+
+    remove_broken_handlers(apps, &broken);
+    apps.save()?;
+    println!("Removed {} handler(s)", broken.len());
+
+    Ok(())
+}
+
+/// Remove each broken handler from the associations of its mime
+fn remove_broken_handlers(apps: &mut MimeApps, broken: &[BrokenHandler]) {
+    for broken in broken {
+        if let Ok(mime) = Mime::from_str(&broken.mime) {
+            apps.remove_from_associations(
+                &mime,
+                &Handler::assume_valid(broken.handler.as_str().into()),
+            );
+        }
+    }
+}
+
+/// Each default or added handler in the raw mimeapps.list
+/// whose desktop entry cannot be loaded
+///
+/// Removed associations are left alone, since they only hide handlers.
+fn broken_handlers(apps: &MimeApps, raw_conf: &str) -> Vec<BrokenHandler> {
+    MimeApps::raw_associations(raw_conf)
+        .into_iter()
+        .flat_map(|(_, mime, value)| {
+            value
+                .split(';')
+                .filter(|s| !s.is_empty())
+                .unique()
+                .filter(|handler| {
+                    apps.get_entry(&Handler::assume_valid((*handler).into()))
+                        .is_err()
+                })
+                .map(|handler| BrokenHandler {
+                    mime: mime.to_owned(),
+                    handler: handler.to_owned(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Check each association in the raw mimeapps.list,
+/// since invalid ones are dropped when it is read normally
+fn check_mimeapps(
+    apps: &MimeApps,
+    location: &Path,
+    raw_conf: &str,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut checked_entries = Vec::<Handler>::new();
+
+    for (i, raw_line) in raw_conf.lines().enumerate() {
+        let at = format!("{}:{}", location.display(), i + 1);
+
+        let Ok(line) = MimeApps::parse(MimeappsRule::line, raw_line) else {
+            findings.push(Finding::warning(
+                format!("{}: malformed line '{}' is ignored", at, raw_line),
+                "Fix or remove the line".into(),
+            ));
+            continue;
+        };
+
+        let Some((name, value)) = property(line) else {
+            continue;
+        };
+
+        match Mime::from_str(name) {
+            Ok(mime) if !mime.subtype().as_str().is_empty() => {}
+            _ => findings.push(Finding::error(
+                format!("{}: '{}' is not a valid mimetype", at, name),
+                "Fix the typo or remove the association".into(),
+            )),
+        }
+
+        let handlers = value.split(';').filter(|s| !s.is_empty());
+
+        for duplicate in handlers.clone().duplicates() {
+            findings.push(Finding::warning(
+                format!("{}: {} is listed more than once", at, duplicate),
+                format!("Remove the extra {}", duplicate),
+            ));
+        }
+
+        for handler in handlers.unique() {
+            let handler = Handler::assume_valid(handler.into());
+            match apps.get_entry(&handler) {
+                Err(e) => findings.push(Finding::error(
+                    format!("{}: {} cannot be used: {}", at, handler, e),
+                    format!(
+                        "Install {} or remove it from the line, e.g. with `handlr unset {}`",
+                        handler, name
+                    ),
+                )),
+                Ok(_) if checked_entries.contains(&handler) => {}
+                Ok(entry) => {
+                    findings.extend(check_exec(&handler.to_string(), &entry));
+                    checked_entries.push(handler);
+                }
+            }
+        }
+    }
+
+    findings
+}
+
+/// Get the name and value of a parsed mimeapps.list line, if it is a property
+fn property(mut line: Pairs<'_, MimeappsRule>) -> Option<(&str, &str)> {
+    // { SOI ~ (section | property | comment)? ~ EOI }
+    let property = line
+        .next()?
+        .into_inner()
+        .find(|pair| pair.as_rule() == MimeappsRule::property)?;
+
+    // { name ~ "=" ~ value }
+    let mut inner_rules = property.into_inner();
+    Some((inner_rules.next()?.as_str(), inner_rules.next()?.as_str()))
+}
+
+/// Check that the program a desktop entry runs is installed
+fn check_exec(name: &str, entry: &DesktopEntry) -> Option<Finding> {
+    let program = split_exec(&entry.exec)?.into_iter().next()?;
+
+    (!utils::is_installed(&program)).then(|| {
+        Finding::error(
+            format!("{} runs '{}', which is not installed", name, program),
+            format!("Install '{}' or fix the entry's Exec key", program),
+        )
+    })
+}
+
+/// Check regex handlers from handlr.toml, as `handlr doctor --regex` does
+pub fn diagnose_regex(config: &Config) -> Vec<Finding> {
+    let mut findings = check_regex_handlers(&config.handlers);
+    findings.sort_by_key(|finding| finding.severity);
+    findings
+}
+
+fn check_regex_handlers(handlers: &[ConfigHandler]) -> Vec<Finding> {
+    let mut findings = RegexApps::validate(handlers)
+        .into_iter()
+        .map(|e| match e {
+            RegexValidationError::InvalidRegex { .. } => Finding::error(
+                e.to_string(),
+                "Fix the regex in handlr.toml".into(),
+            ),
+            RegexValidationError::MissingProgram { ref program, .. } => {
+                Finding::error(
+                    e.to_string(),
+                    format!(
+                        "Install '{}' or fix the handler's exec in handlr.toml",
+                        program
+                    ),
+                )
+            }
+            RegexValidationError::DuplicateRegex { .. } => Finding::warning(
+                e.to_string(),
+                "Remove the regex from one of the handlers".into(),
+            ),
+        })
+        .collect::<Vec<_>>();
+
+    for (i, handler) in handlers.iter().enumerate() {
+        let exec = handler.exec();
+
+        if !handler.invalid_regexes().is_empty() {
+            continue;
+        }
+
+        let shadowed = handlers.len() - i - 1;
+        if handler.is_catch_all() && shadowed > 0 {
+            findings.push(Finding::warning(
+                format!(
+                    "regex handler '{}' matches everything, so {} later regex handler(s) are never used",
+                    exec, shadowed
+                ),
+                "Make its regexes more specific or move it to the end of handlr.toml".into(),
+            ));
+        }
+    }
+
+    findings
+}
+
+/// Check wildcards in mimeapps.list for conflicts, as `handlr doctor --wildcards` does
+pub fn diagnose_wildcards(apps: &MimeApps) -> Vec<Finding> {
+    check_wildcards(apps)
+}
+
+fn check_wildcards(apps: &MimeApps) -> Vec<Finding> {
+    apps.overlapping_wildcards()
+        .into_iter()
+        .map(|(a, b)| {
+            Finding::warning(
+                format!("wildcards {} and {} both match some of the same mimetypes with different handlers", a, b),
+                format!("Set the mimetypes they share individually or remove one with `handlr unset {}`, or `handlr remove {} <handler>` if they are added associations", b, b),
+            )
+        })
+        .collect()
+}
+
+/// Check that a terminal emulator is set if anything needs one
+fn check_terminal(
+    apps: &MimeApps,
+    config: &Config,
+    env_terminal: Option<String>,
+) -> Option<Finding> {
+    let terminal_mime = Mime::from_str("x-scheme-handler/terminal").unwrap();
+    let has_terminal = apps
+        .get_handler(&terminal_mime)
+        .and_then(|handler| apps.get_entry(&handler))
+        .is_ok()
+        || config.terminal_command.is_some()
+        || env_terminal.is_some_and(|t| !t.is_empty());
+
+    let needs_terminal = config.handlers.iter().any(|h| h.terminal())
+        || apps.associated_handlers().any(|handler| {
+            // Broken entries have already been reported
+            apps.get_entry(&handler).is_ok_and(|entry| entry.terminal)
+        });
+
+    (needs_terminal && !has_terminal).then(|| {
+        Finding::warning(
+            "some handlers run in a terminal, but no terminal emulator is set, so handlr will guess one".into(),
+            "Set one with `handlr set x-scheme-handler/terminal <terminal>.desktop`, or set `terminal_command` in handlr.toml".into(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn doctor() -> Result<()> {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "handlers": [
+                { "exec": "handlr-missing-everything %u", "regexes": [".*"] },
+                { "exec": "handlr-missing-freetube %u", "regexes": ["youtu\\.?be"] },
+                { "exec": "handlr-missing-broken %u", "regexes": ["("] },
+                { "exec": "sh %u", "regexes": ["youtu\\.?be"] },
+            ],
+        }))?;
+        let apps = MimeApps::from_paths(
+            Path::new("tests/mimeapps_doctor.list"),
+            &[PathBuf::from("tests")],
+            &config,
+        )?;
+
+        let findings = diagnose_from(&apps, &config, None)?;
+        let expected = [
+            (Severity::Error, "mimeapps_doctor.list:3: nonexistent.desktop cannot be used"),
+            (Severity::Error, "mimeapps_doctor.list:4: 'text' is not a valid mimetype"),
+            (Severity::Error, "missing-program.desktop runs 'handlr-missing-program'"),
+            (Severity::Error, "regex handler 'handlr-missing-everything %u' runs 'handlr-missing-everything', which is not installed"),
+            (Severity::Error, "regex handler 'handlr-missing-freetube %u' runs 'handlr-missing-freetube', which is not installed"),
+            (Severity::Error, "regex handler 'handlr-missing-broken %u' has an invalid regex"),
+            (Severity::Error, "regex handler 'handlr-missing-broken %u' runs 'handlr-missing-broken', which is not installed"),
+            (Severity::Warning, "mimeapps_doctor.list:2: emacsclient.desktop is listed more than once"),
+            (Severity::Warning, "is used by both 'handlr-missing-freetube %u' and 'sh %u', so only 'handlr-missing-freetube %u' matches it"),
+            (Severity::Warning, "regex handler 'handlr-missing-everything %u' matches everything, so 3 later"),
+            (Severity::Warning, "no terminal emulator is set"),
+        ];
+
+        assert_eq!(findings.len(), expected.len(), "{:#?}", findings);
+        for (finding, (severity, problem)) in findings.iter().zip(expected) {
+            assert_eq!(finding.severity, severity);
+            assert!(
+                finding.problem.contains(problem),
+                "'{}' does not contain '{}'",
+                finding.problem,
+                problem
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn fix() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let location = dir.path().join("mimeapps.list");
+        // Removed associations to missing handlers are not broken
+        std::fs::write(
+            &location,
+            std::fs::read_to_string("tests/mimeapps_doctor.list")?
+                + "\n[Removed Associations]\ntext/html=nonexistent.desktop;\n",
+        )?;
+
+        let config = Config::default();
+        let mut apps = MimeApps::from_paths(
+            &location,
+            &[PathBuf::from("tests")],
+            &config,
+        )?;
+        let before = broken_handlers(&apps, &read_if_exists(&location)?);
+
+        remove_broken_handlers(&mut apps, &before);
+        apps.save()?;
+        let raw_conf = read_if_exists(&location)?;
+        let after = broken_handlers(&apps, &raw_conf);
+
+        assert_eq!(
+            before,
+            [BrokenHandler {
+                mime: "text/x-java".into(),
+                handler: "nonexistent.desktop".into(),
+            }]
+        );
+        assert!(after.is_empty());
+        assert!(raw_conf.contains("text/plain=emacsclient.desktop"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use crate::Handler;
+use mime::Mime;
+use std::process::{Command, Stdio};
+
+/// Placeholders in `post_set_hooks` that are replaced for each changed mime
+const PLACEHOLDERS: [&str; 2] = ["{mime}", "{handler}"];
+
+/// Run `hooks` from `post_set_hooks` in handlr.toml one after another,
+/// after mimeapps.list was saved with `changes`
+///
+/// Each change is a mime along with the handler it is now opened with, if any.
+/// Hooks with `{mime}` or `{handler}` are run once for each change,
+/// and other hooks are run only once. Nothing is run if nothing changed.
+///
+/// Hooks are run with `sh -c`, so failing hooks only cause a warning,
+/// since mimeapps.list has already been changed by then.
+pub fn run_post_set_hooks(
+    hooks: &[String],
+    changes: &[(Mime, Option<Handler>)],
+) {
+    if changes.is_empty() {
+        return;
+    }
+
+    for command in hook_commands(hooks, changes) {
+        run_hook(&command);
+    }
+}
+
+/// The commands to run for `hooks`, in order, with their placeholders replaced
+///
+/// Values are quoted for the shell, and a mime with no handler left gets an empty `{handler}`.
+fn hook_commands(
+    hooks: &[String],
+    changes: &[(Mime, Option<Handler>)],
+) -> Vec<String> {
+    let quote = |value: &str| {
+        shlex::try_quote(value)
+            .map(|quoted| quoted.into_owned())
+            .unwrap_or_default()
+    };
+
+    hooks
+        .iter()
+        .flat_map(|hook| {
+            if PLACEHOLDERS.iter().any(|p| hook.contains(p)) {
+                changes
+                    .iter()
+                    .map(|(mime, handler)| {
+                        let handler = handler
+                            .as_ref()
+                            .map(|handler| handler.to_string())
+                            .unwrap_or_default();
+                        hook.replace("{mime}", &quote(mime.essence_str()))
+                            .replace("{handler}", &quote(&handler))
+                    })
+                    .collect()
+            } else {
+                vec![hook.clone()]
+            }
+        })
+        .collect()
+}
+
+/// Run a hook, warning instead of failing if it does not succeed
+fn run_hook(command: &str) {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", command]).stdin(Stdio::null());
+
+    match cmd.output() {
+        Ok(output) if output.status.success() => {
+            tracing::info!("ran post_set_hooks command `{}`", command)
+        }
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            tracing::warn!(
+                "post_set_hooks command `{}` failed with {}{}",
+                command,
+                output.status,
+                match stderr.trim() {
+                    "" => String::new(),
+                    stderr => format!(": {}", stderr),
+                }
+            )
+        }
+        Err(e) => tracing::warn!(
+            "post_set_hooks command `{}` could not be run: {}",
+            command,
+            e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn hooks_in_order() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let script = dir.path().join("hook.sh");
+        let out = dir.path().join("out");
+        std::fs::write(
+            &script,
+            format!("#!/bin/sh\necho \"$1|$2\" >> '{}'\n", out.display()),
+        )?;
+        std::fs::set_permissions(
+            &script,
+            std::fs::Permissions::from_mode(0o755),
+        )?;
+
+        let script = script.display();
+        let hooks = [
+            format!("{} first {{mime}}", script),
+            format!("{} {{handler}} {{mime}}", script),
+            "exit 1".to_owned(),
+            format!("{} once", script),
+        ];
+        let changes = [
+            (
+                Mime::from_str("text/plain")?,
+                Some(Handler::assume_valid("emacsclient.desktop".into())),
+            ),
+            (Mime::from_str("image/png")?, None),
+        ];
+
+        run_post_set_hooks(&hooks, &[]);
+        assert!(!out.exists());
+
+        // The failing hook does not stop the ones after it
+        run_post_set_hooks(&hooks, &changes);
+        assert_eq!(
+            std::fs::read_to_string(&out)?.lines().collect::<Vec<_>>(),
+            [
+                "first|text/plain",
+                "first|image/png",
+                "emacsclient.desktop|text/plain",
+                "|image/png",
+                "once|",
+            ]
+        );
+
+        Ok(())
+    }
+}
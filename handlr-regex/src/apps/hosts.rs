@@ -0,0 +1,193 @@
+use crate::{
+    common::UserPath, Config, GenericHandler, Handler, RegexHandler, CONFIG,
+};
+
+/// A host or host glob from `[hosts]` in handlr.toml
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    /// A host without wildcards, matching only itself
+    Exact(String),
+    /// `*.example.com`, matching `example.com` and every host under it
+    Subdomains(String),
+    /// Any other glob, matched against the whole host
+    Glob(glob::Pattern),
+}
+
+impl HostPattern {
+    /// Parse a key of `[hosts]`, converting unicode hosts to punycode
+    fn new(pattern: &str) -> Result<Self, glob::PatternError> {
+        let has_glob = |s: &str| s.contains(['*', '?', '[']);
+
+        Ok(match pattern.strip_prefix("*.") {
+            Some(domain) if !has_glob(domain) => {
+                HostPattern::Subdomains(normalize_host(domain))
+            }
+            _ if has_glob(pattern) => {
+                // Only labels without wildcards can be converted to punycode
+                let pattern = pattern
+                    .split('.')
+                    .map(|label| {
+                        if has_glob(label) {
+                            label.to_lowercase()
+                        } else {
+                            normalize_host(label)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(".");
+                HostPattern::Glob(glob::Pattern::new(&pattern)?)
+            }
+            _ => HostPattern::Exact(normalize_host(pattern)),
+        })
+    }
+
+    /// Whether the pattern matches `host`, which must already be normalized
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(exact) => host == exact,
+            HostPattern::Subdomains(domain) => {
+                host == domain
+                    || host
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|sub| sub.ends_with('.'))
+            }
+            HostPattern::Glob(glob) => glob.matches(host),
+        }
+    }
+
+    /// How specific the pattern is, where the most specific pattern that matches wins
+    fn specificity(&self) -> (u8, usize) {
+        match self {
+            HostPattern::Exact(exact) => (2, exact.len()),
+            HostPattern::Subdomains(domain) => (1, domain.len()),
+            HostPattern::Glob(glob) => (0, glob.as_str().len()),
+        }
+    }
+}
+
+/// Lowercase a host, converting it to punycode if it is an internationalized domain name
+///
+/// Hosts in http and https URLs are already punycode, so this lets `[hosts]` use either form.
+fn normalize_host(host: &str) -> String {
+    let host = host.trim_end_matches('.');
+    idna::domain_to_ascii(host).unwrap_or_else(|_| host.to_lowercase())
+}
+
+/// Handlers for http and https URLs by their host, from `[hosts]` in handlr.toml
+///
+/// Each handler is a desktop file or a command like a regex handler's `exec`.
+#[derive(Debug, Clone, Default)]
+pub struct HostApps(Vec<(HostPattern, GenericHandler)>);
+
+impl HostApps {
+    pub fn populate() -> Self {
+        Self::from_config(&CONFIG)
+    }
+
+    /// Read `[hosts]` from the given config, skipping invalid globs with a warning
+    pub fn from_config(config: &Config) -> Self {
+        let mut hosts = config
+            .hosts
+            .iter()
+            .filter_map(|(pattern, handler)| {
+                let pattern = HostPattern::new(pattern)
+                    .map_err(|e| {
+                        tracing::warn!(
+                            "skipping host '{}' in [hosts]: {}",
+                            pattern,
+                            e
+                        )
+                    })
+                    .ok()?;
+                let handler = if handler.ends_with(".desktop") {
+                    GenericHandler::Handler(Handler::assume_valid(
+                        handler.into(),
+                    ))
+                } else {
+                    GenericHandler::RegexHandler(RegexHandler::from_exec(
+                        handler,
+                    ))
+                };
+                Some((pattern, handler))
+            })
+            .collect::<Vec<_>>();
+        // Stable, so equally specific patterns are tried in alphabetical order
+        hosts.sort_by_key(|(pattern, _)| {
+            std::cmp::Reverse(pattern.specificity())
+        });
+        Self(hosts)
+    }
+
+    /// Get the handler for an http or https URL from the most specific pattern matching its host
+    pub fn get_handler(&self, path: &UserPath) -> Option<GenericHandler> {
+        let UserPath::Url { url, .. } = path else {
+            return None;
+        };
+        if !matches!(url.scheme(), "http" | "https") {
+            return None;
+        }
+
+        let host = normalize_host(url.host_str()?);
+        self.0
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&host))
+            .map(|(_, handler)| handler.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn host_handlers() -> Result<()> {
+        let mut config = Config::default();
+        config.hosts = [
+            ("*.github.com", "firefox-work.desktop"),
+            ("gist.github.com", "gist-viewer %u"),
+            ("*.BÜCHER.de", "books %u"),
+            ("docs.*.org", "docs %u"),
+            ("[bad", "nothing %u"),
+        ]
+        .iter()
+        .map(|(host, handler)| (host.to_string(), handler.to_string()))
+        .collect();
+        let hosts = HostApps::from_config(&config);
+        let handler = |url: &str| -> Result<Option<String>> {
+            Ok(hosts
+                .get_handler(&UserPath::from_str(url)?)
+                .map(|handler| handler.to_string()))
+        };
+
+        let work = Some("firefox-work.desktop".to_owned());
+        assert_eq!(handler("https://github.com/foo")?, work);
+        assert_eq!(handler("https://API.GitHub.com/repos")?, work);
+        assert_eq!(handler("http://a.b.github.com")?, work);
+        // The exact host is more specific than the glob
+        assert_eq!(
+            handler("https://gist.github.com/x")?,
+            Some("gist-viewer %u".to_owned())
+        );
+        // Suffixes only match at a label boundary
+        assert_eq!(handler("https://notgithub.com")?, None);
+
+        // Unicode and punycode hosts are the same host
+        let books = Some("books %u".to_owned());
+        assert_eq!(handler("https://bücher.de/a")?, books);
+        assert_eq!(handler("https://shop.xn--bcher-kva.de/a")?, books);
+
+        assert_eq!(
+            handler("https://docs.rust-lang.org")?,
+            Some("docs %u".to_owned())
+        );
+
+        // Anything else falls through to regex handlers and x-scheme-handler/https
+        assert_eq!(handler("https://example.com")?, None);
+        assert_eq!(handler("ftp://github.com/foo")?, None);
+        assert_eq!(handler("tests/cmus.desktop")?, None);
+
+        Ok(())
+    }
+}
@@ -1,7 +1,29 @@
+mod doctor;
+mod hooks;
+mod hosts;
+mod recent;
 mod regex;
+mod status;
 mod system;
 mod user;
+mod watch;
+mod wildcards;
 
-pub use self::regex::{ConfigHandler, RegexApps, RegexHandler};
+pub use self::regex::{
+    ConfigHandler, RegexApps, RegexHandler, RegexValidationError,
+};
+pub use doctor::{
+    diagnose, diagnose_regex, diagnose_wildcards, fix, print_findings, Finding,
+    Severity,
+};
+pub use hooks::run_post_set_hooks;
+pub use hosts::HostApps;
+pub use recent::{RecentSelection, RecentSelections};
+pub use status::{print_status, status, SourceStatus};
 pub use system::SystemApps;
-pub use user::{MimeApps, Rule as MimeappsRule, APPS};
+pub use user::{
+    HandlerSource, ListFormat, ListRows, MimeApps, Picked, Resolution,
+    Rule as MimeappsRule, SortBy, APPS,
+};
+pub use watch::watch;
+pub use wildcards::{is_wildcard, wildcard_matches, WildcardExpansions};
@@ -0,0 +1,203 @@
+use crate::{common::Handler, render_table, utils, Result};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tabled::Tabled;
+
+/// The handler picked with the selector for a mime, and when
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentSelection {
+    /// Desktop file name, kept as a string since Handler serializes OS-specifically
+    pub handler: String,
+    /// Seconds since the Unix epoch
+    pub time: u64,
+}
+
+/// The most recently selected handler for each mime
+/// when `remember_selection` is enabled
+///
+/// Stored in $XDG_CACHE_HOME/handlr/recent.json, so mimeapps.list is left alone.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecentSelections(BTreeMap<String, RecentSelection>);
+
+impl RecentSelections {
+    fn path() -> Result<PathBuf> {
+        Ok(xdg::BaseDirectories::with_prefix("handlr")?
+            .place_cache_file("recent.json")?)
+    }
+
+    /// Load recorded selections, starting fresh if there are none
+    pub fn load() -> Self {
+        match Self::path() {
+            Ok(path) => Self::load_from(&path),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn load_from(path: &Path) -> Self {
+        match std::fs::read(path) {
+            Ok(raw) => serde_json::from_slice(&raw).unwrap_or_else(|e| {
+                tracing::warn!(
+                    "could not read recent selections from {}: {}",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Get the handler most recently selected for `mime`
+    pub fn get(&self, mime: &Mime) -> Option<Handler> {
+        self.0.get(mime.essence_str()).map(|selection| {
+            Handler::assume_valid(selection.handler.clone().into())
+        })
+    }
+
+    /// Record that `handler` was just selected for `mime`
+    pub fn record(mime: &Mime, handler: &Handler) -> Result<()> {
+        Self::update(&Self::path()?, |recent| {
+            recent.0.insert(
+                mime.essence_str().to_owned(),
+                RecentSelection {
+                    handler: handler.to_string(),
+                    time: now(),
+                },
+            );
+        })
+    }
+
+    /// Forget every recorded selection
+    pub fn clear() -> Result<()> {
+        Self::update(&Self::path()?, |recent| recent.0.clear())
+    }
+
+    /// Change the selections stored at `path`
+    ///
+    /// Several handlr processes may be started at once, i.e. by a file manager,
+    /// so the file is locked while it is read and changed,
+    /// and replaced all at once so that it is never read half-written.
+    fn update(path: &Path, f: impl FnOnce(&mut Self)) -> Result<()> {
+        let lock = utils::lock_file(&path.with_extension("lock"))?;
+
+        let mut recent = Self::load_from(path);
+        f(&mut recent);
+
+        utils::write_atomic(path, &serde_json::to_vec(&recent)?)?;
+
+        // Closing the lock file releases the lock
+        drop(lock);
+        Ok(())
+    }
+
+    /// Print recorded selections as a table or json, most recent first
+    pub fn print(&self, output_json: bool) -> Result<()> {
+        if output_json {
+            println!("{}", serde_json::to_string(self)?);
+            return Ok(());
+        }
+
+        let now = now();
+        let mut rows = self
+            .0
+            .iter()
+            .map(|(mime, selection)| RecentRow {
+                mime: mime.clone(),
+                handler: selection.handler.clone(),
+                selected: ago(now.saturating_sub(selection.time)),
+                time: selection.time,
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by_key(|row| std::cmp::Reverse(row.time));
+
+        println!("{}", render_table(&rows));
+        Ok(())
+    }
+}
+
+/// Internal helper struct for turning RecentSelections into tabular data
+#[derive(Tabled)]
+struct RecentRow {
+    mime: String,
+    handler: String,
+    selected: String,
+    #[tabled(skip)]
+    time: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs())
+}
+
+/// Show how long ago something happened in the largest fitting unit
+fn ago(secs: u64) -> String {
+    let (count, unit) = match secs {
+        0..=59 => return "just now".into(),
+        60..=3599 => (secs / 60, "minute"),
+        3600..=86399 => (secs / 3600, "hour"),
+        _ => (secs / 86400, "day"),
+    };
+
+    format!(
+        "{} {}{} ago",
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn concurrent_updates() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("recent.json");
+
+        let threads = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    RecentSelections::update(&path, |recent| {
+                        recent.0.insert(
+                            format!("text/x-{}", i),
+                            RecentSelection {
+                                handler: format!("{}.desktop", i),
+                                time: i,
+                            },
+                        );
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap()?;
+        }
+        let recent = RecentSelections::load_from(&path);
+
+        assert_eq!(recent.0.len(), 8);
+        assert_eq!(
+            recent.get(&Mime::from_str("text/x-3")?),
+            Some(Handler::assume_valid("3.desktop".into()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn ago() {
+        assert_eq!(super::ago(5), "just now");
+        assert_eq!(super::ago(60), "1 minute ago");
+        assert_eq!(super::ago(7200), "2 hours ago");
+        assert_eq!(super::ago(86400 * 3), "3 days ago");
+    }
+}
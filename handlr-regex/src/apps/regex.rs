@@ -1,36 +1,109 @@
 use crate::{
-    common::{DesktopEntry, ExecMode, UserPath},
-    error::{ErrorKind, Result},
-    CONFIG,
+    common::{join_exec, DesktopEntry, ExecMode, ExecOptions, UserPath},
+    error::{NotFoundKind, Result},
+    utils, Config, CONFIG,
 };
+use itertools::Itertools;
 use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     ffi::OsString,
     hash::{Hash, Hasher},
+    process::Child,
 };
 
 // used for deserializing from config file
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ConfigHandler {
+    /// What to show for the handler in the selector instead of its `exec`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
     exec: String,
     #[serde(default)]
     terminal: bool,
+    /// Whether to start a terminal emulator for `terminal` handlers,
+    /// turned off for ones whose `exec` starts one itself
+    #[serde(default = "default_wrap_terminal")]
+    wrap_terminal: bool,
     regexes: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<String, String>,
+}
+
+fn default_wrap_terminal() -> bool {
+    true
 }
 
 impl ConfigHandler {
+    pub(crate) fn exec(&self) -> &str {
+        &self.exec
+    }
+
+    /// Whether handlr needs to run the handler in a terminal emulator
+    pub(crate) fn terminal(&self) -> bool {
+        self.terminal && self.wrap_terminal
+    }
+
+    /// Whether any of the regexes matches every path, like `.*` or an empty one
+    ///
+    /// Matching an empty string is not enough, since `^$` does too.
+    pub(crate) fn is_catch_all(&self) -> bool {
+        const PROBES: [&str; 4] = [
+            "",
+            "file.txt",
+            "/home/user/Documents/report final.pdf",
+            "https://example.com/path?query=1#fragment",
+        ];
+
+        self.regexes.iter().any(|regex| {
+            regex::Regex::new(regex).is_ok_and(|regex| {
+                PROBES.iter().all(|probe| regex.is_match(probe))
+            })
+        })
+    }
+
+    /// Each regex that does not compile along with why
+    pub(crate) fn invalid_regexes(&self) -> Vec<(&str, regex::Error)> {
+        self.regexes
+            .iter()
+            .filter_map(|regex| {
+                Some((regex.as_str(), regex::Regex::new(regex).err()?))
+            })
+            .collect()
+    }
+
     // convert to RegexHandler
-    fn compile_regex(&self) -> Result<RegexHandler> {
+    pub(crate) fn compile_regex(&self) -> Result<RegexHandler> {
         Ok(RegexHandler {
+            name: self.name.clone(),
             exec: self.exec.clone(),
-            terminal: self.terminal,
+            terminal: self.terminal(),
             regexes: HandlerRegexSet::new(self.regexes.clone())?,
+            env: self.env.clone(),
         })
     }
 }
 
+/// A problem with a regex handler from handlr.toml
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RegexValidationError {
+    #[error("regex handler '{exec}' has an invalid regex '{regex}': {reason}")]
+    InvalidRegex {
+        exec: String,
+        regex: String,
+        reason: String,
+    },
+    #[error("regex handler '{exec}' runs '{program}', which is not installed")]
+    MissingProgram { exec: String, program: String },
+    #[error("regex '{regex}' is used by both '{first}' and '{second}', so only '{first}' matches it")]
+    DuplicateRegex {
+        regex: String,
+        first: String,
+        second: String,
+    },
+}
+
 // wrapping RegexSet in a struct and implementing Eq and Hash for it
 // saves us from having to implement them for RegexHandler as a whole
 // although it complicates method calls a bit
@@ -65,31 +138,104 @@ impl Hash for HandlerRegexSet {
     }
 }
 
+/// Longest part of `exec` shown for a regex handler without a `name`
+const MAX_EXEC_NAME_LEN: usize = 40;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct RegexHandler {
+    name: Option<String>,
     exec: String,
     terminal: bool,
     regexes: HandlerRegexSet,
+    env: BTreeMap<String, String>,
 }
 
 impl RegexHandler {
+    /// Create a handler that runs the given command, without any regexes to match
+    pub fn from_exec(exec: &str) -> Self {
+        RegexHandler {
+            name: None,
+            exec: exec.to_owned(),
+            terminal: false,
+            regexes: HandlerRegexSet(RegexSet::empty()),
+            env: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn exec(&self) -> &str {
+        &self.exec
+    }
+
+    /// What to show for the handler in the selector,
+    /// which is its `name` or else the start of its `exec`
+    pub(crate) fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None if self.exec.chars().count() > MAX_EXEC_NAME_LEN => format!(
+                "{}…",
+                self.exec
+                    .chars()
+                    .take(MAX_EXEC_NAME_LEN)
+                    .collect::<String>()
+            ),
+            None => self.exec.clone(),
+        }
+    }
+
     // kludge together a fake DesktopEntry
     // there's probably a better way to avoid reinventing the wheel with the program execution code
-    fn get_entry(&self) -> DesktopEntry {
+    pub(crate) fn get_entry(&self) -> DesktopEntry {
         //
         DesktopEntry {
-            name: String::from(""),
-            exec: self.exec.clone(),
+            name: self.display_name(),
+            generic_name: None,
+            // Regex handlers are written like shell commands,
+            // so they are requoted the way desktop entries expect
+            exec: join_exec(&shlex::split(&self.exec).unwrap_or_default()),
             file_name: OsString::from(""),
             terminal: self.terminal,
             mimes: Vec::new(),
             categories: HashMap::new(),
+            actions: Vec::new(),
+            env: self.env.clone(),
+            working_dir: None,
+            icon: None,
+            no_display: false,
+            hidden: false,
+            dbus_activatable: false,
+            prefers_non_default_gpu: false,
         }
     }
 
     // open the given paths with handler
-    pub fn open(&self, args: Vec<String>) -> Result<()> {
-        self.get_entry().exec(ExecMode::Open, args)
+    pub fn open(
+        &self,
+        args: Vec<String>,
+        wait: bool,
+        options: &ExecOptions,
+    ) -> Result<Vec<Child>> {
+        self.get_entry()
+            .exec(ExecMode::Open, args, wait, false, options)
+    }
+
+    pub fn launch(
+        &self,
+        args: Vec<String>,
+        wait: bool,
+        detach: bool,
+        options: &ExecOptions,
+    ) -> Result<()> {
+        let children = self.get_entry().exec(
+            ExecMode::Launch,
+            args,
+            wait,
+            detach,
+            options,
+        )?;
+        if wait {
+            utils::wait_all(children)?;
+        }
+        Ok(())
     }
 
     fn is_match(&self, path: &str) -> bool {
@@ -103,21 +249,99 @@ pub struct RegexApps(Vec<RegexHandler>);
 impl RegexApps {
     // convert Config's ConfigHandlers
     pub fn populate() -> Self {
+        Self::from_config(&CONFIG)
+    }
+    // convert the given Config's ConfigHandlers
+    //
+    // Handlers with invalid regexes are skipped with a warning,
+    // so one typo does not break the rest.
+    // Other problems are left to `validate`, which `handlr doctor` runs,
+    // since searching $PATH for every handler's program is too slow to do every time.
+    pub fn from_config(config: &Config) -> Self {
         RegexApps(
-            CONFIG
+            config
                 .handlers
                 .iter()
-                .filter_map(|handler| handler.compile_regex().ok())
+                .filter_map(|handler| {
+                    let compiled = handler.compile_regex().ok();
+                    if compiled.is_none() {
+                        for (regex, e) in handler.invalid_regexes() {
+                            let e = RegexValidationError::InvalidRegex {
+                                exec: handler.exec.clone(),
+                                regex: regex.to_owned(),
+                                reason: e.to_string(),
+                            };
+                            tracing::warn!("{}, so it is skipped", e);
+                        }
+                    }
+                    compiled
+                })
                 .collect(),
         )
     }
+    /// Check that every regex compiles, that every handler's program is installed,
+    /// and that no regex is used by more than one handler
+    pub fn validate(handlers: &[ConfigHandler]) -> Vec<RegexValidationError> {
+        let mut errors = Vec::new();
+        let mut seen = HashMap::<&str, &str>::new();
+
+        for handler in handlers {
+            let exec = handler.exec.clone();
+
+            for (regex, e) in handler.invalid_regexes() {
+                errors.push(RegexValidationError::InvalidRegex {
+                    exec: exec.clone(),
+                    regex: regex.to_owned(),
+                    reason: e.to_string(),
+                });
+            }
+
+            let program = shlex::split(&handler.exec)
+                .and_then(|exec| exec.into_iter().next());
+            if let Some(program) = program {
+                if !utils::is_installed(&program) {
+                    errors.push(RegexValidationError::MissingProgram {
+                        exec: exec.clone(),
+                        program,
+                    });
+                }
+            }
+
+            for regex in handler.regexes.iter().unique() {
+                match seen.get(regex.as_str()) {
+                    Some(first) => {
+                        errors.push(RegexValidationError::DuplicateRegex {
+                            regex: regex.clone(),
+                            first: (*first).to_owned(),
+                            second: exec.clone(),
+                        })
+                    }
+                    None => {
+                        seen.insert(regex, &handler.exec);
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+    // get every matching handler, in order of priority
+    //
+    // Regexes are matched against the argument as it was given, see `UserPath::original`
+    pub fn get_handlers(&self, path: &UserPath) -> Vec<RegexHandler> {
+        self.0
+            .iter()
+            .filter(|app| app.is_match(path.original()))
+            .cloned()
+            .collect()
+    }
     // get matching handler
     pub fn get_handler(&self, path: &UserPath) -> Result<RegexHandler> {
         Ok(self
             .0
             .iter()
-            .find(|app| app.is_match(&path.to_string()))
-            .ok_or_else(|| ErrorKind::NotFound(path.to_string()))?
+            .find(|app| app.is_match(path.original()))
+            .ok_or_else(|| NotFoundKind::Path(path.to_string()))?
             .clone())
     }
 }
@@ -134,9 +358,12 @@ mod tests {
             &[String::from(r"(https://)?(www\.)?youtu(be\.com|\.be)/*")];
 
         let config_handler = ConfigHandler {
+            name: None,
             exec: String::from(exec),
             terminal: false,
+            wrap_terminal: true,
             regexes: regexes.to_owned(),
+            env: BTreeMap::new(),
         };
 
         let regex_handler = config_handler
@@ -144,10 +371,12 @@ mod tests {
             .expect("ConfigHandler::compile_regex() returned Err");
 
         let expected_regex_handler = RegexHandler {
+            name: None,
             exec: String::from(exec),
             terminal: false,
             regexes: HandlerRegexSet::new(regexes)
                 .expect("Test regex is invalid"),
+            env: BTreeMap::new(),
         };
 
         assert_eq!(regex_handler, expected_regex_handler);
@@ -156,7 +385,7 @@ mod tests {
 
         assert_eq!(
             regex_apps
-                .get_handler(&UserPath::Url(
+                .get_handler(&UserPath::url(
                     Url::parse("https://youtu.be/dQw4w9WgXcQ").unwrap()
                 ))
                 .expect("RegexApps::get_handler() returned Err"),
@@ -164,11 +393,153 @@ mod tests {
         );
 
         assert!(regex_apps
-            .get_handler(&UserPath::Url(
+            .get_handler(&UserPath::url(
                 Url::parse("https://en.wikipedia.org").unwrap()
             ))
             .is_err());
 
         Ok(())
     }
+
+    #[test]
+    fn original_arguments() -> Result<()> {
+        let handler = |exec: &str, regex: &str| {
+            ConfigHandler {
+                name: None,
+                exec: exec.into(),
+                terminal: false,
+                wrap_terminal: true,
+                regexes: vec![regex.into()],
+                env: BTreeMap::new(),
+            }
+            .compile_regex()
+        };
+        let regex_apps = RegexApps(vec![
+            handler("playlist %u", r"youtube\.com/watch\?.*\blist=")?,
+            handler("video %u", r"youtube\.com/watch\?.*\bv=")?,
+            handler("timestamp %u", r"#t=\d+$")?,
+            handler("literal %f", r"(^|/)a\?b$")?,
+        ]);
+        let exec = |path: &str| -> Result<String> {
+            Ok(regex_apps.get_handler(&path.parse()?)?.exec)
+        };
+
+        assert_eq!(
+            exec("https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123")?,
+            "playlist %u"
+        );
+        assert_eq!(
+            exec("https://www.youtube.com/watch?v=dQw4w9WgXcQ")?,
+            "video %u"
+        );
+        assert_eq!(exec("https://example.com/talk#t=42")?, "timestamp %u");
+
+        // A local file with a question mark in its name is not taken as a query
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("a?b");
+        std::fs::write(&file, "")?;
+        assert_eq!(exec(&file.to_string_lossy())?, "literal %f");
+        assert_eq!(exec("./a?b")?, "literal %f");
+
+        Ok(())
+    }
+
+    #[test]
+    fn wrap_terminal() -> Result<()> {
+        let terminal = |handler: serde_json::Value| -> Result<bool> {
+            let handler: ConfigHandler = serde_json::from_value(handler)?;
+            Ok(handler.compile_regex()?.get_entry().terminal)
+        };
+
+        assert!(terminal(serde_json::json!({
+            "exec": "nvim %f",
+            "terminal": true,
+            "regexes": [r"\.md$"],
+        }))?);
+        // The handler starts its own terminal emulator
+        assert!(!terminal(serde_json::json!({
+            "exec": "wezterm start -- nvim %f",
+            "terminal": true,
+            "wrap_terminal": false,
+            "regexes": [r"\.md$"],
+        }))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn display_name() -> Result<()> {
+        let name = |handler: serde_json::Value| -> Result<String> {
+            let handler: ConfigHandler = serde_json::from_value(handler)?;
+            Ok(handler.compile_regex()?.get_entry().name)
+        };
+
+        assert_eq!(
+            name(serde_json::json!({
+                "name": "FreeTube",
+                "exec": "freetube %u",
+                "regexes": ["youtu\\.?be"],
+            }))?,
+            "FreeTube"
+        );
+        assert_eq!(
+            name(serde_json::json!({
+                "exec": "freetube %u",
+                "regexes": ["youtu\\.?be"],
+            }))?,
+            "freetube %u"
+        );
+        assert_eq!(
+            name(serde_json::json!({
+                "exec": "mpv --profile=stream --ytdl-format=bestvideo+bestaudio %u",
+                "regexes": ["twitch\\.tv"],
+            }))?,
+            "mpv --profile=stream --ytdl-format=bestv…"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn catch_all() {
+        let catch_all = |regex: &str| {
+            ConfigHandler {
+                name: None,
+                exec: String::from("everything %u"),
+                terminal: false,
+                wrap_terminal: true,
+                regexes: vec![regex.to_owned()],
+                env: BTreeMap::new(),
+            }
+            .is_catch_all()
+        };
+
+        assert!(catch_all(".*"));
+        assert!(catch_all("^.*$"));
+        assert!(catch_all(""));
+        assert!(!catch_all("^$"));
+        assert!(!catch_all("youtu\\.?be"));
+    }
+
+    #[test]
+    fn regex_handler_env() -> Result<()> {
+        let config_handler = ConfigHandler {
+            name: None,
+            exec: String::from("mpv %u"),
+            terminal: false,
+            wrap_terminal: true,
+            regexes: vec![String::from(r"\.m3u$")],
+            env: BTreeMap::from([(
+                String::from("MPV_HOME"),
+                String::from("/home/user/.config/mpv-playlist"),
+            )]),
+        };
+
+        assert_eq!(
+            config_handler.compile_regex()?.get_entry().env,
+            config_handler.env
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,161 @@
+use crate::{apps::MimeApps, common::render_table, Config, ErrorKind, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tabled::Tabled;
+
+/// A source of handlers and whether it could be loaded, shown by `handlr status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Tabled)]
+pub struct SourceStatus {
+    pub source: &'static str,
+    /// Where it was loaded from, with directories separated by `:`
+    pub path: String,
+    #[tabled(rename = "status", display_with("Self::display_status", self))]
+    pub loaded: bool,
+    #[tabled(skip)]
+    pub error: Option<String>,
+    /// How many of each thing it has, i.e. `regex_handlers`
+    #[tabled(display_with("Self::display_counts", self))]
+    pub counts: BTreeMap<&'static str, usize>,
+}
+
+impl SourceStatus {
+    fn new(
+        source: &'static str,
+        path: String,
+        error: Option<&str>,
+        counts: &[(&'static str, usize)],
+    ) -> Self {
+        Self {
+            source,
+            path,
+            loaded: error.is_none(),
+            error: error.map(str::to_owned),
+            counts: counts.iter().copied().collect(),
+        }
+    }
+
+    fn display_status(&self) -> String {
+        match &self.error {
+            Some(e) => format!("failed: {}", e),
+            None => "loaded".to_owned(),
+        }
+    }
+
+    fn display_counts(&self) -> String {
+        self.counts
+            .iter()
+            .map(|(name, count)| {
+                format!("{} {}", count, name.replace('_', " "))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Check which of handlr.toml, mimeapps.list and the installed desktop entries were loaded,
+/// along with where they are and what they contain
+pub fn status(apps: &MimeApps, config: &Config) -> Result<Vec<SourceStatus>> {
+    let (default_apps, added_associations) = apps.association_counts();
+    let system_apps = apps.system_apps();
+    let errors = apps.load_errors();
+    let application_dirs = apps
+        .application_dirs()?
+        .iter()
+        .map(|dir| dir.display().to_string())
+        .collect::<Vec<_>>()
+        .join(":");
+
+    Ok(vec![
+        SourceStatus::new(
+            "handlr.toml",
+            Config::path()?.display().to_string(),
+            config.load_error.as_deref(),
+            &[
+                ("regex_handlers", config.handlers.len()),
+                ("host_handlers", config.hosts.len()),
+            ],
+        ),
+        SourceStatus::new(
+            "mimeapps.list",
+            apps.location()?.display().to_string(),
+            errors.mimeapps.as_deref(),
+            &[
+                ("default_associations", default_apps),
+                ("added_associations", added_associations),
+            ],
+        ),
+        SourceStatus::new(
+            "desktop entries",
+            application_dirs,
+            errors.system_apps.as_deref(),
+            &[
+                ("desktop_entries", system_apps.entry_count()),
+                ("mimetypes", system_apps.0.len()),
+            ],
+        ),
+    ])
+}
+
+/// Print each source as a table or json, failing if any could not be loaded
+pub fn print_status(sources: &[SourceStatus], output_json: bool) -> Result<()> {
+    if output_json {
+        println!("{}", serde_json::to_string(sources)?);
+    } else {
+        println!("{}", render_table(&sources.to_vec()));
+    }
+
+    let failed = sources.iter().filter(|source| !source.loaded).count();
+    if failed > 0 {
+        return Err(ErrorKind::LoadFailed(failed).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn load_status() -> Result<()> {
+        let mut config: Config = serde_json::from_value(serde_json::json!({
+            "handlers": [{ "exec": "mpv %u", "regexes": ["youtu\\.?be"] }],
+        }))?;
+        let dirs = [PathBuf::from("tests/shadowing/user")];
+
+        let apps = MimeApps::from_paths(
+            &PathBuf::from("tests/mimeapps_doctor.list"),
+            &dirs,
+            &config,
+        )?;
+        let sources = status(&apps, &config)?;
+        assert!(sources.iter().all(|source| source.loaded));
+        assert_eq!(sources[0].counts["regex_handlers"], 1);
+        assert_eq!(sources[1].path, "tests/mimeapps_doctor.list");
+        assert_eq!(sources[2].path, "tests/shadowing/user");
+        assert_eq!(sources[2].counts["desktop_entries"], 1);
+
+        // A directory cannot be read as mimeapps.list, so it is left empty
+        // and is not overwritten, while everything else is still loaded
+        config.load_error = Some("Bad TOML data".into());
+        let apps =
+            MimeApps::from_paths(&PathBuf::from("tests"), &dirs, &config)?;
+        let sources = status(&apps, &config)?;
+        let loaded = sources
+            .iter()
+            .map(|source| source.loaded)
+            .collect::<Vec<_>>();
+        assert_eq!(loaded, [false, false, true]);
+        assert_eq!(sources[1].counts["default_associations"], 0);
+        assert!(matches!(
+            *apps.save().unwrap_err().kind,
+            ErrorKind::NotLoaded(_, _)
+        ));
+
+        let e = print_status(&sources, true).unwrap_err();
+        assert!(matches!(*e.kind, ErrorKind::LoadFailed(2)));
+
+        Ok(())
+    }
+}
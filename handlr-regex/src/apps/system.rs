@@ -1,53 +1,376 @@
 use crate::{
-    common::{DesktopEntry, Handler},
-    Result,
+    common::{locales, DesktopEntry, Handler},
+    Result, CONFIG,
 };
 use mime::Mime;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, VecDeque},
     convert::TryFrom,
     ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct SystemApps(pub HashMap<Mime, VecDeque<Handler>>);
 
+/// On-disk representation of SystemApps, since Mime cannot be serialized
+#[derive(Serialize, Deserialize)]
+struct SystemAppsCache {
+    /// The locales the entries were read with, see `common::locales`
+    locales: Vec<String>,
+    apps: HashMap<String, VecDeque<Handler>>,
+}
+
 impl SystemApps {
     pub fn get_handlers(&self, mime: &Mime) -> Option<VecDeque<Handler>> {
         Some(self.0.get(mime)?.clone())
     }
     pub fn get_handler(&self, mime: &Mime) -> Option<Handler> {
-        Some(self.get_handlers(mime)?.get(0).unwrap().clone())
+        Some(self.get_handlers(mime)?.front().unwrap().clone())
     }
 
+    /// Parse the desktop entries in `extra_app_dirs` from handlr.toml
+    /// and in the `applications` directories in $XDG_DATA_DIRS
     pub fn get_entries(
     ) -> Result<impl Iterator<Item = (OsString, DesktopEntry)>> {
-        Ok(xdg::BaseDirectories::new()?
-            .list_data_files_once("applications")
-            .into_iter()
+        use itertools::Itertools;
+
+        // Only the first file with each name is listed, like `Handler::get_path`
+        // finds, so the same copy is used for associations and for running it
+        let paths = files_in(&CONFIG.extra_app_dirs)
+            .chain(
+                xdg::BaseDirectories::new()?
+                    .list_data_files_once("applications"),
+            )
+            .unique_by(|p| p.file_name().map(|name| name.to_owned()));
+        Ok(Self::load_entries(paths))
+    }
+
+    /// Parse desktop entries, given in order of priority with a single path per name
+    ///
+    /// Entries with `Hidden=true` count as deleted, so they are left out
+    /// instead of falling back to a lower priority copy.
+    fn load_entries(
+        paths: impl Iterator<Item = PathBuf>,
+    ) -> impl Iterator<Item = (OsString, DesktopEntry)> {
+        paths
             .filter(|p| {
                 p.extension().and_then(|x| x.to_str()) == Some("desktop")
             })
             .filter_map(|p| {
-                Some((
-                    p.file_name().unwrap().to_owned(),
-                    DesktopEntry::try_from(p.clone()).ok()?,
-                ))
-            }))
+                // Links and directories are skipped too, so this is no cause for a warning
+                let entry = DesktopEntry::try_from(p.clone())
+                    .map_err(|e| tracing::debug!("skipping {}", e))
+                    .ok()?;
+                Some((p.file_name()?.to_owned(), entry))
+            })
+            .filter(|(_, entry)| !entry.hidden)
     }
 
     pub fn populate() -> Result<Self> {
+        Ok(Self::from_entries(Self::get_entries()?))
+    }
+
+    /// Scan desktop entries in the given directories only,
+    /// instead of the `applications` directories in $XDG_DATA_DIRS
+    ///
+    /// Earlier directories take priority, like in $XDG_DATA_DIRS.
+    pub fn populate_from(application_dirs: &[PathBuf]) -> Self {
+        use itertools::Itertools;
+
+        let paths = files_in(application_dirs)
+            .unique_by(|p| p.file_name().map(|name| name.to_owned()));
+
+        Self::from_entries(Self::load_entries(paths))
+    }
+
+    fn from_entries(
+        entries: impl Iterator<Item = (OsString, DesktopEntry)>,
+    ) -> Self {
         let mut map = HashMap::<Mime, VecDeque<Handler>>::with_capacity(50);
 
-        Self::get_entries()?.for_each(|(_, entry)| {
+        entries.for_each(|(_, entry)| {
             let (file_name, mimes) = (entry.file_name, entry.mimes);
             mimes.into_iter().for_each(|mime| {
-                map.entry(mime)
-                    .or_default()
-                    .push_back(Handler::assume_valid(file_name.clone()));
+                let handler = Handler::assume_valid(file_name.clone());
+                let handlers = map.entry(mime).or_default();
+                // An entry may list a mime more than once
+                if !handlers.contains(&handler) {
+                    handlers.push_back(handler);
+                }
             });
         });
 
-        Ok(Self(map))
+        Self(map)
+    }
+
+    /// Installed desktop entries in the given category, ignoring case, sorted by desktop file
+    ///
+    /// Categories are not cached, so entries are only read when this is called.
+    /// Entries without any mimetypes are included too.
+    pub fn by_category(&self, category: &str) -> Vec<Handler> {
+        self.by_category_in(category, None)
+            .into_iter()
+            .map(|(handler, _)| handler)
+            .collect()
+    }
+
+    /// Like `by_category`, along with each entry, reading entries from
+    /// `application_dirs` instead of $XDG_DATA_DIRS if given
+    pub(crate) fn by_category_in(
+        &self,
+        category: &str,
+        application_dirs: Option<&[PathBuf]>,
+    ) -> Vec<(Handler, DesktopEntry)> {
+        use itertools::Itertools;
+
+        let entries = match application_dirs {
+            Some(dirs) => Box::new(Self::load_entries(
+                files_in(dirs)
+                    .unique_by(|p| p.file_name().map(|name| name.to_owned())),
+            )) as Box<dyn Iterator<Item = _>>,
+            None => Box::new(Self::get_entries().into_iter().flatten()),
+        };
+        let unassociated = entries
+            .filter(|(_, entry)| entry.mimes.is_empty())
+            .map(|(_, entry)| Handler::assume_valid(entry.file_name));
+
+        self.0
+            .values()
+            .flatten()
+            .cloned()
+            .chain(unassociated)
+            .unique()
+            .filter_map(|handler| {
+                let entry = match application_dirs {
+                    Some(dirs) => handler.get_entry_in(dirs),
+                    None => handler.get_entry(),
+                }
+                .ok()?;
+                entry
+                    .categories
+                    .keys()
+                    .any(|c| c.eq_ignore_ascii_case(category))
+                    .then_some((handler, entry))
+            })
+            .sorted_by(|(a, _), (b, _)| a.cmp(b))
+            .collect()
+    }
+
+    /// Use the cache written by `handlr update-db` if it is newer than every desktop entry
+    /// and was written for the same locale, otherwise scan desktop entries
+    pub fn load() -> Result<Self> {
+        match Self::cache_path()? {
+            Some(cache) if Self::cache_is_fresh(&cache) => {
+                match Self::read_cache(&cache, &locales()) {
+                    Ok(Some(apps)) => Ok(apps),
+                    _ => Self::populate(),
+                }
+            }
+            _ => Self::populate(),
+        }
+    }
+
+    /// Rescan desktop entries and write them to the cache
+    pub fn update_cache() -> Result<Self> {
+        let apps = Self::populate()?;
+        let path = xdg::BaseDirectories::with_prefix("handlr")?
+            .place_cache_file("system_apps.msgpack")?;
+        apps.write_cache(&path, &locales())?;
+        Ok(apps)
+    }
+
+    /// Number of distinct desktop entries associated with any mime
+    pub fn entry_count(&self) -> usize {
+        use itertools::Itertools;
+
+        self.0.values().flatten().unique().count()
+    }
+
+    fn cache_path() -> Result<Option<PathBuf>> {
+        Ok(xdg::BaseDirectories::with_prefix("handlr")?
+            .find_cache_file("system_apps.msgpack"))
+    }
+
+    fn cache_is_fresh(cache: &Path) -> bool {
+        let modified = |path: &Path| path.metadata().and_then(|m| m.modified());
+
+        let (Ok(cache_modified), Ok(dirs)) =
+            (modified(cache), xdg::BaseDirectories::new())
+        else {
+            return false;
+        };
+
+        // Include the directories themselves so removed entries are noticed
+        std::iter::once(dirs.get_data_home())
+            .chain(dirs.get_data_dirs())
+            .map(|dir| dir.join("applications"))
+            .chain(CONFIG.extra_app_dirs.iter().cloned())
+            .chain(dirs.list_data_files_once("applications"))
+            .chain(files_in(&CONFIG.extra_app_dirs))
+            .filter_map(|path| modified(&path).ok())
+            .all(|entry_modified| entry_modified <= cache_modified)
+    }
+
+    /// Read the cache, or `None` if it was written for other locales
+    fn read_cache(path: &Path, locales: &[String]) -> Result<Option<Self>> {
+        let cache: SystemAppsCache =
+            rmp_serde::from_slice(&std::fs::read(path)?)?;
+        if cache.locales != locales {
+            return Ok(None);
+        }
+
+        Ok(Some(Self(
+            cache
+                .apps
+                .into_iter()
+                .filter_map(|(mime, handlers)| {
+                    Some((Mime::from_str(&mime).ok()?, handlers))
+                })
+                .collect(),
+        )))
+    }
+
+    fn write_cache(&self, path: &Path, locales: &[String]) -> Result<()> {
+        let cache = SystemAppsCache {
+            locales: locales.to_vec(),
+            apps: self
+                .0
+                .iter()
+                .map(|(mime, handlers)| (mime.to_string(), handlers.clone()))
+                .collect(),
+        };
+
+        std::fs::write(path, rmp_serde::to_vec(&cache)?)?;
+        Ok(())
+    }
+}
+
+/// Every file in the given directories, in order
+fn files_in(dirs: &[PathBuf]) -> impl Iterator<Item = PathBuf> + '_ {
+    dirs.iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_round_trip() -> Result<()> {
+        let mut apps = SystemApps::default();
+        apps.0.insert(
+            Mime::from_str("text/plain")?,
+            vec![
+                Handler::assume_valid("helix.desktop".into()),
+                Handler::assume_valid("nvim.desktop".into()),
+            ]
+            .into(),
+        );
+        apps.0.insert(
+            Mime::from_str("video/mp4")?,
+            vec![Handler::assume_valid("mpv.desktop".into())].into(),
+        );
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("system-apps.msgpack");
+        let locales = vec!["de_DE".to_owned(), "de".to_owned()];
+        apps.write_cache(&path, &locales)?;
+
+        assert_eq!(
+            SystemApps::read_cache(&path, &locales)?,
+            Some(apps.clone())
+        );
+        // A cache written for another locale is not used
+        assert_eq!(SystemApps::read_cache(&path, &["fr".to_owned()])?, None);
+        assert_eq!(apps.entry_count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn repeated_mimes() -> Result<()> {
+        let entry = DesktopEntry {
+            file_name: "mpv.desktop".into(),
+            mimes: vec![
+                Mime::from_str("video/mp4")?,
+                Mime::from_str("video/MP4")?,
+                Mime::from_str("video/mp4")?,
+            ],
+            ..Default::default()
+        };
+        let apps = SystemApps::from_entries(std::iter::once((
+            entry.file_name.clone(),
+            entry,
+        )));
+
+        assert_eq!(
+            apps.get_handlers(&Mime::from_str("video/mp4")?),
+            Some(vec![Handler::assume_valid("mpv.desktop".into())].into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn shadowing() -> Result<()> {
+        let dirs = [
+            PathBuf::from("tests/shadowing/user"),
+            PathBuf::from("tests/shadowing/system"),
+        ];
+        let apps = SystemApps::populate_from(&dirs);
+        let shadowed = Handler::assume_valid("handlr-shadowed.desktop".into());
+
+        // Only the user copy's associations are used
+        assert_eq!(
+            apps.get_handler(&Mime::from_str(
+                "application/x-handlr-shadowed-user"
+            )?),
+            Some(shadowed.clone())
+        );
+        assert_eq!(
+            apps.get_handler(&Mime::from_str(
+                "application/x-handlr-shadowed-system"
+            )?),
+            None
+        );
+        // And so is its Exec
+        assert_eq!(shadowed.get_entry_in(&dirs)?.exec, "user-copy %f");
+
+        // A hidden user copy deletes the entry
+        assert_eq!(
+            apps.get_handler(&Mime::from_str("application/x-handlr-deleted")?),
+            None
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn categories() {
+        let dirs = [
+            PathBuf::from("tests/shadowing/user"),
+            PathBuf::from("tests/shadowing/system"),
+        ];
+        let apps = SystemApps::populate_from(&dirs);
+        let in_category = |category: &str| {
+            apps.by_category_in(category, Some(&dirs))
+                .into_iter()
+                .map(|(handler, _)| handler.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // Entries without mimetypes are found too, and case is ignored
+        assert_eq!(
+            in_category("office"),
+            ["handlr-settings.desktop", "handlr-shadowed.desktop"]
+        );
+        // Only the user copy's categories are used
+        assert!(in_category("Graphics").is_empty());
+        assert!(in_category("Deleted").is_empty());
     }
 }
@@ -1,23 +1,126 @@
 use crate::{
-    apps::{RegexApps, RegexHandler, SystemApps},
-    common::Handler,
-    render_table, Error, ErrorKind, GenericHandler, Result, UserPath, CONFIG,
+    apps::{
+        is_wildcard, wildcard_matches, HostApps, RecentSelections, RegexApps,
+        SystemApps, WildcardExpansions,
+    },
+    common::{open_with_chooser, split_exec, ExecOptions, Handler, MimeType},
+    render_table, utils, Config, DesktopEntry, Error, ErrorKind,
+    GenericHandler, NotFoundKind, Result, SelectorCandidate, UserPath, CONFIG,
 };
 use mime::Mime;
 use once_cell::sync::Lazy;
 use pest::Parser;
+use regex::Regex;
 use serde::Serialize;
 use tabled::Tabled;
 
 use std::{
-    collections::{HashMap, VecDeque},
-    io::{IsTerminal, Read},
-    path::PathBuf,
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::Child,
     str::FromStr,
 };
 
 pub static APPS: Lazy<MimeApps> = Lazy::new(|| MimeApps::read().unwrap());
 
+/// Where an association comes from, in order of priority
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HandlerSource {
+    /// `[Default Applications]` in mimeapps.list
+    UserDefault,
+    /// `[Added Associations]` in mimeapps.list
+    UserAdded,
+    /// Installed desktop entries
+    System,
+    /// `fallback_handler` in handlr.toml, used when nothing else is found
+    Fallback,
+}
+
+impl HandlerSource {
+    pub const ALL: [HandlerSource; 3] = [
+        HandlerSource::UserDefault,
+        HandlerSource::UserAdded,
+        HandlerSource::System,
+    ];
+}
+
+impl HandlerSource {
+    /// Name of the section in `handlr list --jsonl` output
+    fn section(&self) -> &'static str {
+        match self {
+            HandlerSource::UserDefault => "default",
+            HandlerSource::UserAdded => "added",
+            HandlerSource::System => "system",
+            HandlerSource::Fallback => "fallback",
+        }
+    }
+
+    /// Short name of where an association comes from in the source column of `handlr list --all`
+    fn label(&self) -> &'static str {
+        match self {
+            HandlerSource::UserDefault => "user",
+            HandlerSource::UserAdded => "added",
+            HandlerSource::System => "system",
+            HandlerSource::Fallback => "fallback",
+        }
+    }
+}
+
+/// What to sort the rows of `handlr list` by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortBy {
+    /// Alphabetically by mimetype
+    #[default]
+    Mime,
+    /// By the first handler, grouping mimetypes opened with the same app
+    Handler,
+}
+
+/// How `handlr list` prints associations
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ListFormat {
+    /// A table for each section
+    #[default]
+    Table,
+    /// A json array for each section
+    Json,
+    /// A json object for each mimetype, one per line
+    Jsonl,
+    /// A line for each mimetype, with its handlers after a tab
+    Tsv,
+}
+
+/// Which rows `handlr list` shows and in what order
+#[derive(Debug, Clone, Default)]
+pub struct ListRows {
+    pub sort_by: SortBy,
+    /// Only show mimetypes where this matches the mimetype or one of its handlers,
+    /// or only one of its handlers when sorting by handler
+    pub filter: Option<Regex>,
+}
+
+impl ListRows {
+    fn keep(&self, entry: &MimeAppsEntry) -> bool {
+        let Some(filter) = &self.filter else {
+            return true;
+        };
+        (self.sort_by == SortBy::Mime && filter.is_match(&entry.mime))
+            || entry.handlers.iter().any(|h| filter.is_match(h))
+    }
+}
+
+impl std::fmt::Display for HandlerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            HandlerSource::UserDefault => "default apps",
+            HandlerSource::UserAdded => "added associations",
+            HandlerSource::System => "system apps",
+            HandlerSource::Fallback => "fallback_handler",
+        })
+    }
+}
+
 #[derive(Debug, Default, Clone, pest_derive::Parser)]
 #[grammar = "common/ini.pest"]
 pub struct MimeApps {
@@ -25,393 +128,4434 @@ pub struct MimeApps {
     default_apps: HashMap<Mime, VecDeque<Handler>>,
     system_apps: SystemApps,
     regex_apps: RegexApps,
+    /// Handlers for URLs by host, consulted before `regex_apps`
+    host_apps: HostApps,
+    expansions: WildcardExpansions,
+    expand_wildcards: bool,
+    /// Most mimes to change without confirmation, see `confirm_changes`
+    expand_confirm_threshold: usize,
+    enable_selector: bool,
+    /// Whether the desktop portal's "Open With" dialog is used instead of the selector,
+    /// from `use_portal_chooser` or `--portal`, see `choose_with_portal`
+    portal: bool,
+    /// Whether to offer recent selections first and record new ones
+    remember_selection: bool,
+    /// Mimes for which files are identified by their contents, see `UserPath::get_mime_with`
+    prefer_content_detection: Vec<String>,
+    /// Sets of mimes that share handlers, see `equivalents`
+    equivalent_mimes: Vec<Vec<String>>,
+    /// Handler to use when no other handler is found for a mime
+    fallback_handler: Option<Handler>,
+    recent: RecentSelections,
+    in_transaction: bool,
+    /// Lines of mimeapps.list that are not associations, kept when saving
+    preserved: PreservedLines,
+    /// mimeapps.list to use instead of the one in $XDG_CONFIG_HOME
+    location: Option<PathBuf>,
+    /// Directories to find desktop entries in instead of $XDG_DATA_DIRS
+    application_dirs: Option<Vec<PathBuf>>,
+    /// Why parts of the config could not be loaded and were left empty instead
+    load_errors: LoadErrors,
+}
+
+/// Why mimeapps.list or the installed desktop entries could not be loaded, shown by `handlr status`
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LoadErrors {
+    pub mimeapps: Option<String>,
+    pub system_apps: Option<String>,
+}
+
+/// Lines of mimeapps.list that handlr does not manage, written back as they were
+#[derive(Debug, Default, Clone)]
+struct PreservedLines {
+    /// Lines before the first section
+    preamble: Vec<String>,
+    /// Comments in `[Added Associations]` and `[Default Applications]`, by section
+    comments: HashMap<String, Vec<String>>,
+    /// Every other section, like `[Removed Associations]`, as its header and lines
+    sections: Vec<(String, Vec<String>)>,
+}
+
+/// How a path would be opened, as found by [`MimeApps::resolve`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The path's mimetype, if it could be determined
+    pub mime: Option<Mime>,
+    /// The handler that would open the path
+    pub handler: GenericHandler,
+    /// Where the handler was found, or `None` for regex handlers
+    pub source: Option<HandlerSource>,
+    /// The program to run
+    pub program: String,
+    /// The arguments to run the program with
+    pub args: Vec<String>,
+    /// Extra environment variables to run the program with
+    pub env: BTreeMap<String, String>,
+    /// Directory to run the program in, if the handler sets one
+    pub working_dir: Option<PathBuf>,
+    /// Whether the program expects to be run in a terminal
+    ///
+    /// Unlike `handlr open`, the command is never wrapped in a terminal emulator.
+    pub terminal: bool,
 }
 
 impl MimeApps {
-    pub fn add_handler(&mut self, mime: Mime, handler: Handler) {
-        self.default_apps
-            .entry(mime)
-            .or_default()
-            .push_back(handler);
+    /// Make several changes at once, saving only once at the end
+    ///
+    /// Methods that normally save on their own do not save while `f` runs.
+    /// If `f` fails, its error is returned and nothing is saved.
+    pub fn transaction<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        // Nested transactions are left for the outermost one to save
+        let nested = std::mem::replace(&mut self.in_transaction, true);
+        let res = f(self);
+        self.in_transaction = nested;
+        res?;
+
+        if nested {
+            Ok(())
+        } else {
+            self.save()
+        }
     }
 
-    pub fn set_handler(&mut self, mime: Mime, handler: Handler) {
-        self.default_apps.insert(mime, vec![handler].into());
+    /// Add `handler` to the end of the default handlers of `mime`
+    ///
+    /// Returns whether anything was added, since handlers that are already
+    /// there are skipped with a warning instead of duplicated.
+    pub fn add_handler(&mut self, mime: Mime, handler: Handler) -> bool {
+        let mut added = false;
+        for expanded in self.expand(mime.clone()) {
+            let handlers = self.default_apps.entry(expanded).or_default();
+            if !handlers.contains(&handler) {
+                handlers.push_back(handler.clone());
+                added = true;
+            }
+        }
+
+        if !added {
+            tracing::warn!(
+                "{} is already a default handler for {}, so it was not added again",
+                handler,
+                mime
+            );
+        }
+        added
     }
 
-    pub fn unset_handler(&mut self, mime: &Mime) -> Result<()> {
-        if let Some(_unset) = self.default_apps.remove(mime) {
-            self.save()?;
+    /// Add `handler` to the added associations of `mime`,
+    /// which "open with" menus offer without it becoming the default
+    ///
+    /// Returns whether anything was added, since handlers that are already
+    /// associated with `mime` are skipped with a warning instead of duplicated.
+    pub fn add_association(&mut self, mime: Mime, handler: Handler) -> bool {
+        if self
+            .default_apps
+            .get(&mime)
+            .is_some_and(|handlers| handlers.contains(&handler))
+        {
+            tracing::warn!(
+                "{} is already a default handler for {}, so it was not added",
+                handler,
+                mime
+            );
+            return false;
         }
 
-        Ok(())
+        let handlers = self.added_associations.entry(mime).or_default();
+        if handlers.contains(&handler) {
+            tracing::warn!("{} is already an added association", handler);
+            return false;
+        }
+
+        handlers.push_back(handler);
+        true
     }
 
-    pub fn remove_handler(
+    pub fn set_handler(&mut self, mime: Mime, handler: Handler) {
+        self.set_expanded(mime, handler, self.expand_wildcards);
+    }
+
+    /// Like `set_handler`, but always setting every known mime a wildcard matches one by one,
+    /// as if `expand_wildcards` was enabled, returning the mimes that were set
+    pub fn set_handler_glob(
         &mut self,
         mime: Mime,
         handler: Handler,
-    ) -> Result<()> {
-        let handler_list = self.default_apps.entry(mime).or_default();
+    ) -> Vec<Mime> {
+        self.set_expanded(mime, handler, true)
+    }
 
-        if let Some(pos) = handler_list.iter().position(|x| *x == handler) {
-            if let Some(_removed) = handler_list.remove(pos) {
-                self.save()?
-            }
+    fn set_expanded(
+        &mut self,
+        mime: Mime,
+        handler: Handler,
+        expand: bool,
+    ) -> Vec<Mime> {
+        let mimes = self.expand_if(mime, expand);
+        for mime in &mimes {
+            self.default_apps
+                .insert(mime.clone(), vec![handler.clone()].into());
         }
+        mimes
+    }
 
-        Ok(())
+    /// Get the other mimes in the `equivalent_mimes` set that `mime` is in, in the order they are listed
+    pub fn equivalents(&self, mime: &Mime) -> Vec<Mime> {
+        self.equivalent_mimes
+            .iter()
+            .find(|set| set.iter().any(|m| m == mime.essence_str()))
+            .into_iter()
+            .flatten()
+            .filter(|m| *m != mime.essence_str())
+            .filter_map(|m| Mime::from_str(m).ok())
+            .collect()
     }
 
-    pub fn get_handler(&self, mime: &Mime) -> Result<Handler> {
-        match self.get_handler_from_user(mime) {
-            Err(e) if matches!(*e.kind, ErrorKind::Cancelled) => Err(e),
-            h => h
-                .or_else(|_| {
-                    let wildcard =
-                        Mime::from_str(&format!("{}/*", mime.type_())).unwrap();
-                    self.get_handler_from_user(&wildcard)
-                })
-                .or_else(|_| self.get_handler_from_added_associations(mime)),
+    /// Give every mime equivalent to `mime` the same default handlers,
+    /// returning the mimes that were changed
+    pub fn sync_equivalents(&mut self, mime: &Mime) -> Vec<Mime> {
+        let Some(handlers) = self.default_apps.get(mime).cloned() else {
+            return Vec::new();
+        };
+
+        let equivalents = self.equivalents(mime);
+        for equivalent in &equivalents {
+            self.default_apps
+                .insert(equivalent.clone(), handlers.clone());
         }
+        equivalents
     }
 
-    fn get_handler_from_user(&self, mime: &Mime) -> Result<Handler> {
-        match self.default_apps.get(mime) {
-            Some(handlers) if CONFIG.enable_selector && handlers.len() > 1 => {
-                let handlers = handlers
+    /// Get each pair of wildcards in default apps that both match some known mime,
+    /// like `video/*` and `*/mp4` for `video/mp4`, making it unclear which should be used
+    ///
+    /// Pairs with the same handlers are left out, since either gives the same result.
+    /// Default applications and added associations are checked separately,
+    /// since a default application always comes before added associations.
+    pub fn overlapping_wildcards(&self) -> Vec<(Mime, Mime)> {
+        use itertools::Itertools;
+
+        let known = self.known_mimes().collect::<Vec<_>>();
+
+        [&self.default_apps, &self.added_associations]
+            .iter()
+            .flat_map(|associations| {
+                associations
                     .iter()
-                    .map(|h| (h, h.get_entry().unwrap().name))
-                    .collect::<Vec<_>>();
+                    .filter(|(mime, _)| is_wildcard(mime))
+                    .sorted_by(|(a, _), (b, _)| a.cmp(b))
+                    .tuple_combinations()
+            })
+            .filter(|((a, a_handlers), (b, b_handlers))| {
+                a_handlers != b_handlers
+                    && known.iter().any(|known| {
+                        wildcard_matches(a.essence_str(), known)
+                            && wildcard_matches(b.essence_str(), known)
+                    })
+            })
+            .map(|((a, _), (b, _))| (a.clone(), b.clone()))
+            .unique()
+            .collect()
+    }
 
-                let handler = {
-                    let name =
-                        CONFIG.select(handlers.iter().map(|h| h.1.clone()))?;
+    /// Every mime handlr knows of, from its database and from system apps
+    fn known_mimes(&self) -> impl Iterator<Item = &str> {
+        use itertools::Itertools;
 
-                    handlers
-                        .into_iter()
-                        .find(|h| h.1 == name)
-                        .unwrap()
-                        .0
-                        .clone()
-                };
+        mime_db::TYPES
+            .iter()
+            .map(|(mime, _, _)| *mime)
+            .chain(self.system_apps.0.keys().map(|mime| mime.essence_str()))
+            .unique()
+    }
 
-                Ok(handler)
-            }
-            Some(handlers) => Ok(handlers.get(0).unwrap().clone()),
-            None => Err(Error::from(ErrorKind::NotFound(mime.to_string()))),
+    /// Get every known mime that the wildcard `mime` matches,
+    /// or `None` if it is not a wildcard that can be expanded
+    ///
+    /// `x-scheme-handler/*` is never expanded, since it is meant as a catch-all
+    /// for protocols that no known mime covers.
+    pub fn wildcard_expansion(&self, mime: &Mime) -> Option<Vec<Mime>> {
+        if !is_wildcard(mime) || mime.type_() == "x-scheme-handler" {
+            return None;
         }
+
+        Some(
+            self.known_mimes()
+                .filter(|known| wildcard_matches(mime.essence_str(), known))
+                .filter_map(|known| Mime::from_str(known).ok())
+                .collect(),
+        )
     }
 
-    fn get_handler_from_added_associations(
-        &self,
-        mime: &Mime,
-    ) -> Result<Handler> {
-        self.added_associations
-            .get(mime)
-            .map_or_else(
-                || self.system_apps.get_handler(mime),
-                |h| h.get(0).cloned(),
-            )
-            .ok_or_else(|| Error::from(ErrorKind::NotFound(mime.to_string())))
+    /// Get the mimes to change when changing associations for `mime`
+    ///
+    /// If `expand_wildcards` is enabled, wildcards are expanded to every
+    /// matching known mime and the expansion is recorded so it can be undone.
+    /// Otherwise, `mime` is changed directly and treated as a manual override.
+    fn expand(&mut self, mime: Mime) -> Vec<Mime> {
+        self.expand_if(mime, self.expand_wildcards)
     }
 
-    fn get_handler_from_regex_handlers(
-        &self,
-        path: &UserPath,
-    ) -> Result<RegexHandler> {
-        self.regex_apps.get_handler(path)
+    /// Like `expand`, but expanding wildcards only if `expand` is set
+    fn expand_if(&mut self, mime: Mime, expand: bool) -> Vec<Mime> {
+        match self.wildcard_expansion(&mime) {
+            Some(mimes) if expand => {
+                self.expansions.record(&mime, &mimes);
+                mimes
+            }
+            _ => {
+                self.expansions.forget(&mime);
+                vec![mime]
+            }
+        }
     }
 
-    pub fn show_handler(&self, mime: &Mime, output_json: bool) -> Result<()> {
-        let handler = self.get_handler(mime)?;
-        let output = if output_json {
-            let entry = handler.get_entry()?;
-            let cmd = entry.get_cmd(vec![])?;
+    pub fn unset_handler(&mut self, mime: &Mime) -> Result<()> {
+        if self.remove_default(mime) {
+            self.save()?;
+        }
 
-            (serde_json::json!( {
-                "handler": handler.to_string(),
-                "name": entry.name.as_str(),
-                "cmd": cmd.0 + " " + &cmd.1.join(" "),
-            }))
-            .to_string()
-        } else {
-            handler.to_string()
-        };
-        println!("{}", output);
         Ok(())
     }
-    pub fn path() -> Result<PathBuf> {
-        let mut config = xdg::BaseDirectories::new()?.get_config_home();
-        config.push("mimeapps.list");
-        Ok(config)
-    }
-    pub fn read() -> Result<Self> {
-        let raw_conf = {
-            let mut buf = String::new();
-            let exists = std::path::Path::new(&Self::path()?).exists();
-            std::fs::OpenOptions::new()
-                .write(!exists)
-                .create(!exists)
-                .read(true)
-                .open(Self::path()?)?
-                .read_to_string(&mut buf)?;
-            buf
-        };
-        let file = Self::parse(Rule::file, &raw_conf)?.next().unwrap();
 
-        let mut current_section_name = "".to_string();
-        let mut conf = Self {
-            added_associations: HashMap::default(),
-            default_apps: HashMap::default(),
-            system_apps: SystemApps::populate()?,
-            regex_apps: RegexApps::populate(),
-        };
+    /// Remove the default handlers for `mime`, along with any associations
+    /// that were expanded from it, returning whether anything was removed
+    fn remove_default(&mut self, mime: &Mime) -> bool {
+        let expanded = self.expansions.take(mime);
+        let mut removed = !expanded.is_empty();
 
-        file.into_inner().for_each(|line| {
-            match line.as_rule() {
-                Rule::section => {
-                    current_section_name = line.into_inner().concat();
-                }
-                Rule::property => {
-                    let mut inner_rules = line.into_inner(); // { name ~ "=" ~ value }
+        for mime in std::iter::once(mime).chain(expanded.iter()) {
+            removed |= self.default_apps.remove(mime).is_some();
+        }
 
-                    let name = inner_rules.next().unwrap().as_str();
-                    let handlers = {
-                        use itertools::Itertools;
+        removed
+    }
 
-                        inner_rules
-                            .next()
-                            .unwrap()
-                            .as_str()
-                            .split(';')
-                            .filter(|s| !s.is_empty())
-                            .unique()
-                            .filter_map(|s| Handler::from_str(s).ok())
-                            .collect::<VecDeque<_>>()
-                    };
+    /// Remove `handler` from the default apps of `mime`, see `remove_default_handler`
+    pub fn remove_handler(
+        &mut self,
+        mime: Mime,
+        handler: Handler,
+        wildcard: bool,
+    ) -> Result<()> {
+        if self.remove_default_handler(&mime, &handler, wildcard) {
+            self.save()?;
+        }
 
-                    if !handlers.is_empty() {
-                        match (
-                            Mime::from_str(name),
-                            current_section_name.as_str(),
-                        ) {
-                            (Ok(mime), "Added Associations") => {
-                                conf.added_associations.insert(mime, handlers)
-                            }
+        Ok(())
+    }
 
-                            (Ok(mime), "Default Applications") => {
-                                conf.default_apps.insert(mime, handlers)
-                            }
-                            _ => None,
-                        };
-                    }
+    /// Remove `handler` from the default apps of `mime`, returning whether it was removed
+    ///
+    /// If `wildcard` is set, it is also removed from the wildcard for the mime's type,
+    /// e.g. `video/*` for `video/mp4`, since the mime would otherwise still be opened with it.
+    /// Otherwise, a handler that is only set for the wildcard is left alone with a warning.
+    fn remove_default_handler(
+        &mut self,
+        mime: &Mime,
+        handler: &Handler,
+        wildcard: bool,
+    ) -> bool {
+        let type_wildcard =
+            Mime::from_str(&format!("{}/*", mime.type_())).unwrap();
+        let targets = std::iter::once(mime).chain(
+            (wildcard && type_wildcard != *mime).then_some(&type_wildcard),
+        );
+
+        let mut removed = false;
+        for target in targets {
+            let Some(handlers) = self.default_apps.get_mut(target) else {
+                continue;
+            };
+            if let Some(pos) = handlers.iter().position(|h| h == handler) {
+                handlers.remove(pos);
+                if handlers.is_empty() {
+                    self.default_apps.remove(target);
                 }
-                _ => {}
+                removed = true;
             }
-        });
+        }
 
-        Ok(conf)
+        if !removed
+            && self
+                .default_apps
+                .get(&type_wildcard)
+                .is_some_and(|handlers| handlers.contains(handler))
+        {
+            tracing::warn!(
+                "{} is a default handler for {} rather than {}, \
+                 use --wildcard to remove it from there",
+                handler,
+                type_wildcard,
+                mime
+            );
+        }
+        removed
     }
-    pub fn save(&self) -> Result<()> {
-        use itertools::Itertools;
-        use std::io::{prelude::*, BufWriter};
 
-        let f = std::fs::OpenOptions::new()
-            .read(true)
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(Self::path()?)?;
-        let mut writer = BufWriter::new(f);
-
-        writer.write_all(b"[Added Associations]\n")?;
-        for (k, v) in self.added_associations.iter().sorted() {
-            writer.write_all(k.essence_str().as_ref())?;
-            writer.write_all(b"=")?;
-            writer.write_all(v.iter().join(";").as_ref())?;
-            writer.write_all(b";\n")?;
-        }
+    /// Remove `handler` from the added associations of `mime`
+    pub fn remove_association(
+        &mut self,
+        mime: &Mime,
+        handler: &Handler,
+    ) -> Result<()> {
+        let Some(handlers) = self.added_associations.get_mut(mime) else {
+            return Ok(());
+        };
 
-        writer.write_all(b"\n[Default Applications]\n")?;
-        for (k, v) in self.default_apps.iter().sorted() {
-            writer.write_all(k.essence_str().as_ref())?;
-            writer.write_all(b"=")?;
-            writer.write_all(v.iter().join(";").as_ref())?;
-            writer.write_all(b";\n")?;
+        if let Some(pos) = handlers.iter().position(|x| x == handler) {
+            handlers.remove(pos);
+            if handlers.is_empty() {
+                self.added_associations.remove(mime);
+            }
+            self.save()?
         }
 
-        writer.flush()?;
         Ok(())
     }
-    pub fn print(&self, detailed: bool, output_json: bool) -> Result<()> {
-        let mimeapps_table = MimeAppsTable::new(&self);
 
-        if detailed {
-            if output_json {
-                println!(
-                    "{}",
-                    serde_json::to_string(&MimeAppsTable::new(&self))?
-                )
-            } else {
-                println!("Default Apps");
-                println!("{}", render_table(&mimeapps_table.default_apps));
-                if !self.added_associations.is_empty() {
-                    println!("Added associations");
-                    println!(
-                        "{}",
-                        render_table(&mimeapps_table.added_associations)
-                    );
+    /// Remove `handler` from the associations of `mime` in both sections,
+    /// returning whether it was listed in either
+    pub(crate) fn remove_from_associations(
+        &mut self,
+        mime: &Mime,
+        handler: &Handler,
+    ) -> bool {
+        let mut removed = false;
+
+        for source in [HandlerSource::UserDefault, HandlerSource::UserAdded] {
+            let associations = self.associations_mut(source);
+            let Some(handlers) = associations.get_mut(mime) else {
+                continue;
+            };
+            if let Some(pos) = handlers.iter().position(|h| h == handler) {
+                handlers.remove(pos);
+                if handlers.is_empty() {
+                    associations.remove(mime);
                 }
-                println!("System Apps");
-                println!("{}", render_table(&mimeapps_table.system_apps))
+                removed = true;
             }
-        } else if output_json {
-            println!("{}", serde_json::to_string(&mimeapps_table.default_apps)?)
-        } else {
-            println!("{}", render_table(&mimeapps_table.default_apps))
         }
 
-        Ok(())
+        removed
     }
-    pub fn list_handlers() -> Result<()> {
-        use std::{io::Write, os::unix::ffi::OsStrExt};
-
-        let stdout = std::io::stdout();
-        let mut stdout = stdout.lock();
-
-        SystemApps::get_entries()?.for_each(|(_, e)| {
-            stdout.write_all(e.file_name.as_bytes()).unwrap();
-            stdout.write_all(b"\t").unwrap();
-            stdout.write_all(e.name.as_bytes()).unwrap();
-            stdout.write_all(b"\n").unwrap();
-        });
 
-        Ok(())
-    }
-    pub fn open_paths(&self, paths: &[UserPath]) -> Result<()> {
-        let mut handlers: HashMap<GenericHandler, Vec<String>> = HashMap::new();
+    /// Remove `handler` from every association in mimeapps.list,
+    /// even if its desktop file is no longer installed
+    ///
+    /// Prints each association that changed, and only saves if `dry_run` is not set.
+    pub fn purge(&mut self, handler: &Handler, dry_run: bool) -> Result<()> {
+        let raw_conf = read_if_exists(&self.location()?)?;
+        let rows = self.purge_associations(&raw_conf, handler);
 
-        for path in paths.iter() {
-            handlers
-                .entry(
-                    if let Ok(handler) =
-                        self.get_handler_from_regex_handlers(path)
-                    {
-                        GenericHandler::RegexHandler(handler)
-                    } else {
-                        GenericHandler::Handler(
-                            self.get_handler(&path.get_mime()?)?,
-                        )
-                    },
-                )
-                .or_default()
-                .push(path.to_string())
+        if rows.is_empty() {
+            println!("{} is not in mimeapps.list", handler);
+            return Ok(());
         }
 
-        for (handler, paths) in handlers.into_iter() {
-            handler.open(paths)?;
+        println!("{}", render_table(&rows));
+        if dry_run {
+            println!("Nothing was changed, since --dry-run was used");
+        } else {
+            self.save()?;
         }
 
         Ok(())
     }
-}
 
-/// Internal helper struct for turning MimeApps into tabular data
-#[derive(PartialEq, Eq, PartialOrd, Ord, Tabled, Serialize)]
-struct MimeAppsEntry {
-    mime: String,
-    #[tabled(display_with("Self::display_handlers", self))]
-    handlers: Vec<String>,
-}
+    /// Remove `handler` from both sections, returning what changed
+    ///
+    /// Occurrences are looked for in the raw mimeapps.list,
+    /// so they are listed in the order they appear there.
+    fn purge_associations(
+        &mut self,
+        raw_conf: &str,
+        handler: &Handler,
+    ) -> Vec<PurgedEntry> {
+        let mut rows = Vec::new();
 
-impl MimeAppsEntry {
-    fn new(mime: &Mime, handlers: &VecDeque<Handler>) -> Self {
-        Self {
-            mime: mime.to_string(),
-            handlers: handlers
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<String>>(),
+        for (source, name, value) in Self::raw_associations(raw_conf) {
+            if !value.split(';').any(|h| h == handler.to_string()) {
+                continue;
+            }
+
+            let associations = self.associations_mut(source);
+            let remaining = match Mime::from_str(name) {
+                Ok(mime) => {
+                    let handlers =
+                        associations.entry(mime.clone()).or_default();
+                    handlers.retain(|h| h != handler);
+                    let remaining = handlers
+                        .iter()
+                        .map(|h| h.to_string())
+                        .collect::<Vec<_>>()
+                        .join(";");
+                    if handlers.is_empty() {
+                        associations.remove(&mime);
+                    }
+                    remaining
+                }
+                Err(_) => String::new(),
+            };
+
+            rows.push(PurgedEntry {
+                mime: name.to_owned(),
+                section: source.to_string(),
+                remaining,
+            });
         }
+
+        rows
     }
 
-    fn display_handlers(&self) -> String {
-        // If output is a terminal, optimize for readability
-        // Otherwise, if piped, optimize for parseability
-        let separator = if std::io::stdout().is_terminal() {
-            ",\n"
-        } else {
-            ", "
-        };
+    /// Replace `old` with `new` in every association in mimeapps.list,
+    /// even if the desktop file of `old` is no longer installed
+    ///
+    /// Returns how many associations were changed, without saving them.
+    pub fn rename_handler(
+        &mut self,
+        old: &Handler,
+        new: &Handler,
+    ) -> Result<usize> {
+        let raw_conf = read_if_exists(&self.location()?)?;
+        Ok(self.rename_associations(&raw_conf, old, new))
+    }
 
-        self.handlers.join(separator)
+    /// Replace `old` with `new` in both sections, returning how many associations changed
+    ///
+    /// Like `purge_associations`, occurrences are looked for in the raw mimeapps.list.
+    /// `new` takes the place of `old`, unless it is already listed.
+    fn rename_associations(
+        &mut self,
+        raw_conf: &str,
+        old: &Handler,
+        new: &Handler,
+    ) -> usize {
+        use itertools::Itertools;
+
+        let mut renamed = 0;
+
+        for (source, name, value) in Self::raw_associations(raw_conf) {
+            let listed = value.split(';').collect::<Vec<_>>();
+            let Ok(mime) = Mime::from_str(name) else {
+                continue;
+            };
+            if !listed.contains(&old.to_string().as_str()) {
+                continue;
+            }
+
+            let handlers =
+                self.associations_mut(source).entry(mime).or_default();
+            *handlers = listed
+                .into_iter()
+                .filter_map(|h| {
+                    if h == old.to_string() {
+                        Some(new.clone())
+                    } else {
+                        handlers
+                            .iter()
+                            .find(|kept| kept.to_string() == h)
+                            .cloned()
+                    }
+                })
+                .unique()
+                .collect();
+            renamed += 1;
+        }
+
+        renamed
     }
-}
 
-/// Internal helper struct for turning MimeApps into tabular data
-#[derive(Serialize)]
-struct MimeAppsTable {
-    added_associations: Vec<MimeAppsEntry>,
-    default_apps: Vec<MimeAppsEntry>,
-    system_apps: Vec<MimeAppsEntry>,
-}
+    /// Each association in the raw mimeapps.list, as its section, mime and handlers
+    ///
+    /// Unlike when reading it, associations with invalid mimetypes are included.
+    pub(crate) fn raw_associations(
+        raw_conf: &str,
+    ) -> Vec<(HandlerSource, &str, &str)> {
+        let mut associations = Vec::new();
+        let mut source = None;
+
+        for raw_line in raw_conf.lines() {
+            // { SOI ~ (section | property | comment)? ~ EOI }
+            let Some(line) = Self::parse(Rule::line, raw_line)
+                .ok()
+                .and_then(|mut line| line.next()?.into_inner().next())
+            else {
+                continue;
+            };
+
+            match line.as_rule() {
+                Rule::section => {
+                    source = match line.into_inner().concat().as_str() {
+                        "Added Associations" => Some(HandlerSource::UserAdded),
+                        "Default Applications" => {
+                            Some(HandlerSource::UserDefault)
+                        }
+                        _ => None,
+                    };
+                }
+                Rule::property => {
+                    // { name ~ "=" ~ value }
+                    let mut inner_rules = line.into_inner();
+                    let name = inner_rules.next().unwrap().as_str();
+                    let value = inner_rules.next().unwrap().as_str();
+
+                    if let Some(source) = source {
+                        associations.push((source, name, value));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        associations
+    }
+
+    /// The associations from the given section of mimeapps.list
+    fn associations_mut(
+        &mut self,
+        source: HandlerSource,
+    ) -> &mut HashMap<Mime, VecDeque<Handler>> {
+        match source {
+            HandlerSource::UserAdded => &mut self.added_associations,
+            _ => &mut self.default_apps,
+        }
+    }
+
+    pub fn get_handler(&self, mime: &Mime) -> Result<Handler> {
+        Ok(self.lookup(mime, self.enable_selector)?.0)
+    }
+
+    /// Get the first handler for `mime` that is actually installed,
+    /// trying every handler in the order `lookup` tries the first of each
+    ///
+    /// Handlers are skipped if their desktop file cannot be loaded
+    /// or the program it runs is not installed. The selector is never used.
+    pub fn get_installed_handler(&self, mime: &Mime) -> Result<Handler> {
+        let probes = self.probes(mime);
+        let handlers = HandlerSource::ALL
+            .iter()
+            .flat_map(|source| probes.iter().map(move |probe| (*source, probe)))
+            .flat_map(|(source, probe)| match source {
+                HandlerSource::UserDefault => {
+                    self.default_apps.get(probe).cloned().unwrap_or_default()
+                }
+                HandlerSource::UserAdded => self
+                    .added_associations
+                    .get(probe)
+                    .cloned()
+                    .unwrap_or_default(),
+                _ => self.system_apps.get_handlers(probe).unwrap_or_default(),
+            })
+            .chain(self.fallback_handler.clone());
+
+        for handler in handlers {
+            if self.is_installed(&handler) {
+                return Ok(handler);
+            }
+            tracing::debug!(
+                "skipping {} for {}, since it is not installed",
+                handler,
+                mime
+            );
+        }
+
+        Err(Error::from(NotFoundKind::Mime(mime.clone())))
+    }
+
+    /// Whether `handler`'s desktop file can be loaded and the program it runs is installed
+    fn is_installed(&self, handler: &Handler) -> bool {
+        self.get_entry(handler).is_ok_and(|entry| {
+            split_exec(&entry.exec)
+                .and_then(|exec| exec.into_iter().next())
+                .is_some_and(|program| utils::is_installed(&program))
+        })
+    }
+
+    /// Get every handler for `mime` in order of priority, along with where each was found
+    ///
+    /// Like in `get_handler`, each section of mimeapps.list gives the handlers for `mime`
+    /// before those for the wildcard for its type, e.g. `video/*` for `video/mp4`.
+    /// Handlers are only listed the first time they appear,
+    /// and those from mimeapps.list are left out if their entries cannot be loaded.
+    pub fn get_all_handlers(
+        &self,
+        mime: &Mime,
+    ) -> Vec<(Handler, HandlerSource)> {
+        use itertools::Itertools;
+
+        let wildcard = Mime::from_str(&format!("{}/*", mime.type_())).unwrap();
+        let from_user = |associations: &HashMap<Mime, VecDeque<Handler>>,
+                         source| {
+            std::iter::once(mime)
+                .chain((wildcard != *mime).then_some(&wildcard))
+                .filter_map(|mime| associations.get(mime))
+                .flatten()
+                .filter(|handler| self.get_entry(handler).is_ok())
+                .cloned()
+                .map(move |handler| (handler, source))
+                .collect::<Vec<_>>()
+        };
+
+        from_user(&self.default_apps, HandlerSource::UserDefault)
+            .into_iter()
+            .chain(from_user(
+                &self.added_associations,
+                HandlerSource::UserAdded,
+            ))
+            .chain(
+                self.system_apps
+                    .get_handlers(mime)
+                    .into_iter()
+                    .flatten()
+                    .map(|handler| (handler, HandlerSource::System)),
+            )
+            .unique_by(|(handler, _)| handler.clone())
+            .collect()
+    }
+
+    /// The mimes `lookup` tries at each source, in order: the exact mime,
+    /// then the mime it is an alias of, then its equivalents, before the wildcard
+    fn probes(&self, mime: &Mime) -> Vec<Mime> {
+        let wildcard = Mime::from_str(&format!("{}/*", mime.type_())).unwrap();
+        let canonical = MimeType(mime.clone()).canonical();
+
+        std::iter::once(mime.clone())
+            .chain((canonical != *mime).then_some(canonical))
+            .chain(self.equivalents(mime))
+            .chain((wildcard != *mime).then_some(wildcard))
+            .collect()
+    }
+
+    /// Find the handler for `mime` along with where it was found,
+    /// prompting with the selector if `select` is set
+    fn lookup(
+        &self,
+        mime: &Mime,
+        select: bool,
+    ) -> Result<(Handler, HandlerSource)> {
+        self.lookup_traced(mime, select, None)
+    }
+
+    /// Like `lookup`, recording each source and mime it checks in `trace` if given
+    fn lookup_traced(
+        &self,
+        mime: &Mime,
+        select: bool,
+        mut trace: Option<&mut Vec<LookupStep>>,
+    ) -> Result<(Handler, HandlerSource)> {
+        let mut record = |source, probe: &Mime, handler: Option<&Handler>| {
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.push(LookupStep::new(source, probe, handler));
+            }
+        };
+        let equivalents = self.equivalents(mime);
+        let mime_probes = self.probes(mime);
+        let probes = HandlerSource::ALL.iter().flat_map(|source| {
+            mime_probes.iter().map(move |probe| (*source, probe))
+        });
+
+        for (source, probe) in probes {
+            match self.get_handler_from_source(source, probe, select) {
+                Ok(handler) => {
+                    record(source, probe, Some(&handler));
+                    if equivalents.contains(probe) {
+                        tracing::info!(
+                            "using {} for {}, since it is equivalent to {}",
+                            handler,
+                            mime,
+                            probe
+                        );
+                    }
+                    tracing::debug!(
+                        "found {} for {} in {}",
+                        handler,
+                        probe,
+                        source
+                    );
+                    return Ok((handler, source));
+                }
+                Err(e) if matches!(*e.kind, ErrorKind::Cancelled) => {
+                    return Err(e)
+                }
+                Err(_) => {
+                    record(source, probe, None);
+                    tracing::debug!("no handler for {} in {}", probe, source)
+                }
+            }
+        }
+
+        // Tried once for the mime itself, after everything else
+        let fallback =
+            self.get_handler_from_source(HandlerSource::Fallback, mime, select);
+        record(HandlerSource::Fallback, mime, fallback.as_ref().ok());
+        if let Ok(fallback) = fallback {
+            tracing::warn!(
+                "no handler found for {}, using fallback_handler {}",
+                mime,
+                fallback
+            );
+            return Ok((fallback, HandlerSource::Fallback));
+        }
+
+        Err(Error::from(NotFoundKind::Mime(mime.clone())))
+    }
+
+    fn get_handler_from_source(
+        &self,
+        source: HandlerSource,
+        mime: &Mime,
+        select: bool,
+    ) -> Result<Handler> {
+        match source {
+            HandlerSource::UserDefault => {
+                self.get_handler_from_user(mime, select)
+            }
+            HandlerSource::UserAdded => self
+                .added_associations
+                .get(mime)
+                .into_iter()
+                .flatten()
+                .find(|handler| self.get_entry(handler).is_ok())
+                .cloned()
+                .ok_or_else(|| Error::from(NotFoundKind::Mime(mime.clone()))),
+            HandlerSource::System => self
+                .system_apps
+                .get_handler(mime)
+                .ok_or_else(|| Error::from(NotFoundKind::Mime(mime.clone()))),
+            HandlerSource::Fallback => self
+                .fallback_handler
+                .clone()
+                .ok_or_else(|| Error::from(NotFoundKind::Mime(mime.clone()))),
+        }
+    }
+
+    fn get_handler_from_user(
+        &self,
+        mime: &Mime,
+        select: bool,
+    ) -> Result<Handler> {
+        // Handlers whose entries cannot be loaded are skipped,
+        // so one uninstalled handler does not hide the rest
+        let handlers = self
+            .default_apps
+            .get(mime)
+            .into_iter()
+            .flatten()
+            .filter_map(|handler| match self.get_entry(handler) {
+                Ok(entry) => Some((handler, entry)),
+                Err(e) => {
+                    tracing::warn!("skipping {} for {}: {}", handler, mime, e);
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let (first, _) = handlers
+            .first()
+            .ok_or_else(|| Error::from(NotFoundKind::Mime(mime.clone())))?;
+        if !select {
+            return Ok((*first).clone());
+        }
+
+        // Entries that ask to be left out of menus are not offered
+        let mut shown = handlers
+            .iter()
+            .filter(|(_, entry)| entry.is_shown())
+            .collect::<Vec<_>>();
+
+        // The last selection is offered first, as long as it is still associated
+        if let Some(recent) = self.recent.get(mime) {
+            if let Some(pos) = shown.iter().position(|(h, _)| *h == &recent) {
+                let recent = shown.remove(pos);
+                shown.insert(0, recent);
+            }
+        }
+
+        match shown.as_slice() {
+            [] => Ok((*first).clone()),
+            [(handler, _)] => Ok((*handler).clone()),
+            _ => {
+                let candidates = shown
+                    .iter()
+                    .map(|(handler, entry)| SelectorCandidate {
+                        name: entry.display_name(),
+                        handler: handler.to_string(),
+                        exec: entry.exec.clone(),
+                        mime: mime.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+
+                let selected = shown[CONFIG.select_candidate(&candidates)?].0;
+                if self.remember_selection {
+                    if let Err(e) = RecentSelections::record(mime, selected) {
+                        tracing::warn!("could not record selection: {}", e);
+                    }
+                }
+                Ok(selected.clone())
+            }
+        }
+    }
+
+    /// Get the handler for a path from `[hosts]` or else the regex handlers in handlr.toml
+    ///
+    /// Regex handlers and commands from `[hosts]` are skipped when using an action,
+    /// since they do not have any actions to use.
+    fn get_handler_from_config(
+        &self,
+        path: &UserPath,
+        action: Option<&str>,
+    ) -> Option<GenericHandler> {
+        let host_handler = self.host_apps.get_handler(path).filter(|handler| {
+            action.is_none() || matches!(handler, GenericHandler::Handler(_))
+        });
+        if host_handler.is_some() || action.is_some() {
+            return host_handler;
+        }
+
+        self.regex_apps
+            .get_handler(path)
+            .ok()
+            .map(GenericHandler::RegexHandler)
+    }
+
+    /// Get a handler's desktop entry from the application directories in use
+    pub(crate) fn get_entry(&self, handler: &Handler) -> Result<DesktopEntry> {
+        match &self.application_dirs {
+            Some(dirs) => handler.get_entry_in(dirs),
+            None => handler.get_entry(),
+        }
+    }
+
+    /// Get the handler for a path, as `handlr open` would
+    ///
+    /// Handlers from handlr.toml come first, see `get_handler_from_config`.
+    fn get_handler_for_path(
+        &self,
+        path: &UserPath,
+        action: Option<&str>,
+        select: bool,
+    ) -> Result<(GenericHandler, Option<HandlerSource>)> {
+        if let Some(handler) = self.get_handler_from_config(path, action) {
+            return Ok((handler, None));
+        }
+
+        let (handler, source) = self.lookup(&self.mime_of(path)?, select)?;
+        Ok((GenericHandler::Handler(handler), Some(source)))
+    }
+
+    /// Get every handler that could open `path`, in the order `handlr open` would try them
+    ///
+    /// The handler from `[hosts]` and matching regex handlers come first,
+    /// followed by handlers for the path's mime
+    /// from default apps, added associations and system apps,
+    /// then those for the mime it is an alias of and for its wildcard.
+    /// Each handler is only listed once, and handlers that are broken
+    /// or hidden from menus are left out.
+    pub fn pick_candidates(
+        &self,
+        path: &UserPath,
+    ) -> Result<Vec<GenericHandler>> {
+        use itertools::Itertools;
+
+        let regex_handlers =
+            self.host_apps.get_handler(path).into_iter().chain(
+                self.regex_apps
+                    .get_handlers(path)
+                    .into_iter()
+                    .map(GenericHandler::RegexHandler),
+            );
+
+        let mime_handlers = match self.mime_of(path) {
+            Ok(mime) => {
+                // Which includes the wildcard for each
+                let canonical = MimeType(mime.clone()).canonical();
+                [mime, canonical]
+                    .iter()
+                    .unique()
+                    .flat_map(|mime| self.get_all_handlers(mime))
+                    .map(|(handler, _)| handler)
+                    .unique()
+                    .filter(|handler| {
+                        self.get_entry(handler).is_ok_and(|e| e.is_shown())
+                    })
+                    .map(GenericHandler::Handler)
+                    .collect::<Vec<_>>()
+            }
+            Err(_) => Vec::new(),
+        };
+
+        let candidates =
+            regex_handlers.chain(mime_handlers).collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return Err(Error::from(NotFoundKind::Path(path.to_string())));
+        }
+
+        Ok(candidates)
+    }
+
+    /// Prompt with the selector to pick any handler that could open `path`,
+    /// using `selector` instead of the configured selector command if given
+    ///
+    /// Without `selector`, the desktop portal's "Open With" dialog is used instead if enabled,
+    /// which opens `path` itself. The selector is used if the portal cannot be, with a warning.
+    pub fn pick(
+        &self,
+        path: &UserPath,
+        selector: Option<&str>,
+    ) -> Result<Picked> {
+        let mime = self
+            .mime_of(path)
+            .map(|mime| mime.to_string())
+            .unwrap_or_default();
+
+        if self.portal && selector.is_none() {
+            match open_with_chooser(path, &mime) {
+                Ok(choice) => return Ok(Picked::Portal(choice)),
+                Err(e) if matches!(*e.kind, ErrorKind::Portal(_)) => {
+                    tracing::warn!("{}, using the selector instead", e)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let mut handlers = self.pick_candidates(path)?;
+        let candidates = handlers
+            .iter()
+            .map(|handler| -> Result<SelectorCandidate> {
+                let entry = self.get_generic_entry(handler, None)?;
+                Ok(match handler {
+                    GenericHandler::Handler(handler) => SelectorCandidate {
+                        name: entry.display_name(),
+                        handler: handler.to_string(),
+                        exec: entry.exec,
+                        mime: mime.clone(),
+                    },
+                    GenericHandler::RegexHandler(handler) => {
+                        SelectorCandidate {
+                            name: handler.display_name(),
+                            handler: handler.exec().to_owned(),
+                            exec: handler.exec().to_owned(),
+                            mime: mime.clone(),
+                        }
+                    }
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let selected = CONFIG.select_candidate_with(
+            selector.unwrap_or(&CONFIG.selector),
+            &candidates,
+        )?;
+        Ok(Picked::Handler(handlers.swap_remove(selected)))
+    }
+
+    /// Get the mime to open `path` as
+    ///
+    /// Symlinks are followed first, so a link without the right extension
+    /// is opened like the file it points to. Files that do not exist, i.e. with `open --force`,
+    /// are identified by their own name.
+    fn mime_of(&self, path: &UserPath) -> Result<Mime> {
+        let resolved = path.canonicalize();
+        resolved
+            .as_ref()
+            .unwrap_or(path)
+            .get_mime_with(&self.prefer_content_detection)
+    }
+
+    /// Get the entry a handler runs, using the given action's Exec if there is one
+    fn get_generic_entry(
+        &self,
+        handler: &GenericHandler,
+        action: Option<&str>,
+    ) -> Result<DesktopEntry> {
+        let entry = match handler {
+            GenericHandler::Handler(handler) => self.get_entry(handler)?,
+            GenericHandler::RegexHandler(handler) => handler.get_entry(),
+        };
+
+        match action {
+            Some(action) => entry.with_action(action),
+            None => Ok(entry),
+        }
+    }
+
+    /// Work out how `path` would be opened without opening it
+    ///
+    /// This gives the same handler as `handlr open`,
+    /// except that the first handler is always used instead of prompting with the selector.
+    pub fn resolve(&self, path: &UserPath) -> Result<Resolution> {
+        let (handler, source) = self.get_handler_for_path(path, None, false)?;
+        let entry = self.get_generic_entry(&handler, None)?;
+
+        let mut command = entry.expand_exec(vec![path.to_string()]);
+
+        Ok(Resolution {
+            mime: self.mime_of(path).ok(),
+            handler,
+            source,
+            program: command.remove(0),
+            args: command,
+            env: entry.effective_env(&ExecOptions::default()),
+            working_dir: entry.working_dir,
+            terminal: entry.terminal,
+        })
+    }
+
+    /// Print each source and mime checked to find the handler for `mime`, as `handlr get` would,
+    /// failing afterwards if no handler was found
+    pub fn show_lookup(&self, mime: &Mime, output_json: bool) -> Result<()> {
+        let mut trace = Vec::new();
+        let res =
+            self.lookup_traced(mime, self.enable_selector, Some(&mut trace));
+        if output_json {
+            println!("{}", serde_json::to_string(&trace)?);
+        } else {
+            println!("{}", render_table(&trace));
+        }
+        res.map(|_| ())
+    }
+
+    pub fn show_handler(
+        &self,
+        mime: &Mime,
+        output_json: bool,
+        actions: bool,
+        installed_only: bool,
+        quiet: bool,
+    ) -> Result<()> {
+        let handler = if installed_only {
+            self.get_installed_handler(mime)?
+        } else {
+            self.get_handler(mime)?
+        };
+        let output = if actions {
+            let actions = handler.get_entry()?.actions;
+            if output_json {
+                serde_json::to_string(&actions)?
+            } else {
+                render_table(&actions)
+            }
+        } else if output_json {
+            let entry = handler.get_entry()?;
+            let options = ExecOptions {
+                quiet,
+                ..Default::default()
+            };
+            let cmd = entry.get_cmd(vec![], &options)?;
+
+            let mut output = serde_json::json!( {
+                "handler": handler.to_string(),
+                "name": entry.name.as_str(),
+                "cmd": cmd.0 + " " + &cmd.1.join(" "),
+                "env": entry.effective_env(&options),
+            });
+            if let Some(generic_name) = &entry.generic_name {
+                output["generic_name"] = generic_name.as_str().into();
+            }
+            if let Some(icon) = entry.icon() {
+                output["icon"] = icon.into();
+            }
+            output.to_string()
+        } else {
+            handler.to_string()
+        };
+        println!("{}", output);
+        Ok(())
+    }
+    /// Everything that decides how `mime` is opened, for `handlr info --mime`
+    ///
+    /// Associations are listed in the order `lookup` tries them, followed by the regex handlers
+    /// that match a file with the mime's usual extension and `fallback_handler`.
+    fn mime_info(&self, mime: &Mime) -> MimeInfo {
+        let probes = self.probes(mime);
+        let mut entries = HandlerSource::ALL
+            .iter()
+            .flat_map(|source| probes.iter().map(move |probe| (*source, probe)))
+            .filter_map(|(source, probe)| {
+                let handlers = match source {
+                    HandlerSource::UserDefault => {
+                        self.default_apps.get(probe).cloned()
+                    }
+                    HandlerSource::UserAdded => {
+                        self.added_associations.get(probe).cloned()
+                    }
+                    _ => self.system_apps.get_handlers(probe),
+                }
+                .filter(|handlers| !handlers.is_empty())?;
+
+                let matched = match self.expansions.origin(probe) {
+                    Some(pattern) if source == HandlerSource::UserDefault => {
+                        format!("{} (from {})", probe, pattern)
+                    }
+                    _ => probe.to_string(),
+                };
+                Some(MimeInfoEntry {
+                    source: source.to_string(),
+                    matched,
+                    handlers: handlers.iter().map(|h| h.to_string()).collect(),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(ext) = mime_db::extension(mime.essence_str()) {
+            let example = format!("file.{}", ext);
+            let handlers = self
+                .regex_apps
+                .get_handlers(&UserPath::file(PathBuf::from(&example)))
+                .iter()
+                .map(|handler| handler.display_name())
+                .collect::<Vec<_>>();
+            if !handlers.is_empty() {
+                entries.push(MimeInfoEntry {
+                    source: "regex handlers".to_owned(),
+                    matched: example,
+                    handlers,
+                });
+            }
+        }
+
+        if let Some(fallback) = &self.fallback_handler {
+            entries.push(MimeInfoEntry {
+                source: HandlerSource::Fallback.to_string(),
+                matched: mime.to_string(),
+                handlers: vec![fallback.to_string()],
+            });
+        }
+
+        let found = self.lookup(mime, false).ok();
+        MimeInfo {
+            mime: mime.to_string(),
+            handler: found.as_ref().map(|(handler, _)| handler.to_string()),
+            source: found.map(|(_, source)| source),
+            entries,
+        }
+    }
+    /// Show every handler that could open `mime` and where it comes from
+    pub fn show_mime_info(&self, mime: &Mime, output_json: bool) -> Result<()> {
+        let info = self.mime_info(mime);
+
+        if output_json {
+            println!("{}", serde_json::to_string(&info)?);
+            return Ok(());
+        }
+
+        match (&info.handler, &info.source) {
+            (Some(handler), Some(source)) => {
+                println!("{} is opened with {} from {}", mime, handler, source)
+            }
+            _ => println!("No handler found for {}", mime),
+        }
+        if !info.entries.is_empty() {
+            println!("{}", render_table(&info.entries));
+        }
+
+        Ok(())
+    }
+    /// The mimeapps.list in use
+    pub(crate) fn location(&self) -> Result<PathBuf> {
+        match &self.location {
+            Some(location) => Ok(location.clone()),
+            None => Self::path(),
+        }
+    }
+    /// Also use the desktop portal's "Open With" dialog instead of the selector,
+    /// as `use_portal_chooser` in handlr.toml does
+    pub fn use_portal(&mut self, portal: bool) {
+        self.portal |= portal;
+    }
+    /// Why parts of the config could not be loaded, if any
+    pub(crate) fn load_errors(&self) -> &LoadErrors {
+        &self.load_errors
+    }
+    /// Number of mimes with default apps and with added associations
+    pub(crate) fn association_counts(&self) -> (usize, usize) {
+        (self.default_apps.len(), self.added_associations.len())
+    }
+    pub(crate) fn system_apps(&self) -> &SystemApps {
+        &self.system_apps
+    }
+    /// Directories desktop entries are looked for in, earlier ones taking priority
+    pub(crate) fn application_dirs(&self) -> Result<Vec<PathBuf>> {
+        match &self.application_dirs {
+            Some(dirs) => Ok(dirs.clone()),
+            None => {
+                use itertools::Itertools;
+
+                let dirs = xdg::BaseDirectories::new()?;
+                Ok(CONFIG
+                    .extra_app_dirs
+                    .iter()
+                    .cloned()
+                    .chain(
+                        std::iter::once(dirs.get_data_home())
+                            .chain(dirs.get_data_dirs())
+                            .map(|dir| dir.join("applications")),
+                    )
+                    .unique()
+                    .collect())
+            }
+        }
+    }
+    /// Every handler in default apps and added associations
+    pub(crate) fn associated_handlers(&self) -> impl Iterator<Item = Handler> {
+        use itertools::Itertools;
+
+        self.default_apps
+            .values()
+            .chain(self.added_associations.values())
+            .flatten()
+            .unique()
+            .cloned()
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+    pub fn path() -> Result<PathBuf> {
+        let mut config = xdg::BaseDirectories::new()?.get_config_home();
+        config.push("mimeapps.list");
+        Ok(config)
+    }
+    /// Read the user's mimeapps.list along with handlr.toml and the installed desktop entries
+    ///
+    /// If mimeapps.list or the desktop entries cannot be loaded, a warning is shown
+    /// and only that part is left empty, see `handlr status`.
+    pub fn read() -> Result<Self> {
+        let path = Self::path()?;
+        let (raw_conf, mimeapps_error) = read_or_warn(&path);
+        let (system_apps, system_apps_error) = match SystemApps::load() {
+            Ok(system_apps) => (system_apps, None),
+            Err(e) => {
+                tracing::warn!(
+                    "could not load installed desktop entries, so none are used: {}",
+                    e
+                );
+                (SystemApps::default(), Some(e.to_string()))
+            }
+        };
+
+        let mut conf = Self {
+            system_apps,
+            load_errors: LoadErrors {
+                mimeapps: mimeapps_error,
+                system_apps: system_apps_error,
+            },
+            regex_apps: RegexApps::populate(),
+            host_apps: HostApps::populate(),
+            expansions: WildcardExpansions::load(),
+            expand_wildcards: CONFIG.expand_wildcards,
+            expand_confirm_threshold: CONFIG.expand_confirm_threshold,
+            enable_selector: CONFIG.enable_selector,
+            portal: CONFIG.use_portal_chooser,
+            remember_selection: CONFIG.remember_selection,
+            prefer_content_detection: CONFIG.prefer_content_detection.clone(),
+            equivalent_mimes: CONFIG.equivalent_mimes.clone(),
+            fallback_handler: CONFIG
+                .fallback_handler
+                .as_ref()
+                .map(|handler| Handler::assume_valid(handler.into())),
+            recent: if CONFIG.remember_selection {
+                RecentSelections::load()
+            } else {
+                RecentSelections::default()
+            },
+            ..Default::default()
+        };
+        conf.read_associations(&raw_conf, &path);
+
+        Ok(conf)
+    }
+    /// Read associations without looking anything up in XDG directories
+    ///
+    /// `mimeapps` is used in place of ~/.config/mimeapps.list, including when saving,
+    /// and is treated as empty if it does not exist.
+    /// Desktop entries are only looked for in `application_dirs`, earlier ones taking priority.
+    /// Regex handlers and other settings are taken from `config` instead of handlr.toml.
+    pub fn from_paths(
+        mimeapps: &Path,
+        application_dirs: &[PathBuf],
+        config: &Config,
+    ) -> Result<Self> {
+        let (raw_conf, mimeapps_error) = read_or_warn(mimeapps);
+
+        let mut conf = Self {
+            load_errors: LoadErrors {
+                mimeapps: mimeapps_error,
+                system_apps: None,
+            },
+            system_apps: SystemApps::populate_from(application_dirs),
+            regex_apps: RegexApps::from_config(config),
+            host_apps: HostApps::from_config(config),
+            expand_wildcards: config.expand_wildcards,
+            expand_confirm_threshold: config.expand_confirm_threshold,
+            enable_selector: config.enable_selector,
+            prefer_content_detection: config.prefer_content_detection.clone(),
+            equivalent_mimes: config.equivalent_mimes.clone(),
+            fallback_handler: config
+                .fallback_handler
+                .as_ref()
+                .map(|handler| Handler::assume_valid(handler.into())),
+            location: Some(mimeapps.to_owned()),
+            application_dirs: Some(application_dirs.to_vec()),
+            ..Default::default()
+        };
+        conf.read_associations(&raw_conf, mimeapps);
+
+        Ok(conf)
+    }
+    /// Parse the contents of a mimeapps.list read from `source`
+    ///
+    /// Malformed lines are skipped with a warning instead of failing the whole file.
+    /// Handlers that are not installed are kept, so saving does not lose them,
+    /// and are skipped when looking up a handler instead.
+    fn read_associations(&mut self, raw_conf: &str, source: &Path) {
+        let mut current_section_name = "".to_string();
+
+        for (i, raw_line) in raw_conf.lines().enumerate() {
+            // { SOI ~ (section | property | comment)? ~ EOI }
+            let parsed = Self::parse(Rule::line, raw_line)
+                .map(|mut line| line.next().unwrap().into_inner().next());
+
+            // Anything outside of the sections handlr manages is kept as it is
+            let is_section = matches!(
+                &parsed,
+                Ok(Some(line)) if line.as_rule() == Rule::section
+            );
+            if !is_section && !raw_line.trim().is_empty() {
+                let preserved = &mut self.preserved;
+                match current_section_name.as_str() {
+                    "" => {
+                        preserved.preamble.push(raw_line.to_owned());
+                        continue;
+                    }
+                    "Added Associations" | "Default Applications" => {
+                        if raw_line.trim_start().starts_with('#') {
+                            preserved
+                                .comments
+                                .entry(current_section_name.clone())
+                                .or_default()
+                                .push(raw_line.to_owned());
+                            continue;
+                        }
+                    }
+                    _ => {
+                        if let Some((_, lines)) = preserved.sections.last_mut()
+                        {
+                            lines.push(raw_line.to_owned());
+                        }
+                        continue;
+                    }
+                }
+            }
+
+            let line = match parsed {
+                Ok(line) => line,
+                Err(_) => {
+                    tracing::warn!(
+                        "{}:{}: skipping malformed line '{}'",
+                        source.display(),
+                        i + 1,
+                        raw_line
+                    );
+                    continue;
+                }
+            };
+            let Some(line) = line else { continue };
+
+            match line.as_rule() {
+                Rule::section => {
+                    current_section_name = line.into_inner().concat();
+                    if !matches!(
+                        current_section_name.as_str(),
+                        "Added Associations" | "Default Applications"
+                    ) {
+                        self.preserved
+                            .sections
+                            .push((raw_line.to_owned(), Vec::new()));
+                    }
+                }
+                Rule::property => {
+                    let mut inner_rules = line.into_inner(); // { name ~ "=" ~ value }
+
+                    let name = inner_rules.next().unwrap().as_str();
+                    let handlers = {
+                        use itertools::Itertools;
+
+                        inner_rules
+                            .next()
+                            .unwrap()
+                            .as_str()
+                            .split(';')
+                            .filter(|s| !s.is_empty())
+                            .unique()
+                            .map(|s| Handler::assume_valid(s.into()))
+                            .collect::<VecDeque<_>>()
+                    };
+
+                    if !handlers.is_empty() {
+                        match (
+                            Mime::from_str(name),
+                            current_section_name.as_str(),
+                        ) {
+                            (Ok(mime), "Added Associations") => {
+                                self.added_associations.insert(mime, handlers)
+                            }
+
+                            (Ok(mime), "Default Applications") => {
+                                self.default_apps.insert(mime, handlers)
+                            }
+                            _ => None,
+                        };
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    /// Lock mimeapps.list against changes by other handlr processes until the lock is dropped
+    ///
+    /// Taking it before reading makes changes like `handlr set` safe to run at the same time,
+    /// since otherwise one process could save over another's change.
+    /// The lock is on a separate file, since saving replaces mimeapps.list itself.
+    pub fn lock() -> Result<std::fs::File> {
+        let path = Self::path()?;
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(".lock");
+        utils::lock_file(&path.with_file_name(name))
+    }
+    /// Write mimeapps.list, replacing it all at once so it is never left half-written
+    pub fn save(&self) -> Result<()> {
+        if self.in_transaction {
+            return Ok(());
+        }
+        // Otherwise everything in it would be lost
+        if let Some(e) = &self.load_errors.mimeapps {
+            return Err(
+                ErrorKind::NotLoaded(self.location()?, e.clone()).into()
+            );
+        }
+
+        utils::write_atomic(
+            &self.location()?,
+            self.to_ini_string().as_bytes(),
+        )?;
+
+        // Expansions are only tracked for the user's own mimeapps.list,
+        // and only when wildcards are expanded at all
+        if self.location.is_none() && self.expand_wildcards {
+            self.expansions.save()?;
+        }
+        Ok(())
+    }
+    /// Get every mime whose default apps or added associations differ from `before`, sorted
+    pub fn changes_since(&self, before: &MimeApps) -> Vec<Mime> {
+        use itertools::Itertools;
+
+        [
+            (&self.default_apps, &before.default_apps),
+            (&self.added_associations, &before.added_associations),
+        ]
+        .iter()
+        .flat_map(|(after, before)| {
+            after.keys().chain(before.keys()).filter(move |mime| {
+                // Mimes left with no handlers are the same as missing ones
+                let after = after.get(*mime).filter(|h| !h.is_empty());
+                let before = before.get(*mime).filter(|h| !h.is_empty());
+                after != before
+            })
+        })
+        .unique()
+        .sorted_by(|a, b| a.essence_str().cmp(b.essence_str()))
+        .cloned()
+        .collect()
+    }
+
+    /// Check with the user before saving changes to more than `expand_confirm_threshold` mimes,
+    /// like from expanding a wildcard, showing how many of each type would change
+    ///
+    /// Unless `yes` is set, this asks for confirmation in a terminal and fails otherwise.
+    pub fn confirm_changes(&self, before: &MimeApps, yes: bool) -> Result<()> {
+        let interactive =
+            std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+        self.confirm_changes_with(before, yes, interactive, utils::confirm)
+    }
+
+    fn confirm_changes_with(
+        &self,
+        before: &MimeApps,
+        yes: bool,
+        interactive: bool,
+        confirm: impl FnOnce(&str) -> Result<bool>,
+    ) -> Result<()> {
+        let changes = self.changes_since(before);
+        if yes || changes.len() <= self.expand_confirm_threshold {
+            return Ok(());
+        }
+        if !interactive {
+            return Err(ErrorKind::TooManyChanges(
+                changes.len(),
+                self.expand_confirm_threshold,
+            )
+            .into());
+        }
+
+        eprintln!("{}", change_summary(&changes));
+        if confirm(&format!("Change {} mimetypes?", changes.len()))? {
+            Ok(())
+        } else {
+            Err(ErrorKind::Cancelled.into())
+        }
+    }
+
+    /// Render mimeapps.list the way `save` writes it, without writing it,
+    /// i.e. to preview or compare changes
+    ///
+    /// Lines that are not associations, like comments and other sections
+    /// such as `[Removed Associations]`, are kept from when it was read.
+    pub fn to_ini_string(&self) -> String {
+        use itertools::Itertools;
+
+        let mut blocks = Vec::new();
+        if !self.preserved.preamble.is_empty() {
+            blocks.push(lines_block(&self.preserved.preamble));
+        }
+
+        for (section, associations) in [
+            ("Added Associations", &self.added_associations),
+            ("Default Applications", &self.default_apps),
+        ] {
+            let mut block = format!("[{}]\n", section);
+            if let Some(comments) = self.preserved.comments.get(section) {
+                block.push_str(&lines_block(comments));
+            }
+            for (k, v) in associations.iter().sorted() {
+                block.push_str(&format!(
+                    "{}={};\n",
+                    k.essence_str(),
+                    v.iter().join(";")
+                ));
+            }
+            blocks.push(block);
+        }
+
+        for (header, lines) in &self.preserved.sections {
+            blocks.push(format!("{}\n{}", header, lines_block(lines)));
+        }
+
+        blocks.join("\n")
+    }
+    pub fn print(
+        &self,
+        detailed: bool,
+        added_only: bool,
+        count: bool,
+        rows: &ListRows,
+        format: ListFormat,
+    ) -> Result<()> {
+        let sections: &[HandlerSource] = if added_only {
+            &[HandlerSource::UserAdded]
+        } else if detailed {
+            &HandlerSource::ALL
+        } else {
+            &[HandlerSource::UserDefault]
+        };
+        match format {
+            ListFormat::Jsonl => {
+                return self.write_jsonl(
+                    &mut std::io::stdout().lock(),
+                    sections,
+                    count,
+                    rows,
+                )
+            }
+            ListFormat::Tsv => {
+                return self.write_tsv(
+                    &mut std::io::stdout().lock(),
+                    sections,
+                    rows,
+                )
+            }
+            ListFormat::Table | ListFormat::Json => {}
+        }
+
+        let output_json = format == ListFormat::Json;
+        let mimeapps_table = MimeAppsTable::new(self, count, detailed, rows)?;
+        let render_table =
+            |rows: &Vec<MimeAppsEntry>| render_rows(rows, count, detailed);
+
+        if added_only {
+            if output_json {
+                println!(
+                    "{}",
+                    serde_json::to_string(&mimeapps_table.added_associations)?
+                )
+            } else {
+                println!("{}", render_table(&mimeapps_table.added_associations))
+            }
+        } else if detailed {
+            if output_json {
+                println!("{}", serde_json::to_string(&mimeapps_table)?)
+            } else {
+                println!("Default Apps");
+                println!("{}", render_table(&mimeapps_table.default_apps));
+                if !self.added_associations.is_empty() {
+                    println!("Added associations");
+                    println!(
+                        "{}",
+                        render_table(&mimeapps_table.added_associations)
+                    );
+                }
+                println!("System Apps");
+                println!("{}", render_table(&mimeapps_table.system_apps))
+            }
+        } else if output_json {
+            println!("{}", serde_json::to_string(&mimeapps_table.default_apps)?)
+        } else {
+            println!("{}", render_table(&mimeapps_table.default_apps))
+        }
+
+        Ok(())
+    }
+    /// Print the installed desktop entries in a category, e.g. `Office`
+    ///
+    /// `--jsonl` prints a json object per app, and `--tsv` its desktop file and name separated by a tab.
+    pub fn print_category(
+        &self,
+        category: &str,
+        format: ListFormat,
+    ) -> Result<()> {
+        let entries = self
+            .system_apps
+            .by_category_in(category, self.application_dirs.as_deref())
+            .into_iter()
+            .map(|(handler, entry)| CategoryEntry {
+                handler: handler.to_string(),
+                name: entry.name,
+            })
+            .collect::<Vec<_>>();
+
+        match format {
+            ListFormat::Json => {
+                println!("{}", serde_json::to_string(&entries)?)
+            }
+            ListFormat::Jsonl => {
+                for entry in &entries {
+                    println!("{}", serde_json::to_string(entry)?);
+                }
+            }
+            ListFormat::Tsv => {
+                for entry in &entries {
+                    println!("{}\t{}", entry.handler, entry.name);
+                }
+            }
+            ListFormat::Table if entries.is_empty() => {
+                println!("No installed apps are in the {} category", category)
+            }
+            ListFormat::Table => println!("{}", render_table(&entries)),
+        }
+
+        Ok(())
+    }
+    /// Print only the associations from installed desktop entries,
+    /// without anything from the user's mimeapps.list
+    pub fn print_system_only(
+        &self,
+        count: bool,
+        rows: &ListRows,
+        format: ListFormat,
+    ) -> Result<()> {
+        match format {
+            ListFormat::Jsonl => {
+                return self.write_jsonl(
+                    &mut std::io::stdout().lock(),
+                    &[HandlerSource::System],
+                    count,
+                    rows,
+                )
+            }
+            ListFormat::Tsv => {
+                return self.write_tsv(
+                    &mut std::io::stdout().lock(),
+                    &[HandlerSource::System],
+                    rows,
+                )
+            }
+            ListFormat::Table | ListFormat::Json => {}
+        }
+
+        let system_apps =
+            MimeAppsTable::new(self, count, false, rows)?.system_apps;
+
+        if format == ListFormat::Json {
+            println!("{}", serde_json::to_string(&system_apps)?)
+        } else {
+            println!("{}", render_rows(&system_apps, count, false))
+        }
+
+        Ok(())
+    }
+    /// Write the associations in the given sections as lines of the mimetype
+    /// and its handlers joined with `;`, separated by a tab, in the same order as `print`
+    fn write_tsv(
+        &self,
+        writer: &mut impl std::io::Write,
+        sections: &[HandlerSource],
+        rows: &ListRows,
+    ) -> Result<()> {
+        let mimeapps_table = MimeAppsTable::new(self, false, false, rows)?;
+
+        for source in sections {
+            for entry in mimeapps_table.section(*source) {
+                writeln!(
+                    writer,
+                    "{}\t{}",
+                    entry.mime,
+                    entry.handlers.join(";")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+    /// Write the associations in the given sections as json objects,
+    /// one per line, in the same order as `print`
+    fn write_jsonl(
+        &self,
+        writer: &mut impl std::io::Write,
+        sections: &[HandlerSource],
+        count: bool,
+        rows: &ListRows,
+    ) -> Result<()> {
+        let mimeapps_table = MimeAppsTable::new(self, count, false, rows)?;
+
+        for source in sections {
+            for entry in mimeapps_table.section(*source) {
+                let line = JsonlEntry {
+                    entry,
+                    section: source.section(),
+                };
+                serde_json::to_writer(&mut *writer, &line)?;
+                writeln!(writer)?;
+            }
+        }
+
+        Ok(())
+    }
+    /// Print the associations in mimeapps.list with handlers that are not installed,
+    /// removing just those handlers and saving if `fix` is set
+    pub fn print_orphans(
+        &mut self,
+        output_json: bool,
+        fix: bool,
+    ) -> Result<()> {
+        let raw_conf = read_if_exists(&self.location()?)?;
+        let orphans = self.orphans(&raw_conf);
+
+        if output_json {
+            println!("{}", serde_json::to_string(&orphans)?);
+        } else if orphans.is_empty() {
+            println!("No associations with missing handlers found");
+        } else {
+            println!("{}", render_table(&orphans));
+        }
+
+        if fix && !orphans.is_empty() {
+            self.remove_orphans(&orphans);
+            self.save()?;
+            if !output_json {
+                let removed =
+                    orphans.iter().map(|o| o.missing.len()).sum::<usize>();
+                println!("Removed {} handler(s)", removed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove just the missing handlers of each orphan, keeping the rest of its handlers
+    fn remove_orphans(&mut self, orphans: &[OrphanEntry]) {
+        for orphan in orphans {
+            let Ok(mime) = Mime::from_str(&orphan.mime) else {
+                continue;
+            };
+            for handler in &orphan.missing {
+                self.remove_from_associations(
+                    &mime,
+                    &Handler::assume_valid(handler.into()),
+                );
+            }
+        }
+    }
+
+    /// Each association in the raw mimeapps.list with at least one handler
+    /// whose desktop entry cannot be loaded, in the order they are listed
+    fn orphans(&self, raw_conf: &str) -> Vec<OrphanEntry> {
+        use itertools::Itertools;
+
+        Self::raw_associations(raw_conf)
+            .into_iter()
+            .filter_map(|(source, mime, value)| {
+                let handlers = value
+                    .split(';')
+                    .filter(|h| !h.is_empty())
+                    .unique()
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>();
+                let missing = handlers
+                    .iter()
+                    .filter(|h| {
+                        self.get_entry(&Handler::assume_valid(h.into()))
+                            .is_err()
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                (!missing.is_empty()).then(|| OrphanEntry {
+                    mime: mime.to_owned(),
+                    section: source.section(),
+                    handlers,
+                    missing,
+                })
+            })
+            .collect()
+    }
+    /// Every mime with an association in default apps, added associations or system apps,
+    /// sorted, along with the handler it is opened with if it has one
+    ///
+    /// Wildcards and regex handlers are not considered,
+    /// since these are the mimes handlr can be asked to change.
+    pub fn configured_mimes(&self) -> Vec<(Mime, Option<Handler>)> {
+        use itertools::Itertools;
+
+        self.default_apps
+            .keys()
+            .chain(self.added_associations.keys())
+            .chain(self.system_apps.0.keys())
+            .unique()
+            .sorted_by(|a, b| a.essence_str().cmp(b.essence_str()))
+            .map(|mime| {
+                let handler = self
+                    .get_all_handlers(mime)
+                    .into_iter()
+                    .next()
+                    .map(|(handler, _)| handler);
+                (mime.clone(), handler)
+            })
+            .collect()
+    }
+    /// Print `configured_mimes` for shell completion,
+    /// with the handler after a tab so it can be shown as a description
+    pub fn list_configured_mimes(&self) -> Result<()> {
+        use std::io::Write;
+
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+
+        for (mime, handler) in self.configured_mimes() {
+            match handler {
+                Some(handler) => writeln!(stdout, "{}\t{}", mime, handler)?,
+                None => writeln!(stdout, "{}", mime)?,
+            }
+        }
+
+        Ok(())
+    }
+    pub fn list_handlers() -> Result<()> {
+        use std::{io::Write, os::unix::ffi::OsStrExt};
+
+        let stdout = std::io::stdout();
+        let mut stdout = stdout.lock();
+
+        SystemApps::get_entries()?.for_each(|(_, e)| {
+            stdout.write_all(e.file_name.as_bytes()).unwrap();
+            stdout.write_all(b"\t").unwrap();
+            stdout.write_all(e.name.as_bytes()).unwrap();
+            stdout.write_all(b"\n").unwrap();
+        });
+
+        Ok(())
+    }
+    /// Show the command `handler` would open `path` with and ask whether to run it
+    fn confirm_open(
+        &self,
+        handler: &GenericHandler,
+        path: &UserPath,
+        action: Option<&str>,
+    ) -> Result<bool> {
+        let entry = self.get_generic_entry(handler, action)?;
+        let command = shlex::try_join(
+            entry
+                .expand_exec(vec![path.to_string()])
+                .iter()
+                .map(String::as_str),
+        )
+        .unwrap_or_else(|_| entry.exec.clone());
+
+        eprintln!("{}", command);
+        let name = match handler {
+            GenericHandler::Handler(handler) => handler.to_string(),
+            GenericHandler::RegexHandler(_) => entry.exec,
+        };
+        crate::utils::confirm(&format!("Open {} with {}?", path, name))
+    }
+    /// Get the handler for each path as `handlr open` would, see `assign_handlers`
+    pub(crate) fn assign<'a>(
+        &self,
+        paths: &'a [UserPath],
+        action: Option<&str>,
+    ) -> Assignments<'a> {
+        self.assign_handlers(paths, action, |mime| {
+            // The portal cannot run actions
+            if self.portal && action.is_none() {
+                return self.lookup_unless_choosing(mime);
+            }
+            Ok(Some(self.lookup(mime, self.enable_selector)?.0))
+        })
+    }
+
+    /// Look up the handler for `mime` without the selector,
+    /// or get `None` if the user should pick one in the desktop portal's dialog instead
+    ///
+    /// That is when the selector would be shown, or when no handler is found,
+    /// since the portal also knows about applications handlr does not.
+    fn lookup_unless_choosing(&self, mime: &Mime) -> Result<Option<Handler>> {
+        match self.lookup(mime, false) {
+            Ok((handler, HandlerSource::UserDefault))
+                if self.enable_selector =>
+            {
+                let choices = self
+                    .probes(mime)
+                    .iter()
+                    .filter_map(|probe| self.default_apps.get(probe))
+                    .find(|handlers| handlers.contains(&handler))
+                    .into_iter()
+                    .flatten()
+                    .filter(|handler| {
+                        self.get_entry(handler).is_ok_and(|e| e.is_shown())
+                    })
+                    .count();
+                Ok((choices < 2).then_some(handler))
+            }
+            Ok((handler, _)) => Ok(Some(handler)),
+            Err(e) if matches!(*e.kind, ErrorKind::NotFound(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Open `paths` of `mime` with the application picked in the desktop portal's "Open With" dialog
+    ///
+    /// The portal opens each path itself until it says which application was picked,
+    /// then the rest are returned to be opened with it like any other handler,
+    /// as long as handlr can find its desktop file.
+    /// If the portal cannot be used, the handler is looked up with the selector instead, with a warning.
+    fn choose_with_portal<'a>(
+        &self,
+        mime: &Mime,
+        paths: Vec<&'a UserPath>,
+    ) -> Result<Vec<(&'a UserPath, Handler)>> {
+        let mut paths = paths.into_iter();
+        while let Some(path) = paths.next() {
+            match open_with_chooser(path, mime.essence_str()) {
+                Ok(Some(handler)) if self.get_entry(&handler).is_ok() => {
+                    return Ok(paths
+                        .map(|path| (path, handler.clone()))
+                        .collect())
+                }
+                Ok(_) => {}
+                Err(e) if matches!(*e.kind, ErrorKind::Portal(_)) => {
+                    tracing::warn!("{}, using the selector instead", e);
+                    let handler = self.lookup(mime, self.enable_selector)?.0;
+                    return Ok(std::iter::once(path)
+                        .chain(paths)
+                        .map(|path| (path, handler.clone()))
+                        .collect());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(Vec::new())
+    }
+
+    /// Get the handler for each path, in the order the paths were given
+    ///
+    /// Paths matching a regex handler are resolved first,
+    /// then `lookup` is used once for each mime the rest have,
+    /// so the selector is only shown once for each mime, however many paths have it.
+    /// Lookups that fail give one error for their mime,
+    /// and the paths of mimes whose selection is cancelled are left out.
+    /// Lookups giving `None` leave the paths of their mime to the desktop portal.
+    fn assign_handlers<'a>(
+        &self,
+        paths: &'a [UserPath],
+        action: Option<&str>,
+        mut lookup: impl FnMut(&Mime) -> Result<Option<Handler>>,
+    ) -> Assignments<'a> {
+        let mut assignments = Assignments::default();
+        let mut assigned = Vec::new();
+        let mut by_mime: Vec<(Mime, Vec<(usize, &UserPath)>)> = Vec::new();
+
+        for (i, path) in paths.iter().enumerate() {
+            if let Some(handler) = self.get_handler_from_config(path, action) {
+                assigned.push((i, path, handler));
+                continue;
+            }
+
+            match self.mime_of(path) {
+                Ok(mime) => {
+                    match by_mime.iter_mut().find(|(m, _)| *m == mime) {
+                        Some((_, paths)) => paths.push((i, path)),
+                        None => by_mime.push((mime, vec![(i, path)])),
+                    }
+                }
+                Err(e) => assignments.errors.push(e),
+            }
+        }
+
+        for (mime, paths) in by_mime {
+            match lookup(&mime) {
+                Ok(Some(handler)) => {
+                    assigned.extend(paths.into_iter().map(|(i, path)| {
+                        (i, path, GenericHandler::Handler(handler.clone()))
+                    }))
+                }
+                Ok(None) => assignments.chooser.push((
+                    mime,
+                    paths.into_iter().map(|(_, path)| path).collect(),
+                )),
+                Err(e) if matches!(*e.kind, ErrorKind::Cancelled) => {
+                    assignments.cancelled.push(mime)
+                }
+                Err(e) => assignments.errors.push(e),
+            }
+        }
+
+        assigned.sort_by_key(|(i, _, _)| *i);
+        assignments.assigned = assigned
+            .into_iter()
+            .map(|(_, path, handler)| (path, handler))
+            .collect();
+        assignments
+    }
+
+    /// Open each path with its handler, giving each handler all of its paths at once
+    ///
+    /// If `confirm` is set and handlr is run in a terminal,
+    /// each path is only opened if the user agrees after being shown its command.
+    /// If `wait` is set, every handler is waited for once all of them are started.
+    pub fn open_paths(
+        &self,
+        paths: &[UserPath],
+        action: Option<&str>,
+        confirm: bool,
+        wait: bool,
+        options: &ExecOptions,
+    ) -> Result<()> {
+        let mut handlers = Vec::new();
+        let confirm = confirm
+            && std::io::stdin().is_terminal()
+            && std::io::stdout().is_terminal();
+
+        // Paths that fail do not stop the rest from being opened
+        let Assignments {
+            mut assigned,
+            mut errors,
+            mut cancelled,
+            chooser,
+        } = self.assign(paths, action);
+
+        for (mime, paths) in chooser {
+            match self.choose_with_portal(&mime, paths) {
+                Ok(chosen) => assigned.extend(chosen.into_iter().map(
+                    |(path, handler)| (path, GenericHandler::Handler(handler)),
+                )),
+                Err(e) if matches!(*e.kind, ErrorKind::Cancelled) => {
+                    cancelled.push(mime)
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        for (path, handler) in assigned {
+            if confirm {
+                match self.confirm_open(&handler, path, action) {
+                    Ok(true) => {}
+                    Ok(false) => continue,
+                    Err(e) => {
+                        errors.push(e);
+                        continue;
+                    }
+                }
+            }
+
+            group_path(&mut handlers, handler, path.to_string())
+        }
+
+        // Nothing else to do, so there is nothing worth reporting either
+        if !cancelled.is_empty() && handlers.is_empty() && errors.is_empty() {
+            return Err(Error::from(ErrorKind::Cancelled));
+        }
+
+        errors.extend(open_groups(handlers, wait, |handler, paths| {
+            handler.open(paths, wait, action, options)
+        }));
+        if !cancelled.is_empty() {
+            errors.push(Error::from(ErrorKind::SelectionsCancelled(cancelled)));
+        }
+
+        match errors.as_slice() {
+            [] => Ok(()),
+            // Cancelling is not a failure to open anything
+            [e] if matches!(*e.kind, ErrorKind::SelectionsCancelled(_)) => {
+                Err(errors.remove(0))
+            }
+            _ => Err(Error::from(ErrorKind::OpenErrors(errors))),
+        }
+    }
+}
+
+/// The handlers `MimeApps::assign_handlers` found for a set of paths
+#[derive(Default)]
+pub(crate) struct Assignments<'a> {
+    /// Each path that can be opened along with its handler
+    pub assigned: Vec<(&'a UserPath, GenericHandler)>,
+    pub errors: Vec<Error>,
+    /// Mimes whose selection was cancelled, in the order they were asked about
+    pub cancelled: Vec<Mime>,
+    /// Paths of each mime to open with the desktop portal's "Open With" dialog,
+    /// see `MimeApps::lookup_unless_choosing`
+    pub chooser: Vec<(Mime, Vec<&'a UserPath>)>,
+}
+
+/// What `MimeApps::pick` picked
+#[derive(Debug)]
+pub enum Picked {
+    /// A handler for handlr to open the path with
+    Handler(GenericHandler),
+    /// The application picked in the desktop portal's dialog, which already opened the path,
+    /// if the portal said which one it was
+    Portal(Option<Handler>),
+}
+
+/// Add `path` to the paths `handler` opens, keeping handlers in the order they first appear
+///
+/// The groups are kept in a Vec rather than keyed by handler,
+/// since regex handlers hold compiled regexes that make poor map keys.
+pub(crate) fn group_path(
+    groups: &mut Vec<(GenericHandler, Vec<String>)>,
+    handler: GenericHandler,
+    path: String,
+) {
+    match groups.iter_mut().find(|(h, _)| *h == handler) {
+        Some((_, paths)) => paths.push(path),
+        None => groups.push((handler, vec![path])),
+    }
+}
+
+/// Open each handler's paths with `open`, carrying on past handlers that fail
+///
+/// Handlers that do not need the terminal are spawned without waiting for them,
+/// so one slow app does not hold up the rest.
+/// If `wait` is set, every handler is waited for once all of them are started,
+/// and the ones that exit unsuccessfully count as failed.
+/// Errors are labelled with the handler they came from.
+pub(crate) fn open_groups(
+    groups: impl IntoIterator<Item = (GenericHandler, Vec<String>)>,
+    wait: bool,
+    mut open: impl FnMut(&GenericHandler, Vec<String>) -> Result<Vec<Child>>,
+) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let mut started = Vec::new();
+    for (handler, paths) in groups {
+        match open(&handler, paths) {
+            Ok(children) => started.push((handler, children)),
+            Err(e) => errors.push((handler, e)),
+        }
+    }
+
+    if wait {
+        for (handler, children) in started {
+            if let Err(e) = utils::wait_all(children) {
+                errors.push((handler, e));
+            }
+        }
+    }
+
+    errors
+        .into_iter()
+        .map(|(handler, e)| {
+            Error::from(ErrorKind::HandlerFailed(handler.to_string(), e))
+        })
+        .collect()
+}
+
+/// How many mimes of each type are changed, followed by a few of them
+fn change_summary(changes: &[Mime]) -> String {
+    /// How many changed mimes are listed by name
+    const SAMPLE_SIZE: usize = 5;
+
+    let mut per_type = BTreeMap::<&str, usize>::new();
+    for mime in changes {
+        *per_type.entry(mime.type_().as_str()).or_default() += 1;
+    }
+
+    let mut summary = per_type
+        .iter()
+        .map(|(type_, count)| format!("{}/*: {}\n", type_, count))
+        .collect::<String>();
+    let sample = changes
+        .iter()
+        .take(SAMPLE_SIZE)
+        .map(|mime| mime.essence_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+    summary.push_str(&format!("i.e. {}", sample));
+    if changes.len() > SAMPLE_SIZE {
+        summary.push_str(&format!(" and {} more", changes.len() - SAMPLE_SIZE));
+    }
+    summary
+}
+
+/// Put each line on its own line
+fn lines_block(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+/// Read a file, treating a missing file as empty
+pub(crate) fn read_if_exists(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        res => Ok(res?),
+    }
+}
+
+/// Read mimeapps.list like `read_if_exists`, but warn and treat it as empty if it cannot be read,
+/// returning why along with it
+fn read_or_warn(path: &Path) -> (String, Option<String>) {
+    match read_if_exists(path) {
+        Ok(raw_conf) => (raw_conf, None),
+        Err(e) => {
+            tracing::warn!(
+                "could not read {}, so no associations from it are used: {}",
+                path.display(),
+                e
+            );
+            (String::new(), Some(e.to_string()))
+        }
+    }
+}
+
+/// Render rows of `handlr list`, with optional count and origin columns
+fn render_rows(
+    rows: &[MimeAppsEntry],
+    count: bool,
+    with_origin: bool,
+) -> String {
+    match (count, with_origin) {
+        (false, false) => render_table(&rows.iter().collect()),
+        (true, false) => {
+            render_table(&rows.iter().map(CountedEntry::new).collect())
+        }
+        (false, true) => render_table(
+            &rows.iter().map(|row| OriginEntry::new(row, row)).collect(),
+        ),
+        (true, true) => render_table(
+            &rows
+                .iter()
+                .map(|row| OriginEntry::new(row, CountedEntry::new(row)))
+                .collect(),
+        ),
+    }
+}
+
+/// Origin of rows from installed desktop entries in `handlr list --all`
+const SYSTEM_ORIGIN: &str = "installed desktop entries";
+
+/// Internal helper struct for turning MimeApps into tabular data
+#[derive(PartialEq, Eq, PartialOrd, Ord, Tabled, Serialize)]
+struct MimeAppsEntry {
+    #[tabled(display_with("Self::display_mime", self))]
+    mime: String,
+    #[tabled(display_with("Self::display_handlers", self))]
+    handlers: Vec<String>,
+    #[tabled(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expanded_from: Option<String>,
+    #[tabled(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<usize>,
+    #[tabled(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'static str>,
+    #[tabled(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    origin: Option<String>,
+}
+
+impl MimeAppsEntry {
+    fn new(
+        mime: &Mime,
+        handlers: &VecDeque<Handler>,
+        expanded_from: Option<&str>,
+        count: bool,
+        origin: Option<(HandlerSource, &str)>,
+    ) -> Self {
+        Self {
+            count: count.then_some(handlers.len()),
+            source: origin.map(|(source, _)| source.label()),
+            origin: origin.map(|(_, origin)| origin.to_owned()),
+            mime: mime.to_string(),
+            handlers: handlers
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>(),
+            expanded_from: expanded_from.map(|pattern| pattern.to_owned()),
+        }
+    }
+
+    fn display_mime(&self) -> String {
+        match &self.expanded_from {
+            Some(pattern) => format!("{} (from {})", self.mime, pattern),
+            None => self.mime.clone(),
+        }
+    }
+
+    fn display_handlers(&self) -> String {
+        // If output is a terminal, optimize for readability
+        // Otherwise, if piped, optimize for parseability
+        let separator = if std::io::stdout().is_terminal() {
+            ",\n"
+        } else {
+            ", "
+        };
+
+        self.handlers.join(separator)
+    }
+}
+
+/// Internal helper struct for a MimeAppsEntry on its own line of `handlr list --jsonl`
+#[derive(Serialize)]
+struct JsonlEntry<'a> {
+    #[serde(flatten)]
+    entry: &'a MimeAppsEntry,
+    section: &'static str,
+}
+
+/// Internal helper struct for showing a MimeAppsEntry with its handler count
+#[derive(Tabled)]
+struct CountedEntry<'a> {
+    #[tabled(inline)]
+    entry: &'a MimeAppsEntry,
+    count: usize,
+}
+
+impl<'a> CountedEntry<'a> {
+    fn new(entry: &'a MimeAppsEntry) -> Self {
+        Self {
+            entry,
+            count: entry.handlers.len(),
+        }
+    }
+}
+
+/// Internal helper struct for showing a row with where its association comes from
+#[derive(Tabled)]
+struct OriginEntry<T: Tabled> {
+    source: &'static str,
+    origin: String,
+    #[tabled(inline)]
+    entry: T,
+}
+
+impl<T: Tabled> OriginEntry<T> {
+    /// Show `entry` with the source and origin of `row`
+    fn new(row: &MimeAppsEntry, entry: T) -> Self {
+        Self {
+            source: row.source.unwrap_or_default(),
+            origin: row.origin.clone().unwrap_or_default(),
+            entry,
+        }
+    }
+}
+
+/// An installed desktop entry shown by `handlr list --category`
+#[derive(Debug, PartialEq, Eq, Tabled, Serialize)]
+struct CategoryEntry {
+    handler: String,
+    name: String,
+}
+
+/// An association changed by `handlr purge`
+#[derive(Debug, PartialEq, Eq, Tabled)]
+struct PurgedEntry {
+    mime: String,
+    section: String,
+    #[tabled(rename = "remaining handlers")]
+    remaining: String,
+}
+
+/// An association found by `handlr list --orphans`
+#[derive(Debug, PartialEq, Eq, Tabled, Serialize)]
+struct OrphanEntry {
+    mime: String,
+    section: &'static str,
+    #[tabled(display_with("Self::display_handlers", self))]
+    handlers: Vec<String>,
+    /// Handlers whose desktop entries cannot be loaded
+    #[tabled(skip)]
+    missing: Vec<String>,
+}
+
+impl OrphanEntry {
+    fn display_handlers(&self) -> String {
+        self.handlers
+            .iter()
+            .map(|h| {
+                if self.missing.contains(h) {
+                    format!("{} (missing)", h)
+                } else {
+                    h.clone()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Everything that decides how a mime is opened, shown by `handlr info --mime`
+#[derive(Debug, Serialize)]
+struct MimeInfo {
+    mime: String,
+    /// The handler the mime is opened with without the selector
+    handler: Option<String>,
+    /// Where `handler` was found, as a section of `handlr list --jsonl` in json
+    #[serde(serialize_with = "serialize_section")]
+    source: Option<HandlerSource>,
+    entries: Vec<MimeInfoEntry>,
+}
+
+fn serialize_section<S: serde::Serializer>(
+    source: &Option<HandlerSource>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    source.map(|source| source.section()).serialize(serializer)
+}
+
+/// A source and mime checked for a handler, shown by `handlr get --verbose`
+#[derive(Debug, PartialEq, Eq, Tabled, Serialize)]
+struct LookupStep {
+    step: String,
+    mime: String,
+    #[tabled(rename = "result", display_with("Self::display_result", self))]
+    found: bool,
+    #[tabled(display_with("Self::display_handler", self))]
+    handler: Option<String>,
+}
+
+impl LookupStep {
+    fn new(
+        source: HandlerSource,
+        mime: &Mime,
+        handler: Option<&Handler>,
+    ) -> Self {
+        Self {
+            step: format!("Check {}", source),
+            mime: mime.to_string(),
+            found: handler.is_some(),
+            handler: handler.map(|handler| handler.to_string()),
+        }
+    }
+
+    fn display_result(&self) -> String {
+        if self.found { "found" } else { "not found" }.to_owned()
+    }
+
+    fn display_handler(&self) -> String {
+        self.handler.clone().unwrap_or_default()
+    }
+}
+
+/// Handlers that could open a mime, along with where they come from
+#[derive(Debug, PartialEq, Eq, Tabled, Serialize)]
+struct MimeInfoEntry {
+    source: String,
+    /// The mimetype, wildcard or example file name the handlers are for
+    matched: String,
+    #[tabled(display_with("Self::display_handlers", self))]
+    handlers: Vec<String>,
+}
+
+impl MimeInfoEntry {
+    fn display_handlers(&self) -> String {
+        self.handlers.join(", ")
+    }
+}
+
+/// Internal helper struct for turning MimeApps into tabular data
+#[derive(Serialize)]
+struct MimeAppsTable {
+    added_associations: Vec<MimeAppsEntry>,
+    default_apps: Vec<MimeAppsEntry>,
+    system_apps: Vec<MimeAppsEntry>,
+}
 
 impl MimeAppsTable {
-    fn new(mimeapps: &MimeApps) -> Self {
-        fn to_entries(
-            map: &HashMap<Mime, VecDeque<Handler>>,
-        ) -> Vec<MimeAppsEntry> {
+    /// Rows from the given source
+    fn section(&self, source: HandlerSource) -> &[MimeAppsEntry] {
+        match source {
+            HandlerSource::UserDefault => &self.default_apps,
+            HandlerSource::UserAdded => &self.added_associations,
+            HandlerSource::System => &self.system_apps,
+            HandlerSource::Fallback => &[],
+        }
+    }
+
+    /// Rows are labelled with where they come from if `with_origin` is set,
+    /// i.e. their section and the user's mimeapps.list or the installed desktop entries
+    fn new(
+        mimeapps: &MimeApps,
+        count: bool,
+        with_origin: bool,
+        list_rows: &ListRows,
+    ) -> Result<Self> {
+        let user_origin = with_origin
+            .then(|| mimeapps.location())
+            .transpose()?
+            .map(|path| path.display().to_string());
+        let system_origin = with_origin.then_some(SYSTEM_ORIGIN);
+
+        let to_entries = |map: &HashMap<Mime, VecDeque<Handler>>,
+                          expansions: Option<&WildcardExpansions>,
+                          source: HandlerSource,
+                          origin: Option<&str>|
+         -> Vec<MimeAppsEntry> {
             let mut rows = map
                 .iter()
-                .map(|(mime, handlers)| MimeAppsEntry::new(mime, handlers))
-                .collect::<Vec<_>>();
-            rows.sort_unstable();
-            rows
+                .map(|(mime, handlers)| {
+                    MimeAppsEntry::new(
+                        mime,
+                        handlers,
+                        expansions.and_then(|e| e.origin(mime)),
+                        count,
+                        origin.map(|origin| (source, origin)),
+                    )
+                })
+                .filter(|entry| list_rows.keep(entry))
+                .collect::<Vec<_>>();
+            rows.sort_unstable();
+            if list_rows.sort_by == SortBy::Handler {
+                // Stable, so mimes stay sorted within each handler
+                rows.sort_by(|a, b| {
+                    a.handlers.first().cmp(&b.handlers.first())
+                });
+            }
+            rows
+        };
+        Ok(Self {
+            added_associations: to_entries(
+                &mimeapps.added_associations,
+                None,
+                HandlerSource::UserAdded,
+                user_origin.as_deref(),
+            ),
+            default_apps: to_entries(
+                &mimeapps.default_apps,
+                Some(&mimeapps.expansions),
+                HandlerSource::UserDefault,
+                user_origin.as_deref(),
+            ),
+            system_apps: to_entries(
+                &mimeapps.system_apps.0,
+                None,
+                HandlerSource::System,
+                system_origin,
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RegexHandler;
+    use itertools::Itertools;
+
+    #[test]
+    fn duplicate_handlers() -> Result<()> {
+        let mut user_apps = MimeApps::default();
+        let mime = Mime::from_str("text/plain")?;
+        let handler = Handler::assume_valid("nvim.desktop".into());
+
+        assert!(user_apps.add_handler(mime.clone(), handler.clone()));
+        assert!(!user_apps.add_handler(mime.clone(), handler));
+        assert_eq!(user_apps.default_apps[&mime].len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_wildcards() -> Result<()> {
+        let mut apps = MimeApps::default();
+        for (mime, handler) in [
+            ("image/*", "eog.desktop"),
+            ("image/png", "feh.desktop"),
+            (
+                "application/vnd.oasis.opendocument.*",
+                "startcenter.desktop",
+            ),
+            ("video/*", "mpv.desktop"),
+            ("*/mp4", "vlc.desktop"),
+            ("*/webm", "mpv.desktop"),
+        ] {
+            apps.set_handler(
+                Mime::from_str(mime)?,
+                Handler::assume_valid(handler.into()),
+            );
+        }
+
+        // A specific mime under a wildcard is not a conflict,
+        // and neither are overlapping wildcards with the same handler
+        assert_eq!(
+            apps.overlapping_wildcards(),
+            [(Mime::from_str("*/mp4")?, Mime::from_str("video/*")?)]
+        );
+
+        for (mime, handler) in
+            [("audio/*", "cmus.desktop"), ("*/ogg", "mpv.desktop")]
+        {
+            apps.added_associations
+                .entry(Mime::from_str(mime)?)
+                .or_default()
+                .push_back(Handler::assume_valid(handler.into()));
+        }
+        assert_eq!(
+            apps.overlapping_wildcards(),
+            [
+                (Mime::from_str("*/mp4")?, Mime::from_str("video/*")?),
+                (Mime::from_str("*/ogg")?, Mime::from_str("audio/*")?)
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn preserved_lines() -> Result<()> {
+        let raw_conf = "# Managed by hand
+[Default Applications]
+# Editors
+text/plain=emacsclient.desktop;gone.desktop;
+
+[Removed Associations]
+text/html=cmus.desktop;
+
+[X-Custom Section]
+some key=anything goes (✓)
+[Added Associations]
+# Music
+audio/mp3=cmus.desktop;
+";
+        let mut apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        apps.read_associations(raw_conf, Path::new("mimeapps.list"));
+        apps.set_handler(
+            Mime::from_str("video/mp4")?,
+            Handler::assume_valid("cmus.desktop".into()),
+        );
+
+        // Associations are rewritten, but everything else is kept,
+        // including handlers that are not installed
+        assert_eq!(
+            apps.to_ini_string(),
+            "# Managed by hand
+
+[Added Associations]
+# Music
+audio/mp3=cmus.desktop;
+
+[Default Applications]
+# Editors
+text/plain=emacsclient.desktop;gone.desktop;
+video/mp4=cmus.desktop;
+
+[Removed Associations]
+text/html=cmus.desktop;
+
+[X-Custom Section]
+some key=anything goes (✓)
+"
+        );
+
+        // Without anything to keep, the output is unchanged
+        let mut apps = MimeApps::default();
+        apps.read_associations(
+            "[Added Associations]\n\n[Default Applications]\n",
+            Path::new("mimeapps.list"),
+        );
+        assert_eq!(
+            apps.to_ini_string(),
+            "[Added Associations]\n\n[Default Applications]\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn ini_round_trip() -> Result<()> {
+        let read = |raw_conf: &str| {
+            let mut apps = MimeApps {
+                application_dirs: Some(vec![PathBuf::from("tests")]),
+                ..Default::default()
+            };
+            apps.read_associations(raw_conf, Path::new("mimeapps.list"));
+            apps
+        };
+
+        let apps = read(
+            "[Default Applications]
+video/mp4=cmus.desktop;emacsclient.desktop
+text/*=emacsclient.desktop;
+# Music
+[Added Associations]
+audio/mp3=cmus.desktop;
+",
+        );
+        let ini = apps.to_ini_string();
+        let reread = read(&ini);
+
+        assert_eq!(reread.default_apps, apps.default_apps);
+        assert_eq!(reread.added_associations, apps.added_associations);
+        assert_eq!(reread.to_ini_string(), ini);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rename_handler() -> Result<()> {
+        let raw_conf = "[Added Associations]
+audio/mp3=firefox.desktop;cmus.desktop;
+
+[Default Applications]
+text/html=firefox.desktop;
+text/plain=emacsclient.desktop;
+x-scheme-handler/https=cmus.desktop;firefox.desktop;gone.desktop;
+video/mp4=cmus.desktop;firefox.desktop;
+";
+        let mut apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        apps.read_associations(raw_conf, Path::new("mimeapps.list"));
+
+        // firefox.desktop is not installed, but is renamed anyway
+        let renamed = apps.rename_associations(
+            raw_conf,
+            &Handler::assume_valid("firefox.desktop".into()),
+            &Handler::assume_valid("cmus.desktop".into()),
+        );
+        assert_eq!(renamed, 4);
+
+        let handlers = |mime: &str| -> Result<Vec<String>> {
+            Ok(apps
+                .default_apps
+                .get(&Mime::from_str(mime)?)
+                .into_iter()
+                .flatten()
+                .map(|h| h.to_string())
+                .collect())
+        };
+        assert_eq!(handlers("text/html")?, ["cmus.desktop"]);
+        assert_eq!(handlers("text/plain")?, ["emacsclient.desktop"]);
+        // Handlers are not duplicated, and missing ones are kept
+        assert_eq!(
+            handlers("x-scheme-handler/https")?,
+            ["cmus.desktop", "gone.desktop"]
+        );
+        assert_eq!(handlers("video/mp4")?, ["cmus.desktop"]);
+        assert_eq!(
+            apps.added_associations[&Mime::from_str("audio/mp3")?],
+            [Handler::assume_valid("cmus.desktop".into())]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn purge() -> Result<()> {
+        let user_apps = || -> Result<MimeApps> {
+            MimeApps::from_paths(
+                Path::new("/nonexistent/mimeapps.list"),
+                &[PathBuf::from("tests")],
+                &Config::default(),
+            )
+        };
+        let raw_conf = "[Added Associations]
+audio/mp3=gone.desktop;cmus.desktop;
+
+[Default Applications]
+text/plain=gone.desktop;
+text/x-rust=emacsclient.desktop;gone.desktop;
+video/mp4=cmus.desktop;
+";
+        let mut apps = user_apps()?;
+        apps.read_associations(raw_conf, Path::new("mimeapps.list"));
+
+        let rows = apps.purge_associations(
+            raw_conf,
+            &Handler::assume_valid("gone.desktop".into()),
+        );
+        let entry = |mime: &str, section: &str, remaining: &str| PurgedEntry {
+            mime: mime.into(),
+            section: section.into(),
+            remaining: remaining.into(),
+        };
+        assert_eq!(
+            rows,
+            [
+                entry("audio/mp3", "added associations", "cmus.desktop"),
+                entry("text/plain", "default apps", ""),
+                entry("text/x-rust", "default apps", "emacsclient.desktop"),
+            ]
+        );
+        assert!(!apps
+            .default_apps
+            .contains_key(&Mime::from_str("text/plain")?));
+        assert_eq!(apps.default_apps.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wildcard_mimes() -> Result<()> {
+        let mut user_apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        user_apps.add_handler(
+            Mime::from_str("video/*").unwrap(),
+            Handler::assume_valid("cmus.desktop".into()),
+        );
+        user_apps.add_handler(
+            Mime::from_str("video/webm").unwrap(),
+            Handler::assume_valid("emacsclient.desktop".into()),
+        );
+
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("video/mp4")?)?
+                .to_string(),
+            "cmus.desktop"
+        );
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("video/asdf")?)?
+                .to_string(),
+            "cmus.desktop"
+        );
+
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("video/webm")?)?
+                .to_string(),
+            "emacsclient.desktop"
+        );
+
+        // Scheme handlers fall back to a catch-all for unknown protocols the same way
+        user_apps.add_handler(
+            Mime::from_str("x-scheme-handler/*")?,
+            Handler::assume_valid("cmus.desktop".into()),
+        );
+        user_apps.add_handler(
+            Mime::from_str("x-scheme-handler/https")?,
+            Handler::assume_valid("emacsclient.desktop".into()),
+        );
+        user_apps.system_apps.0.insert(
+            Mime::from_str("x-scheme-handler/magnet")?,
+            vec![Handler::assume_valid("no-display.desktop".into())].into(),
+        );
+        let magnet: UserPath = "magnet:?xt=urn:btih:0123456789".parse()?;
+        assert_eq!(
+            user_apps.get_handler(&magnet.get_mime()?)?.to_string(),
+            "cmus.desktop"
+        );
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("x-scheme-handler/https")?)?
+                .to_string(),
+            "emacsclient.desktop"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn wildcard_expansion() -> Result<()> {
+        let mut user_apps = MimeApps {
+            expand_wildcards: true,
+            ..Default::default()
+        };
+        let png = Mime::from_str("image/png")?;
+        let jpeg = Mime::from_str("image/jpeg")?;
+        let get = |apps: &MimeApps, mime: &Mime| {
+            apps.default_apps
+                .get(mime)
+                .map(|handlers| handlers.front().unwrap().to_string())
+        };
+
+        // Expanding sets every matching mime individually
+        user_apps.set_handler(
+            Mime::from_str("image/*")?,
+            Handler::assume_valid("feh.desktop".into()),
+        );
+        assert!(!user_apps
+            .default_apps
+            .contains_key(&Mime::from_str("image/*")?));
+        assert_eq!(get(&user_apps, &png).as_deref(), Some("feh.desktop"));
+        assert_eq!(get(&user_apps, &jpeg).as_deref(), Some("feh.desktop"));
+        assert_eq!(user_apps.expansions.origin(&png), Some("image/*"));
+
+        // Setting a single mime overrides the expansion
+        user_apps.set_handler(
+            png.clone(),
+            Handler::assume_valid("imv.desktop".into()),
+        );
+        assert_eq!(user_apps.expansions.origin(&png), None);
+        assert_eq!(user_apps.expansions.origin(&jpeg), Some("image/*"));
+
+        // Unsetting the wildcard leaves the override alone
+        assert!(user_apps.remove_default(&Mime::from_str("image/*")?));
+        assert_eq!(get(&user_apps, &png).as_deref(), Some("imv.desktop"));
+        assert_eq!(get(&user_apps, &jpeg), None);
+        assert_eq!(user_apps.expansions, WildcardExpansions::default());
+
+        // The catch-all for protocols is kept as it is
+        let schemes = Mime::from_str("x-scheme-handler/*")?;
+        user_apps.set_handler(
+            schemes.clone(),
+            Handler::assume_valid("firefox.desktop".into()),
+        );
+        assert_eq!(
+            get(&user_apps, &schemes).as_deref(),
+            Some("firefox.desktop")
+        );
+        assert_eq!(user_apps.expansions, WildcardExpansions::default());
+
+        Ok(())
+    }
+
+    #[test]
+    fn set_handler_glob() -> Result<()> {
+        let mut user_apps = MimeApps::default();
+        let wildcard = Mime::from_str("image/*")?;
+        let png = Mime::from_str("image/png")?;
+
+        let expansion = user_apps.wildcard_expansion(&wildcard).unwrap();
+        assert!(expansion.contains(&png));
+        assert!(expansion.iter().all(|mime| mime.type_() == "image"));
+        assert_eq!(user_apps.wildcard_expansion(&png), None);
+
+        // Wildcards are expanded without expand_wildcards
+        let set = user_apps.set_handler_glob(
+            wildcard.clone(),
+            Handler::assume_valid("eog.desktop".into()),
+        );
+        assert_eq!(set, expansion);
+        assert!(!user_apps.default_apps.contains_key(&wildcard));
+        assert_eq!(
+            user_apps.default_apps.get(&png),
+            Some(&VecDeque::from(vec![Handler::assume_valid(
+                "eog.desktop".into()
+            )]))
+        );
+        assert_eq!(user_apps.expansions.origin(&png), Some("image/*"));
+
+        // While set_handler still keeps the wildcard
+        user_apps.set_handler(
+            wildcard.clone(),
+            Handler::assume_valid("feh.desktop".into()),
+        );
+        assert!(user_apps.default_apps.contains_key(&wildcard));
+
+        Ok(())
+    }
+
+    #[test]
+    fn wildcard_overlap() -> Result<()> {
+        let mut apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        let mp4 = Mime::from_str("video/mp4")?;
+        let video = Mime::from_str("video/*")?;
+        let cmus = Handler::assume_valid("cmus.desktop".into());
+        let emacs = Handler::assume_valid("emacsclient.desktop".into());
+        let default_for = |apps: &MimeApps, mime: &Mime| {
+            apps.default_apps.get(mime).cloned().unwrap_or_default()
+        };
+        let opened_with = |apps: &MimeApps| {
+            apps.lookup(&mp4, false).ok().map(|(handler, _)| handler)
+        };
+
+        // The exact mime and its wildcard are separate lists,
+        // but a handler is only ever added to each list once
+        assert!(apps.add_handler(video.clone(), cmus.clone()));
+        assert!(apps.add_handler(mp4.clone(), cmus.clone()));
+        assert!(!apps.add_handler(mp4.clone(), cmus.clone()));
+        assert!(apps.add_handler(mp4.clone(), emacs.clone()));
+        assert_eq!(default_for(&apps, &mp4), [cmus.clone(), emacs.clone()]);
+        assert_eq!(default_for(&apps, &video), vec![cmus.clone()]);
+
+        // The exact mime comes first, in the order its handlers were added
+        assert_eq!(opened_with(&apps), Some(cmus.clone()));
+        assert_eq!(
+            apps.get_all_handlers(&mp4)
+                .into_iter()
+                .map(|(handler, _)| handler)
+                .collect::<Vec<_>>(),
+            [cmus.clone(), emacs.clone()]
+        );
+
+        assert!(apps.remove_default_handler(&mp4, &cmus, false));
+        assert_eq!(opened_with(&apps), Some(emacs.clone()));
+
+        // Once the exact mime has no handlers left, the wildcard is used
+        assert!(apps.remove_default_handler(&mp4, &emacs, false));
+        assert!(!apps.default_apps.contains_key(&mp4));
+        assert_eq!(opened_with(&apps), Some(cmus.clone()));
+        assert_eq!(
+            apps.get_all_handlers(&mp4),
+            [(cmus.clone(), HandlerSource::UserDefault)]
+        );
+
+        // Which is only changed when asked to
+        assert!(!apps.remove_default_handler(&mp4, &cmus, false));
+        assert_eq!(opened_with(&apps), Some(cmus.clone()));
+        assert!(apps.remove_default_handler(&mp4, &cmus, true));
+        assert!(!apps.default_apps.contains_key(&video));
+        assert_eq!(opened_with(&apps), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn confirm_changes() -> Result<()> {
+        let before = MimeApps {
+            expand_wildcards: true,
+            expand_confirm_threshold: 3,
+            ..Default::default()
+        };
+        let never_asked = |_: &str| -> Result<bool> { panic!("asked") };
+
+        let mut one = before.clone();
+        one.set_handler(
+            Mime::from_str("text/plain")?,
+            Handler::assume_valid("emacsclient.desktop".into()),
+        );
+        assert_eq!(one.changes_since(&before), [Mime::from_str("text/plain")?]);
+        one.confirm_changes_with(&before, false, false, never_asked)?;
+
+        let mut many = before.clone();
+        many.set_handler(
+            Mime::from_str("image/*")?,
+            Handler::assume_valid("eog.desktop".into()),
+        );
+        let changes = many.changes_since(&before);
+        assert!(changes.len() > 3);
+
+        many.confirm_changes_with(&before, true, false, never_asked)?;
+        assert!(matches!(
+            *many
+                .confirm_changes_with(&before, false, false, never_asked)
+                .unwrap_err()
+                .kind,
+            ErrorKind::TooManyChanges(n, 3) if n == changes.len()
+        ));
+        assert!(matches!(
+            *many
+                .confirm_changes_with(&before, false, true, |_| Ok(false))
+                .unwrap_err()
+                .kind,
+            ErrorKind::Cancelled
+        ));
+        many.confirm_changes_with(&before, false, true, |_| Ok(true))?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn change_summary() -> Result<()> {
+        let changes = [
+            "audio/flac",
+            "image/gif",
+            "image/jpeg",
+            "image/png",
+            "image/webp",
+            "text/plain",
+            "text/x-c",
+        ]
+        .iter()
+        .map(|mime| Mime::from_str(mime))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        assert_eq!(
+            super::change_summary(&changes),
+            "audio/*: 1\nimage/*: 4\ntext/*: 2\n\
+             i.e. audio/flac, image/gif, image/jpeg, image/png, image/webp and 2 more"
+        );
+        assert_eq!(
+            super::change_summary(&changes[..1]),
+            "audio/*: 1\ni.e. audio/flac"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlapping_wildcard_expansions() -> Result<()> {
+        let mut user_apps = MimeApps {
+            expand_wildcards: true,
+            ..Default::default()
+        };
+        let png = Mime::from_str("image/png")?;
+
+        user_apps.set_handler(
+            Mime::from_str("image/*")?,
+            Handler::assume_valid("feh.desktop".into()),
+        );
+        user_apps.add_handler(
+            Mime::from_str("*/png")?,
+            Handler::assume_valid("gimp.desktop".into()),
+        );
+
+        // The most recent expansion owns mimes both of them match
+        assert_eq!(user_apps.expansions.origin(&png), Some("*/png"));
+        assert_eq!(
+            user_apps.default_apps[&png],
+            VecDeque::from(vec![
+                Handler::assume_valid("feh.desktop".into()),
+                Handler::assume_valid("gimp.desktop".into())
+            ])
+        );
+        assert!(user_apps.remove_default(&Mime::from_str("image/*")?));
+        assert!(user_apps.default_apps.contains_key(&png));
+        assert!(user_apps.remove_default(&Mime::from_str("*/png")?));
+        assert!(!user_apps.default_apps.contains_key(&png));
+
+        Ok(())
+    }
+
+    #[test]
+    fn failed_transaction() -> Result<()> {
+        let mut user_apps = MimeApps::default();
+        let mime = Mime::from_str("text/plain")?;
+
+        let res = user_apps.transaction(|apps| {
+            apps.set_handler(
+                mime.clone(),
+                Handler::assume_valid("helix.desktop".into()),
+            );
+            // Would normally save on its own
+            apps.unset_handler(&mime)?;
+            apps.transaction(|_| Err(Error::from(ErrorKind::Cancelled)))
+        });
+
+        assert!(matches!(*res.unwrap_err().kind, ErrorKind::Cancelled));
+        assert!(!user_apps.in_transaction);
+
+        Ok(())
+    }
+
+    #[test]
+    fn wildcard_sources() -> Result<()> {
+        let handler = |name: &str| -> VecDeque<Handler> {
+            vec![Handler::assume_valid(name.into())].into()
+        };
+        let get = |apps: &MimeApps, mime: &str| -> Result<String> {
+            Ok(apps.get_handler(&Mime::from_str(mime)?)?.to_string())
+        };
+
+        let mut user_apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        user_apps.added_associations.insert(
+            Mime::from_str("image/*")?,
+            handler("org.handlr.Viewer.desktop"),
+        );
+        user_apps
+            .system_apps
+            .0
+            .insert(Mime::from_str("image/png")?, handler("gimp.desktop"));
+        user_apps
+            .system_apps
+            .0
+            .insert(Mime::from_str("video/*")?, handler("mpv.desktop"));
+
+        // Wildcards in added associations are used before system apps
+        assert_eq!(get(&user_apps, "image/png")?, "org.handlr.Viewer.desktop");
+        assert_eq!(get(&user_apps, "image/jpeg")?, "org.handlr.Viewer.desktop");
+        // Wildcards also apply to system apps
+        assert_eq!(get(&user_apps, "video/mp4")?, "mpv.desktop");
+
+        // Aliases are resolved before wildcards
+        user_apps.system_apps.0.insert(
+            Mime::from_str("application/*")?,
+            handler("xdg-open.desktop"),
+        );
+        user_apps.system_apps.0.insert(
+            Mime::from_str("application/pdf")?,
+            handler("org.pwmt.zathura.desktop"),
+        );
+        assert_eq!(
+            get(&user_apps, "application/x-pdf")?,
+            "org.pwmt.zathura.desktop"
+        );
+
+        // Exact added associations win over wildcard ones
+        user_apps.added_associations.insert(
+            Mime::from_str("image/png")?,
+            handler("no-display.desktop"),
+        );
+        assert_eq!(get(&user_apps, "image/png")?, "no-display.desktop");
+
+        // Default apps win over everything else, even with a wildcard
+        user_apps
+            .default_apps
+            .insert(Mime::from_str("image/*")?, handler("cmus.desktop"));
+        assert_eq!(get(&user_apps, "image/png")?, "cmus.desktop");
+
+        assert!(matches!(
+            *user_apps
+                .get_handler(&Mime::from_str("audio/mpeg")?)
+                .unwrap_err()
+                .kind,
+            ErrorKind::NotFound(NotFoundKind::Mime(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_from_paths() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mimeapps = dir.path().join("mimeapps.list");
+        std::fs::write(
+            &mimeapps,
+            "[Default Applications]\nx-scheme-handler/mailto=emacsclient.desktop;\n",
+        )?;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "handlers": [{
+                "exec": "freetube %u",
+                "regexes": [r"(https://)?(www\.)?youtu(be\.com|\.be)/*"],
+            }],
+        }))?;
+
+        let apps = MimeApps::from_paths(
+            &mimeapps,
+            &[PathBuf::from("tests")],
+            &config,
+        )?;
+
+        let resolution = apps.resolve(&"mailto:me@example.com".parse()?)?;
+        assert_eq!(
+            resolution.handler,
+            GenericHandler::Handler(Handler::assume_valid(
+                "emacsclient.desktop".into()
+            ))
+        );
+        assert_eq!(resolution.source, Some(HandlerSource::UserDefault));
+        assert_eq!(resolution.program, "sh");
+        assert_eq!(
+            resolution.args.last().map(String::as_str),
+            Some("mailto:me@example.com")
+        );
+
+        let resolution =
+            apps.resolve(&"https://youtu.be/dQw4w9WgXcQ".parse()?)?;
+        assert_eq!(resolution.source, None);
+        assert_eq!(resolution.program, "freetube");
+        assert_eq!(resolution.args, vec!["https://youtu.be/dQw4w9WgXcQ"]);
+
+        // Desktop entries only come from the given directories
+        assert_eq!(
+            apps.get_handler(&Mime::from_str("text/plain")?)?
+                .to_string(),
+            "emacsclient.desktop"
+        );
+        assert!(apps.resolve(&"gopher://example.com".parse()?).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn configured_mimes() -> Result<()> {
+        let apps = MimeApps::from_paths(
+            Path::new("tests/mimeapps_orphans.list"),
+            &[PathBuf::from("tests")],
+            &Config::default(),
+        )?;
+        let configured = apps
+            .configured_mimes()
+            .into_iter()
+            .map(|(mime, handler)| {
+                (mime.to_string(), handler.map(|h| h.to_string()))
+            })
+            .collect::<Vec<_>>();
+
+        let handler_of = |mime: &str| {
+            configured
+                .iter()
+                .find(|(m, _)| m == mime)
+                .map(|(_, handler)| handler.as_deref())
+        };
+        // From default apps, added associations and system apps alike
+        assert_eq!(handler_of("audio/mp3"), Some(Some("cmus.desktop")));
+        assert_eq!(handler_of("audio/flac"), Some(Some("cmus.desktop")));
+        assert_eq!(handler_of("audio/ogg"), Some(Some("cmus.desktop")));
+        assert_eq!(
+            handler_of("text/x-java"),
+            Some(Some("emacsclient.desktop"))
+        );
+        assert_eq!(handler_of("image/png"), None);
+
+        // Sorted, with each mime once
+        assert!(configured.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn mime_info() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mimeapps = dir.path().join("mimeapps.list");
+        std::fs::write(
+            &mimeapps,
+            "[Default Applications]\ntext/*=cmus.desktop;\n\n\
+             [Added Associations]\ntext/plain=cmus.desktop;\n",
+        )?;
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "fallback_handler": "cmus.desktop",
+            "handlers": [{
+                "exec": "bat %f",
+                "name": "bat",
+                "regexes": [r"\.txt$"],
+            }],
+        }))?;
+
+        let apps = MimeApps::from_paths(
+            &mimeapps,
+            &[PathBuf::from("tests")],
+            &config,
+        )?;
+        let info = apps.mime_info(&Mime::from_str("text/plain")?);
+
+        let entry =
+            |source: &str, matched: &str, handler: &str| MimeInfoEntry {
+                source: source.to_owned(),
+                matched: matched.to_owned(),
+                handlers: vec![handler.to_owned()],
+            };
+        assert_eq!(info.handler.as_deref(), Some("cmus.desktop"));
+        assert_eq!(info.source, Some(HandlerSource::UserDefault));
+        // In the order they are tried
+        assert_eq!(
+            info.entries,
+            [
+                entry("default apps", "text/*", "cmus.desktop"),
+                entry("added associations", "text/plain", "cmus.desktop"),
+                entry("system apps", "text/plain", "emacsclient.desktop"),
+                entry("regex handlers", "file.txt", "bat"),
+                entry("fallback_handler", "text/plain", "cmus.desktop"),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn get_installed_handler() -> Result<()> {
+        let mut apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        let plain = Mime::from_str("text/plain")?;
+        apps.default_apps.insert(
+            plain.clone(),
+            vec![
+                Handler::assume_valid("missing-program.desktop".into()),
+                Handler::assume_valid("uninstalled.desktop".into()),
+            ]
+            .into(),
+        );
+        apps.system_apps.0.insert(
+            plain.clone(),
+            vec![Handler::assume_valid("emacsclient.desktop".into())].into(),
+        );
+
+        // The desktop file is enough for get_handler
+        assert_eq!(
+            apps.get_handler(&plain)?.to_string(),
+            "missing-program.desktop"
+        );
+        assert_eq!(
+            apps.get_installed_handler(&plain)?.to_string(),
+            "emacsclient.desktop"
+        );
+
+        apps.system_apps.0.clear();
+        assert!(apps.get_installed_handler(&plain).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn lookup_trace() -> Result<()> {
+        let mut apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        let plain = Mime::from_str("text/plain")?;
+        apps.add_association(
+            plain.clone(),
+            Handler::assume_valid("emacsclient.desktop".into()),
+        );
+        let steps = |apps: &MimeApps| {
+            let mut trace = Vec::new();
+            let res = apps.lookup_traced(&plain, false, Some(&mut trace));
+            let steps = trace
+                .iter()
+                .map(|step| {
+                    format!(
+                        "{} {}: {}",
+                        step.step,
+                        step.mime,
+                        step.handler.as_deref().unwrap_or("-")
+                    )
+                })
+                .collect::<Vec<_>>();
+            (res.is_ok(), steps)
+        };
+
+        // Checking stops at the first handler found
+        assert_eq!(
+            steps(&apps),
+            (
+                true,
+                vec![
+                    "Check default apps text/plain: -".to_owned(),
+                    "Check default apps text/*: -".to_owned(),
+                    "Check added associations text/plain: emacsclient.desktop"
+                        .to_owned(),
+                ]
+            )
+        );
+
+        apps.added_associations.clear();
+        let (found, steps) = steps(&apps);
+        assert!(!found);
+        assert_eq!(steps.len(), 7);
+        assert_eq!(
+            steps.last().map(String::as_str),
+            Some("Check fallback_handler text/plain: -")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn fallback_handler() -> Result<()> {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "fallback_handler": "cmus.desktop",
+        }))?;
+        let apps = MimeApps::from_paths(
+            Path::new("tests/nonexistent.list"),
+            &[PathBuf::from("tests")],
+            &config,
+        )?;
+
+        // Handlers that are found still come first
+        assert_eq!(
+            apps.lookup(&Mime::from_str("text/plain")?, false)?,
+            (
+                Handler::assume_valid("emacsclient.desktop".into()),
+                HandlerSource::System
+            )
+        );
+        assert_eq!(
+            apps.lookup(&Mime::from_str("x-scheme-handler/gopher")?, false)?,
+            (
+                Handler::assume_valid("cmus.desktop".into()),
+                HandlerSource::Fallback
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_mimeapps() -> Result<()> {
+        let apps = MimeApps::from_paths(
+            Path::new("tests/mimeapps_malformed.list"),
+            &[PathBuf::from("tests")],
+            &Config::default(),
+        )?;
+        let get = |map: &HashMap<Mime, VecDeque<Handler>>, mime: &str| {
+            map.get(&Mime::from_str(mime).unwrap())
+                .map(|handlers| handlers.iter().join(";"))
+        };
+
+        assert_eq!(
+            get(&apps.added_associations, "text/english").as_deref(),
+            Some("emacsclient.desktop")
+        );
+        // The broken section header does not start a new section
+        assert_eq!(
+            get(&apps.added_associations, "text/x-java").as_deref(),
+            Some("emacsclient.desktop")
+        );
+        assert_eq!(
+            get(&apps.default_apps, "text/plain").as_deref(),
+            Some("emacsclient.desktop")
+        );
+        assert_eq!(
+            get(&apps.default_apps, "x-scheme-handler/mailto").as_deref(),
+            Some("emacsclient.desktop")
+        );
+        assert_eq!(get(&apps.default_apps, "text/x-c"), None);
+        assert_eq!(apps.added_associations.len(), 2);
+        assert_eq!(apps.default_apps.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn all_handlers() -> Result<()> {
+        let handlers = |names: &[&str]| -> VecDeque<Handler> {
+            names
+                .iter()
+                .map(|name| Handler::assume_valid(name.into()))
+                .collect()
+        };
+        let mime = Mime::from_str("text/plain")?;
+
+        let mut user_apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        // Handlers in mimeapps.list that are not installed are left out
+        user_apps.default_apps.insert(
+            mime.clone(),
+            handlers(&["gone.desktop", "emacsclient.desktop"]),
+        );
+        user_apps.added_associations.insert(
+            mime.clone(),
+            handlers(&["cmus.desktop", "emacsclient.desktop"]),
+        );
+        user_apps
+            .system_apps
+            .0
+            .insert(mime.clone(), handlers(&["cmus.desktop", "emacs.desktop"]));
+
+        assert_eq!(
+            user_apps
+                .get_all_handlers(&mime)
+                .into_iter()
+                .map(|(handler, source)| (handler.to_string(), source))
+                .collect::<Vec<_>>(),
+            vec![
+                ("emacsclient.desktop".into(), HandlerSource::UserDefault),
+                ("cmus.desktop".into(), HandlerSource::UserAdded),
+                ("emacs.desktop".into(), HandlerSource::System),
+            ]
+        );
+        assert!(user_apps
+            .get_all_handlers(&Mime::from_str("text/html")?)
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn equivalent_mimes() -> Result<()> {
+        let mut apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            equivalent_mimes: vec![vec![
+                "text/markdown".into(),
+                "text/x-markdown".into(),
+            ]],
+            ..Default::default()
+        };
+        let markdown = Mime::from_str("text/markdown")?;
+        let x_markdown = Mime::from_str("text/x-markdown")?;
+        let text = Mime::from_str("text/*")?;
+        let handlers = |name: &str| -> VecDeque<Handler> {
+            vec![Handler::assume_valid(name.into())].into()
+        };
+
+        assert_eq!(apps.equivalents(&markdown), vec![x_markdown.clone()]);
+        assert!(apps.equivalents(&Mime::from_str("text/plain")?).is_empty());
+
+        apps.default_apps
+            .insert(markdown.clone(), handlers("emacsclient.desktop"));
+        apps.default_apps
+            .insert(x_markdown.clone(), handlers("cmus.desktop"));
+        apps.default_apps
+            .insert(text.clone(), handlers("org.handlr.Viewer.desktop"));
+        apps.added_associations
+            .insert(x_markdown.clone(), handlers("working-dir.desktop"));
+        apps.system_apps
+            .0
+            .insert(markdown.clone(), handlers("no-display.desktop"));
+
+        // Exact > equivalent > wildcard > added > system
+        let mut expected = vec![
+            "emacsclient.desktop",
+            "cmus.desktop",
+            "org.handlr.Viewer.desktop",
+            "working-dir.desktop",
+            "no-display.desktop",
+        ]
+        .into_iter();
+        for remove in [Some(&markdown), Some(&x_markdown), Some(&text), None] {
+            assert_eq!(
+                apps.get_handler(&markdown)?.to_string(),
+                expected.next().unwrap()
+            );
+            match remove {
+                Some(mime) => apps.default_apps.remove(mime),
+                None => apps.added_associations.remove(&x_markdown),
+            };
         }
-        Self {
-            added_associations: to_entries(&mimeapps.added_associations),
-            default_apps: to_entries(&mimeapps.default_apps),
-            system_apps: to_entries(&mimeapps.system_apps.0),
+        assert_eq!(
+            apps.get_handler(&markdown)?.to_string(),
+            expected.next().unwrap()
+        );
+
+        // `set --sync-equivalents`
+        apps.set_handler(
+            x_markdown.clone(),
+            handlers("cmus.desktop")[0].clone(),
+        );
+        assert_eq!(apps.sync_equivalents(&x_markdown), vec![markdown.clone()]);
+        assert_eq!(
+            apps.default_apps.get(&markdown),
+            Some(&handlers("cmus.desktop"))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn added_associations() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mimeapps = dir.path().join("mimeapps.list");
+        std::fs::write(
+            &mimeapps,
+            "[Default Applications]\ntext/plain=emacsclient.desktop;\n",
+        )?;
+        let config = Config::default();
+        let dirs = [PathBuf::from("tests")];
+        let mime = Mime::from_str("text/plain")?;
+        let handler = |name: &str| Handler::assume_valid(name.into());
+
+        let mut apps = MimeApps::from_paths(&mimeapps, &dirs, &config)?;
+        // Already the default, so not duplicated
+        assert!(
+            !apps.add_association(mime.clone(), handler("emacsclient.desktop"))
+        );
+        assert!(apps.add_association(mime.clone(), handler("cmus.desktop")));
+        assert!(!apps.add_association(mime.clone(), handler("cmus.desktop")));
+        assert!(
+            apps.add_association(mime.clone(), handler("working-dir.desktop"))
+        );
+        apps.save()?;
+
+        let mut apps = MimeApps::from_paths(&mimeapps, &dirs, &config)?;
+        apps.remove_association(&mime, &handler("cmus.desktop"))?;
+        let apps = MimeApps::from_paths(&mimeapps, &dirs, &config)?;
+
+        assert_eq!(
+            apps.added_associations.get(&mime),
+            Some(&VecDeque::from([handler("working-dir.desktop")]))
+        );
+        assert_eq!(
+            apps.default_apps.get(&mime),
+            Some(&VecDeque::from([handler("emacsclient.desktop")]))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_default_entries() -> Result<()> {
+        let handler = |name: &str| Handler::assume_valid(name.into());
+        let mime = Mime::from_str("text/plain")?;
+
+        let mut user_apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        user_apps.default_apps.insert(
+            mime.clone(),
+            VecDeque::from([
+                handler("nonexistent.desktop"),
+                handler("emacsclient.desktop"),
+            ]),
+        );
+
+        // Broken handlers are skipped with or without the selector
+        for select in [false, true] {
+            assert_eq!(
+                user_apps.get_handler_from_user(&mime, select)?,
+                handler("emacsclient.desktop")
+            );
         }
+
+        // Entries hidden from menus are not offered by the selector
+        user_apps.default_apps.insert(
+            mime.clone(),
+            VecDeque::from([
+                handler("no-display.desktop"),
+                handler("emacsclient.desktop"),
+            ]),
+        );
+        assert_eq!(
+            user_apps.get_handler_from_user(&mime, false)?,
+            handler("no-display.desktop")
+        );
+        assert_eq!(
+            user_apps.get_handler_from_user(&mime, true)?,
+            handler("emacsclient.desktop")
+        );
+
+        user_apps.default_apps.insert(
+            mime.clone(),
+            VecDeque::from([handler("nonexistent.desktop")]),
+        );
+        assert!(matches!(
+            *user_apps
+                .get_handler_from_user(&mime, true)
+                .unwrap_err()
+                .kind,
+            ErrorKind::NotFound(NotFoundKind::Mime(_))
+        ));
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn portal_lookups() -> Result<()> {
+        let handler = |name: &str| Handler::assume_valid(name.into());
+        let mime = Mime::from_str("text/plain")?;
+
+        let mut user_apps = MimeApps {
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            enable_selector: true,
+            ..Default::default()
+        };
+        // With nothing to open it, the portal offers everything it knows about
+        assert_eq!(user_apps.lookup_unless_choosing(&mime)?, None);
+
+        user_apps.default_apps.insert(
+            mime.clone(),
+            VecDeque::from([handler("emacsclient.desktop")]),
+        );
+        assert_eq!(
+            user_apps.lookup_unless_choosing(&mime)?,
+            Some(handler("emacsclient.desktop"))
+        );
+
+        // The portal is used instead of the selector, but only where it would be shown
+        user_apps.default_apps.insert(
+            mime.clone(),
+            VecDeque::from([
+                handler("emacsclient.desktop"),
+                handler("org.handlr.Viewer.desktop"),
+            ]),
+        );
+        assert_eq!(user_apps.lookup_unless_choosing(&mime)?, None);
+        user_apps.enable_selector = false;
+        assert_eq!(
+            user_apps.lookup_unless_choosing(&mime)?,
+            Some(handler("emacsclient.desktop"))
+        );
+
+        Ok(())
+    }
 
     #[test]
-    fn wildcard_mimes() -> Result<()> {
+    fn list_jsonl() -> Result<()> {
+        let handlers = |names: &[&str]| -> VecDeque<Handler> {
+            names
+                .iter()
+                .map(|name| Handler::assume_valid(name.into()))
+                .collect()
+        };
+
         let mut user_apps = MimeApps::default();
-        user_apps.add_handler(
-            Mime::from_str("video/*").unwrap(),
-            Handler::assume_valid("mpv.desktop".into()),
+        user_apps.default_apps.insert(
+            Mime::from_str("text/plain")?,
+            handlers(&["helix.desktop"]),
         );
-        user_apps.add_handler(
-            Mime::from_str("video/webm").unwrap(),
-            Handler::assume_valid("brave.desktop".into()),
+        user_apps.default_apps.insert(
+            Mime::from_str("image/png")?,
+            handlers(&["imv.desktop", "gimp.desktop"]),
         );
+        user_apps
+            .added_associations
+            .insert(Mime::from_str("text/plain")?, handlers(&["nvim.desktop"]));
+        user_apps
+            .system_apps
+            .0
+            .insert(Mime::from_str("video/mp4")?, handlers(&["mpv.desktop"]));
+
+        let jsonl =
+            |sections: &[HandlerSource], count: bool| -> Result<String> {
+                let mut output = Vec::new();
+                user_apps.write_jsonl(
+                    &mut output,
+                    sections,
+                    count,
+                    &ListRows::default(),
+                )?;
+                Ok(String::from_utf8(output).unwrap())
+            };
 
         assert_eq!(
-            user_apps
-                .get_handler(&Mime::from_str("video/mp4")?)?
-                .to_string(),
-            "mpv.desktop"
+            jsonl(&HandlerSource::ALL, false)?,
+            r#"{"mime":"image/png","handlers":["imv.desktop","gimp.desktop"],"section":"default"}
+{"mime":"text/plain","handlers":["helix.desktop"],"section":"default"}
+{"mime":"text/plain","handlers":["nvim.desktop"],"section":"added"}
+{"mime":"video/mp4","handlers":["mpv.desktop"],"section":"system"}
+"#
         );
         assert_eq!(
-            user_apps
-                .get_handler(&Mime::from_str("video/asdf")?)?
-                .to_string(),
-            "mpv.desktop"
+            jsonl(&[HandlerSource::UserDefault], true)?,
+            r#"{"mime":"image/png","handlers":["imv.desktop","gimp.desktop"],"count":2,"section":"default"}
+{"mime":"text/plain","handlers":["helix.desktop"],"count":1,"section":"default"}
+"#
         );
 
+        let mut tsv = Vec::new();
+        user_apps.write_tsv(
+            &mut tsv,
+            &[HandlerSource::UserDefault, HandlerSource::System],
+            &ListRows::default(),
+        )?;
         assert_eq!(
-            user_apps
-                .get_handler(&Mime::from_str("video/webm")?)?
-                .to_string(),
-            "brave.desktop"
+            String::from_utf8(tsv).unwrap(),
+            "image/png\timv.desktop;gimp.desktop
+text/plain\thelix.desktop
+video/mp4\tmpv.desktop
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn sort_by_handler() -> Result<()> {
+        let mut user_apps = MimeApps::default();
+        for (mime, handler) in [
+            ("audio/mp3", "mpv.desktop"),
+            ("image/png", "imv.desktop"),
+            ("text/plain", "helix.desktop"),
+            ("video/mp4", "mpv.desktop"),
+        ]
+        .iter()
+        {
+            user_apps.set_handler(
+                Mime::from_str(mime)?,
+                Handler::assume_valid(handler.into()),
+            );
+        }
+
+        let mimes = |sort_by: SortBy| -> Result<Vec<String>> {
+            let rows = ListRows {
+                sort_by,
+                filter: None,
+            };
+            Ok(MimeAppsTable::new(&user_apps, false, false, &rows)?
+                .default_apps
+                .into_iter()
+                .map(|entry| entry.mime)
+                .collect())
+        };
+
+        assert_eq!(
+            mimes(SortBy::Mime)?,
+            ["audio/mp3", "image/png", "text/plain", "video/mp4"]
+        );
+        assert_eq!(
+            mimes(SortBy::Handler)?,
+            ["text/plain", "image/png", "audio/mp3", "video/mp4"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_sources() -> Result<()> {
+        let mut user_apps = MimeApps::from_paths(
+            Path::new("tests/nonexistent.list"),
+            &[PathBuf::from("tests")],
+            &Config::default(),
+        )?;
+        let mime = Mime::from_str("text/plain")?;
+        user_apps.set_handler(
+            mime.clone(),
+            Handler::assume_valid("emacsclient.desktop".into()),
+        );
+        user_apps.add_association(
+            mime,
+            Handler::assume_valid("nvim.desktop".into()),
+        );
+
+        let rows = ListRows {
+            sort_by: SortBy::Mime,
+            filter: Some(Regex::new("^text/plain$")?),
+        };
+        let sources = |table: &MimeAppsTable| {
+            HandlerSource::ALL
+                .iter()
+                .flat_map(|source| table.section(*source))
+                .map(|entry| entry.source)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            sources(&MimeAppsTable::new(&user_apps, false, true, &rows)?),
+            [Some("user"), Some("added"), Some("system")]
+        );
+        // Only shown with --all
+        assert_eq!(
+            sources(&MimeAppsTable::new(&user_apps, false, false, &rows)?),
+            [None, None, None]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn orphans() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let location = dir.path().join("mimeapps.list");
+        std::fs::copy("tests/mimeapps_orphans.list", &location)?;
+
+        let mut apps = MimeApps::from_paths(
+            &location,
+            &[PathBuf::from("tests")],
+            &Config::default(),
+        )?;
+        let before = apps.orphans(&read_if_exists(&location)?);
+
+        // `list --orphans --fix`
+        apps.remove_orphans(&before);
+        apps.save()?;
+        let raw_conf = read_if_exists(&location)?;
+        let after = apps.orphans(&raw_conf);
+
+        let orphan = |mime: &str, section, handlers: &[&str], missing: &str| {
+            OrphanEntry {
+                mime: mime.into(),
+                section,
+                handlers: handlers.iter().map(|h| h.to_string()).collect(),
+                missing: vec![missing.into()],
+            }
+        };
+        assert_eq!(
+            before,
+            [
+                orphan(
+                    "audio/mp3",
+                    "default",
+                    &["uninstalled.desktop", "cmus.desktop"],
+                    "uninstalled.desktop"
+                ),
+                orphan(
+                    "video/mp4",
+                    "default",
+                    &["uninstalled.desktop"],
+                    "uninstalled.desktop"
+                ),
+                orphan(
+                    "audio/flac",
+                    "added",
+                    &["cmus.desktop", "removed.desktop"],
+                    "removed.desktop"
+                ),
+            ]
+        );
+        assert_eq!(
+            before[0].display_handlers(),
+            "uninstalled.desktop (missing), cmus.desktop"
+        );
+
+        // Only the missing handlers are removed
+        assert!(after.is_empty());
+        assert!(raw_conf.contains("audio/mp3=cmus.desktop;"));
+        assert!(raw_conf.contains("audio/flac=cmus.desktop;"));
+        assert!(raw_conf.contains("text/plain=emacsclient.desktop;"));
+        assert!(!raw_conf.contains("video/mp4"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn forced_handler() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let location = dir.path().join("mimeapps.list");
+        let read = || {
+            MimeApps::from_paths(
+                &location,
+                &[PathBuf::from("tests")],
+                &Config::default(),
+            )
+        };
+        let plain = Mime::from_str("text/plain")?;
+
+        // `set --force` with a handler that is not installed yet
+        let mut apps = read()?;
+        apps.set_handler(
+            plain.clone(),
+            Handler::assume_valid("later.desktop".into()),
+        );
+        apps.save()?;
+
+        // Changing something else later keeps it
+        let mut apps = read()?;
+        apps.set_handler(
+            Mime::from_str("audio/mp3")?,
+            Handler::assume_valid("cmus.desktop".into()),
+        );
+        apps.save()?;
+
+        let apps = read()?;
+        let raw_conf = read_if_exists(&location)?;
+        let handler = apps.get_handler(&plain);
+
+        assert!(raw_conf.contains("text/plain=later.desktop;"));
+        assert!(raw_conf.contains("audio/mp3=cmus.desktop;"));
+        // It is only skipped when looking up a handler
+        assert_eq!(handler?.to_string(), "emacsclient.desktop");
+
+        Ok(())
+    }
+
+    #[test]
+    fn filter_rows() -> Result<()> {
+        let mut user_apps = MimeApps::default();
+        for (mime, handler) in [
+            ("audio/mp3", "mpv.desktop"),
+            ("image/png", "imv.desktop"),
+            ("video/mp4", "mpv.desktop"),
+            ("video/webm", "firefox.desktop"),
+        ]
+        .iter()
+        {
+            user_apps.set_handler(
+                Mime::from_str(mime)?,
+                Handler::assume_valid(handler.into()),
+            );
+        }
+
+        let mimes = |sort_by: SortBy, filter: &str| -> Result<Vec<String>> {
+            let rows = ListRows {
+                sort_by,
+                filter: Some(
+                    regex::RegexBuilder::new(filter)
+                        .case_insensitive(true)
+                        .build()?,
+                ),
+            };
+            Ok(MimeAppsTable::new(&user_apps, false, false, &rows)?
+                .default_apps
+                .into_iter()
+                .map(|entry| entry.mime)
+                .collect())
+        };
+
+        // Either the mimetype or a handler may match
+        assert_eq!(
+            mimes(SortBy::Mime, "^VIDEO/")?,
+            ["video/mp4", "video/webm"]
+        );
+        assert_eq!(mimes(SortBy::Mime, "MPV")?, ["audio/mp3", "video/mp4"]);
+        // Only handlers are matched when sorting by them
+        assert_eq!(mimes(SortBy::Handler, "^video/")?, Vec::<String>::new());
+        assert_eq!(mimes(SortBy::Handler, "^mpv")?, ["audio/mp3", "video/mp4"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_errors() -> Result<()> {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "handlers": [{ "exec": "true %u", "regexes": ["^handlr-ok:"] }],
+        }))?;
+        let user_apps = MimeApps {
+            regex_apps: RegexApps::from_config(&config),
+            ..Default::default()
+        };
+        let paths = ["handlr-missing:a", "handlr-ok:b", "handlr-gone:c"]
+            .iter()
+            .map(|path| UserPath::from_str(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        let error = user_apps
+            .open_paths(&paths, None, false, false, &ExecOptions::default())
+            .unwrap_err();
+        let ErrorKind::OpenErrors(errors) = &*error.kind else {
+            panic!("expected OpenErrors, got {:?}", error);
+        };
+        assert_eq!(errors.len(), 2);
+        assert_eq!(error.exit_code(), 1);
+        assert_eq!(
+            error.to_string(),
+            "no handlers found for 'x-scheme-handler/handlr-missing'\n\
+             no handlers found for 'x-scheme-handler/handlr-gone'"
+        );
+
+        // So does a single failure
+        let error = user_apps
+            .open_paths(
+                &paths[..2],
+                None,
+                false,
+                false,
+                &ExecOptions::default(),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            &*error.kind,
+            ErrorKind::OpenErrors(errors) if errors.len() == 1
+        ));
+        assert_eq!(error.exit_code(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn assign_handlers() -> Result<()> {
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "handlers": [{ "exec": "true %u", "regexes": ["^handlr-ok:"] }],
+        }))?;
+        let user_apps = MimeApps {
+            regex_apps: RegexApps::from_config(&config),
+            ..Default::default()
+        };
+        let paths = [
+            "handlr-a:1",
+            "handlr-cancel:1",
+            "handlr-ok:1",
+            "handlr-a:2",
+            "handlr-missing:1",
+            "handlr-cancel:2",
+            "handlr-missing:2",
+            "handlr-portal:1",
+        ]
+        .iter()
+        .map(|path| UserPath::from_str(path))
+        .collect::<Result<Vec<_>>>()?;
+
+        let mut asked = Vec::new();
+        let assignments = user_apps.assign_handlers(&paths, None, |mime| {
+            asked.push(mime.to_string());
+            match mime.subtype().as_str() {
+                "handlr-a" => {
+                    Ok(Some(Handler::assume_valid("a.desktop".into())))
+                }
+                "handlr-portal" => Ok(None),
+                "handlr-cancel" => Err(Error::from(ErrorKind::Cancelled)),
+                _ => Err(Error::from(NotFoundKind::Mime(mime.clone()))),
+            }
+        });
+
+        // Each mime is only looked up once, in the order it first appears
+        assert_eq!(
+            asked,
+            [
+                "x-scheme-handler/handlr-a",
+                "x-scheme-handler/handlr-cancel",
+                "x-scheme-handler/handlr-missing",
+                "x-scheme-handler/handlr-portal"
+            ]
+        );
+        // The rest of the paths keep their order
+        assert_eq!(
+            assignments
+                .assigned
+                .iter()
+                .map(|(path, handler)| (path.to_string(), handler.to_string()))
+                .collect::<Vec<_>>(),
+            [
+                ("handlr-a:1".to_owned(), "a.desktop".to_owned()),
+                ("handlr-ok:1".to_owned(), "true %u".to_owned()),
+                ("handlr-a:2".to_owned(), "a.desktop".to_owned()),
+            ]
+        );
+        assert_eq!(assignments.errors.len(), 1);
+        assert_eq!(
+            assignments.cancelled,
+            [Mime::from_str("x-scheme-handler/handlr-cancel")?]
+        );
+        // Left for the desktop portal to open
+        assert_eq!(
+            assignments
+                .chooser
+                .iter()
+                .map(|(mime, paths)| (mime.to_string(), paths.len()))
+                .collect::<Vec<_>>(),
+            [("x-scheme-handler/handlr-portal".to_owned(), 1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_groups() -> Result<()> {
+        let handler = |name: &str| {
+            GenericHandler::Handler(Handler::assume_valid(name.into()))
+        };
+        let groups = vec![
+            (handler("missing.desktop"), vec!["a.txt".to_owned()]),
+            (handler("imv.desktop"), vec!["b.png".to_owned()]),
+            (
+                GenericHandler::RegexHandler(RegexHandler::from_exec("mpv %u")),
+                vec!["c.mp4".to_owned(), "d.mp4".to_owned()],
+            ),
+        ];
+
+        let mut opened = Vec::new();
+        let errors = super::open_groups(groups, false, |handler, paths| {
+            if handler.to_string() == "missing.desktop" {
+                return Err(
+                    std::io::Error::from(std::io::ErrorKind::NotFound).into()
+                );
+            }
+            opened.push((handler.to_string(), paths));
+            Ok(Vec::new())
+        });
+        opened.sort();
+
+        // The other handlers still open their paths
+        assert_eq!(
+            opened,
+            [
+                ("imv.desktop".to_owned(), vec!["b.png".to_owned()]),
+                (
+                    "mpv %u".to_owned(),
+                    vec!["c.mp4".to_owned(), "d.mp4".to_owned()]
+                ),
+            ]
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .to_string()
+            .starts_with("could not open with missing.desktop: "));
+        assert_eq!(errors[0].exit_code(), 5);
+
+        Ok(())
+    }
+
+    #[test]
+    fn open_groups_wait() -> Result<()> {
+        let groups = vec![
+            (
+                GenericHandler::RegexHandler(RegexHandler::from_exec("fails")),
+                vec!["exit 3".to_owned()],
+            ),
+            (
+                GenericHandler::RegexHandler(RegexHandler::from_exec("works")),
+                vec!["exit 0".to_owned()],
+            ),
+        ];
+
+        // Every handler is started before any of them is waited for
+        let errors = super::open_groups(groups, true, |_, script| {
+            Ok(vec![std::process::Command::new("sh")
+                .arg("-c")
+                .args(script)
+                .spawn()?])
+        });
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .to_string()
+            .starts_with("could not open with fails: "));
+        assert_eq!(errors[0].exit_code(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn pick_candidates() -> Result<()> {
+        let handlers = |names: &[&str]| -> VecDeque<Handler> {
+            names
+                .iter()
+                .map(|name| Handler::assume_valid(name.into()))
+                .collect()
+        };
+        let config: Config = serde_json::from_value(serde_json::json!({
+            "handlers": [
+                { "exec": "vim %f", "regexes": [r"\.vim$"] },
+                { "exec": "freetube %u", "regexes": ["youtu"] },
+            ],
+        }))?;
+
+        let mut user_apps = MimeApps {
+            regex_apps: RegexApps::from_config(&config),
+            application_dirs: Some(vec![PathBuf::from("tests")]),
+            ..Default::default()
+        };
+        let plain = Mime::from_str("text/plain")?;
+        user_apps.default_apps.insert(
+            plain.clone(),
+            handlers(&["emacsclient.desktop", "nonexistent.desktop"]),
+        );
+        user_apps.added_associations.insert(
+            plain.clone(),
+            handlers(&["cmus.desktop", "emacsclient.desktop"]),
+        );
+        user_apps.system_apps.0.insert(
+            plain,
+            handlers(&["no-display.desktop", "working-dir.desktop"]),
+        );
+        user_apps.default_apps.insert(
+            Mime::from_str("text/*")?,
+            handlers(&["missing-program.desktop", "cmus.desktop"]),
+        );
+
+        let candidates = user_apps
+            .pick_candidates(&UserPath::from_str("tests/rust.vim")?)?
+            .into_iter()
+            .map(|handler| match handler {
+                GenericHandler::Handler(handler) => handler.to_string(),
+                GenericHandler::RegexHandler(handler) => {
+                    handler.exec().to_owned()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            candidates,
+            vec![
+                "vim %f",
+                "emacsclient.desktop",
+                // The wildcard's default comes before the system's handlers,
+                // like when looking up the handler
+                "missing-program.desktop",
+                // cmus.desktop and no-display.desktop have NoDisplay=true
+                "working-dir.desktop",
+            ]
         );
+        assert!(user_apps
+            .pick_candidates(&UserPath::from_str("handlr-missing:a")?)
+            .is_err());
 
         Ok(())
     }
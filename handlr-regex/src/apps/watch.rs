@@ -0,0 +1,172 @@
+use crate::{
+    apps::{
+        user::{group_path, open_groups, Assignments},
+        MimeApps,
+    },
+    ExecOptions, GenericHandler, Result, UserPath,
+};
+use notify::{RecursiveMode, Watcher};
+use std::{collections::HashMap, process::Child, sync::mpsc, time::Duration};
+
+/// How long to let mimeapps.list settle after it changes before reading it,
+/// since it may be written in more than one step
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// Open `paths`, then open them again whenever mimeapps.list changes
+/// which handler any of them has
+///
+/// Only paths whose handler changed are opened again, with their new handler.
+/// Cancelling the selector keeps the handler the paths had before.
+pub fn watch(paths: &[UserPath], options: &ExecOptions) -> Result<()> {
+    let mimeapps = MimeApps::path()?;
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+    // mimeapps.list is replaced rather than written to, so its directory is watched
+    watcher.watch(
+        mimeapps.parent().unwrap_or(&mimeapps),
+        RecursiveMode::NonRecursive,
+    )?;
+
+    let mut handlers = HashMap::new();
+    reopen(
+        &MimeApps::read()?,
+        paths,
+        &mut handlers,
+        |handler, paths| handler.open(paths, false, None, options),
+    );
+
+    while let Ok(event) = rx.recv() {
+        match event {
+            Ok(event) if event.paths.contains(&mimeapps) => {}
+            Ok(_) => continue,
+            Err(e) => {
+                tracing::warn!("could not watch mimeapps.list: {}", e);
+                continue;
+            }
+        }
+        std::thread::sleep(SETTLE_TIME);
+        rx.try_iter().for_each(drop);
+
+        match MimeApps::read() {
+            Ok(apps) => {
+                tracing::info!("mimeapps.list changed, checking handlers");
+                reopen(&apps, paths, &mut handlers, |handler, paths| {
+                    handler.open(paths, false, None, options)
+                });
+            }
+            Err(e) => tracing::warn!(
+                "could not read mimeapps.list, keeping the old handlers: {}",
+                e
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Open each path with its handler from `apps` if that is not its handler in `handlers`,
+/// then remember the new handler
+fn reopen(
+    apps: &MimeApps,
+    paths: &[UserPath],
+    handlers: &mut HashMap<String, GenericHandler>,
+    open: impl FnMut(&GenericHandler, Vec<String>) -> Result<Vec<Child>>,
+) {
+    let Assignments {
+        assigned,
+        errors,
+        cancelled,
+        chooser,
+    } = apps.assign(paths, None);
+    for e in errors {
+        tracing::warn!("{}", e);
+    }
+    for mime in cancelled {
+        tracing::info!("selection cancelled for {}, keeping its handler", mime);
+    }
+    // Which would ask again on every change
+    for (mime, _) in chooser {
+        tracing::info!(
+            "not opening {} with the desktop portal while watching",
+            mime
+        );
+    }
+
+    let mut changed = Vec::new();
+    for (path, handler) in assigned {
+        let path = path.to_string();
+        if handlers.get(&path) != Some(&handler) {
+            handlers.insert(path.clone(), handler.clone());
+            group_path(&mut changed, handler, path);
+        }
+    }
+
+    // Waiting would stop handlr from watching for the next change
+    for e in open_groups(changed, false, open) {
+        tracing::warn!("{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Handler};
+    use mime::Mime;
+    use std::{
+        path::{Path, PathBuf},
+        str::FromStr,
+    };
+
+    #[test]
+    fn reopen_changed() -> Result<()> {
+        let mut apps = MimeApps::from_paths(
+            Path::new("tests/nonexistent.list"),
+            &[PathBuf::from("tests")],
+            &Config::default(),
+        )?;
+        let mime = Mime::from_str("x-scheme-handler/handlr-watch")?;
+        let paths = ["handlr-watch:a", "handlr-watch:b", "handlr-other:c"]
+            .iter()
+            .map(|path| UserPath::from_str(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut handlers = HashMap::new();
+        let mut reopened = |apps: &MimeApps| {
+            let mut opened = Vec::new();
+            reopen(apps, &paths, &mut handlers, |handler, paths| {
+                opened.push((handler.to_string(), paths));
+                Ok(Vec::new())
+            });
+            opened
+        };
+
+        apps.set_handler(
+            mime.clone(),
+            Handler::assume_valid("cmus.desktop".into()),
+        );
+        assert_eq!(
+            reopened(&apps),
+            [(
+                "cmus.desktop".to_owned(),
+                vec!["handlr-watch:a".to_owned(), "handlr-watch:b".to_owned()]
+            )]
+        );
+
+        // Nothing is opened again until the handler changes
+        assert!(reopened(&apps).is_empty());
+
+        apps.set_handler(
+            mime,
+            Handler::assume_valid("emacsclient.desktop".into()),
+        );
+        assert_eq!(
+            reopened(&apps),
+            [(
+                "emacsclient.desktop".to_owned(),
+                vec!["handlr-watch:a".to_owned(), "handlr-watch:b".to_owned()]
+            )]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use crate::Result;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
+
+/// Keeps track of which associations were created by expanding a wildcard
+/// when `expand_wildcards` is enabled, keyed by the wildcard they came from
+///
+/// Stored in $XDG_CACHE_HOME/handlr/wildcard_expansions.json,
+/// since mimeapps.list has no way to mark where an association came from.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WildcardExpansions(BTreeMap<String, BTreeSet<String>>);
+
+impl WildcardExpansions {
+    fn path() -> Result<PathBuf> {
+        Ok(xdg::BaseDirectories::with_prefix("handlr")?
+            .place_cache_file("wildcard_expansions.json")?)
+    }
+
+    /// Load recorded expansions, starting fresh if there are none
+    pub fn load() -> Self {
+        let Some(path) = xdg::BaseDirectories::with_prefix("handlr")
+            .ok()
+            .and_then(|dirs| dirs.find_cache_file("wildcard_expansions.json"))
+        else {
+            return Self::default();
+        };
+
+        std::fs::read(&path)
+            .map_err(crate::Error::from)
+            .and_then(|raw| Ok(serde_json::from_slice(&raw)?))
+            .unwrap_or_else(|e| {
+                tracing::warn!(
+                    "could not read wildcard expansions from {}: {}",
+                    path.display(),
+                    e
+                );
+                Self::default()
+            })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        std::fs::write(Self::path()?, serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+
+    /// Record that `mimes` were set by expanding `pattern`
+    ///
+    /// Mimes recorded under a different pattern now belong to this one instead.
+    pub fn record<'a>(
+        &mut self,
+        pattern: &Mime,
+        mimes: impl IntoIterator<Item = &'a Mime>,
+    ) {
+        let mimes = mimes
+            .into_iter()
+            .map(|mime| mime.essence_str().to_owned())
+            .collect::<BTreeSet<_>>();
+
+        for (other, other_mimes) in self.0.iter_mut() {
+            if other == pattern.essence_str() {
+                continue;
+            }
+
+            let overlap = other_mimes.intersection(&mimes).count();
+            if overlap > 0 {
+                tracing::warn!(
+                    "{} associations previously expanded from '{}' now come from '{}'",
+                    overlap,
+                    other,
+                    pattern
+                );
+                other_mimes.retain(|mime| !mimes.contains(mime));
+            }
+        }
+
+        self.0.retain(|_, mimes| !mimes.is_empty());
+        self.0.insert(pattern.essence_str().to_owned(), mimes);
+    }
+
+    /// Stop tracking a mime that has been manually changed
+    pub fn forget(&mut self, mime: &Mime) {
+        self.0.values_mut().for_each(|mimes| {
+            mimes.remove(mime.essence_str());
+        });
+        self.0.retain(|_, mimes| !mimes.is_empty());
+    }
+
+    /// Stop tracking a pattern, returning the mimes that were expanded from it
+    pub fn take(&mut self, pattern: &Mime) -> Vec<Mime> {
+        self.0
+            .remove(pattern.essence_str())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|mime| mime.parse().ok())
+            .collect()
+    }
+
+    /// Get the pattern a mime was expanded from, if any
+    pub fn origin(&self, mime: &Mime) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(_, mimes)| mimes.contains(mime.essence_str()))
+            .map(|(pattern, _)| pattern.as_str())
+    }
+}
+
+/// Whether the given mime contains wildcards
+pub fn is_wildcard(mime: &Mime) -> bool {
+    mime.essence_str().contains('*')
+}
+
+/// Whether `mime` is matched by `pattern`, where `*` matches any number of characters
+pub fn wildcard_matches(pattern: &str, mime: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let Some(mut rest) = mime.strip_prefix(parts.next().unwrap_or_default())
+    else {
+        return false;
+    };
+
+    let parts = parts.collect::<Vec<_>>();
+    match parts.split_last() {
+        None => rest.is_empty(),
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            rest.len() >= last.len() && rest.ends_with(last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching() {
+        assert!(wildcard_matches("image/*", "image/png"));
+        assert!(wildcard_matches("*/png", "image/png"));
+        assert!(wildcard_matches(
+            "application/vnd.oasis.opendocument.*",
+            "application/vnd.oasis.opendocument.text"
+        ));
+        assert!(wildcard_matches("image/png", "image/png"));
+        assert!(!wildcard_matches("image/*", "video/mp4"));
+        assert!(!wildcard_matches("image/png", "image/pngx"));
+        assert!(!wildcard_matches("*/png", "image/apng-extra"));
+    }
+}
@@ -1,5 +1,9 @@
-use crate::common::{Handler, MimeOrExtension, UserPath};
-use clap::Parser;
+use crate::apps::SortBy;
+use crate::common::{
+    GenericHandler, Handler, MimeExtOrPath, MimeOrExtension, UserPath,
+};
+use clap::{Parser, Subcommand};
+use regex::{Regex, RegexBuilder};
 
 /// A better xdg-utils
 ///
@@ -12,6 +16,37 @@ use clap::Parser;
 #[derive(Parser)]
 #[clap(disable_help_subcommand = true)]
 #[clap(version, about)]
+#[clap(after_long_help = "Exit codes:
+  0  Success
+  1  Any other error
+  2  No handler, desktop file, or path found
+  3  Invalid desktop entry
+  4  Selection cancelled
+  5  I/O error
+
+When using `launch --wait` or `open --wait`, the handler's own exit code is used instead.")]
+pub struct Cli {
+    #[clap(long, global = true)]
+    /// Never show errors as desktop notifications
+    ///
+    /// Errors are shown as notifications when stdout is not a terminal,
+    /// i.e. when handlr is run by a file manager. With this they are only printed to stderr.
+    pub quiet: bool,
+    #[clap(long, global = true, verbatim_doc_comment)]
+    /// Print errors to stderr as json instead
+    ///
+    /// Errors are printed on one line in the form:
+    ///
+    /// {"error": "no handlers found for 'text/x-foo'", "kind": "not_found"}
+    pub errors_json: bool,
+    #[clap(subcommand)]
+    #[allow(missing_docs)]
+    pub command: Cmd,
+}
+
+/// The subcommands of handlr
+#[deny(missing_docs)]
+#[derive(Subcommand)]
 pub enum Cmd {
     #[clap(verbatim_doc_comment)]
     /// List default apps and the associated handlers
@@ -19,6 +54,9 @@ pub enum Cmd {
     /// Output is formatted as a table with two columns.
     /// The left column shows mimetypes and the right column shows the handlers
     ///
+    /// Mimetypes set by expanding a wildcard because of `expand_wildcards` are marked with the wildcard,
+    /// i.e. `image/png (from image/*)`, and have an `expanded_from` key in `--json` output.
+    ///
     /// Currently does not support regex handlers.
     ///
     /// When using `--json`, output will be in the form:
@@ -48,29 +86,219 @@ pub enum Cmd {
     ///   "system_apps": [ ... ]
     /// }
     ///
-    /// Where each top-level key has an array with the same scheme as the normal `--json` output
+    /// Where each top-level key has an array with the same scheme as the normal `--json` output,
+    /// except that each entry has `source` and `origin` keys with where it comes from.
+    /// Without `--json`, `--all` adds source and origin columns instead.
+    /// The source is `user`, `added` or `system`, and the origin is
+    /// either the path of the user's mimeapps.list or "installed desktop entries".
+    ///
+    /// When using `--added`, only added associations are shown,
+    /// which are the handlers offered by "open with" menus in addition to the defaults.
+    /// Its `--json` output has the same scheme as the normal `--json` output.
+    ///
+    /// When using `--system-only`, only the associations from installed desktop entries are shown,
+    /// ignoring mimeapps.list. Its `--json` output has the same scheme as the normal `--json` output.
+    ///
+    /// When using `--count`, a column with the number of handlers for each mimetype is added,
+    /// and each entry in `--json` output gets a `count` key.
+    ///
+    /// When using `--jsonl`, each mimetype is printed as a json object on its own line,
+    /// in the same order as the tables, with the same scheme regardless of other options:
+    ///
+    /// {"mime":"text/*","handlers":["Helix.desktop"],"section":"default"}
+    ///
+    /// Where "section" is "default", "added", or "system".
+    ///
+    /// When using `--tsv`, each mimetype is printed on its own line without a header,
+    /// followed by a tab and its handlers separated by `;` like in mimeapps.list, for tools like awk and cut,
+    /// i.e. `text/plain`, a tab, then `helix.desktop;nvim.desktop`.
+    ///
+    /// When using `--sort-by handler`, mimetypes are sorted by their first handler instead of alphabetically,
+    /// so that mimetypes opened with the same app are grouped together. This applies to every output format.
+    ///
+    /// When using `--filter`, only mimetypes where the given regex matches the mimetype or one of its handlers are shown,
+    /// ignoring case, i.e. `--filter '^image/'` or `--filter mpv`.
+    /// With `--sort-by handler`, it is only matched against the handlers.
+    ///
+    /// When using `--orphans`, only associations in mimeapps.list with handlers that are not installed are shown,
+    /// marking each missing handler. Add `--fix` to remove just the missing handlers and save,
+    /// keeping the rest of each association.
+    ///
+    /// When using `--category`, the installed apps whose desktop entries list the given category
+    /// are shown instead, with their names, ignoring case, i.e. `--category office`.
+    /// This includes apps without any associations. `--jsonl` prints each app as a json object on its own line,
+    /// and `--tsv` prints its desktop file and name separated by a tab.
     List {
         #[clap(long)]
         /// Output handler info as json
         json: bool,
+        #[clap(long, conflicts_with = "json")]
+        /// Output handler info as json lines, one mimetype per line
+        jsonl: bool,
+        #[clap(long, conflicts_with_all = ["json", "jsonl", "all", "count"])]
+        /// Output each mimetype and its handlers separated by a tab, one mimetype per line
+        tsv: bool,
         #[clap(long, short)]
         /// Expand wildcards in mimetypes and show global defaults
         all: bool,
+        #[clap(long, conflicts_with = "all")]
+        /// Only show added associations
+        added: bool,
+        #[clap(long, conflicts_with_all = ["all", "added"])]
+        /// Only show associations from installed desktop entries
+        system_only: bool,
+        #[clap(long)]
+        /// Show how many handlers each mimetype has
+        count: bool,
+        #[clap(long, value_enum, default_value_t)]
+        /// What to sort mimetypes by
+        sort_by: SortBy,
+        #[clap(long, value_name = "REGEX", value_parser = parse_filter)]
+        /// Only show mimetypes where the regex matches the mimetype or a handler
+        filter: Option<Regex>,
+        #[clap(long, conflicts_with_all = ["jsonl", "tsv", "all", "added", "system_only", "count", "filter"])]
+        /// Only show associations with handlers that are not installed
+        orphans: bool,
+        #[clap(long, requires = "orphans")]
+        /// Remove handlers that are not installed from associations shown by `--orphans`
+        fix: bool,
+        #[clap(long, value_name = "NAME", conflicts_with_all = ["all", "added", "system_only", "count", "filter", "orphans"])]
+        /// Only show installed apps in a desktop entry category, e.g. Office or Graphics
+        category: Option<String>,
     },
 
     /// Open a path/URL with its default handler
     ///
     /// Unlike xdg-open and similar resource openers, multiple paths/URLs may be supplied.
     ///
+    /// Paths that do not exist are expanded as glob patterns, i.e. `*.pdf`,
+    /// for when handlr is not run from a shell that does so itself.
+    ///
     /// If multiple handlers are set and `enable_selector` is set to true,
     /// you will be prompted to select one using `selector` from ~/.config/handlr/handlr.toml.
     /// Otherwise, the default handler will be opened.
+    ///
+    /// When using `--action`, the given desktop entry action is run instead of the handler's main command.
+    /// Regex handlers are skipped since they do not have any actions.
+    ///
+    /// When using `--with`, all paths are opened with the given handler instead,
+    /// without changing any associations.
+    /// It can be a desktop file or a command using desktop entry field codes like a regex handler's `exec`.
+    ///
+    /// When using `--recursive`, directories are replaced by the files in them, in order of their paths,
+    /// and each file is opened with its own handler, with each handler getting all of its files at once.
+    /// `--include` and `--exclude` may be given multiple times.
+    /// More than 100 files are only opened with `--yes`.
+    ///
+    /// If some paths cannot be opened, the rest are still opened and every error is reported at the end.
+    /// The exit code is then 1, or when using `--wait`, that of a handler that failed.
+    ///
+    /// When using `--confirm`, the command for each path is shown and the path is only opened
+    /// if you answer yes. Nothing is asked when handlr is not run in a terminal.
+    ///
+    /// When using `--split`, handlers that can open several paths at once, i.e. with `%F` or `%U`,
+    /// are run once per path instead, for apps that misbehave when given several.
+    ///
+    /// Handlers whose desktop entries have `PrefersNonDefaultGPU=true` are run with `gpu_offload_env`
+    /// from handlr.toml set, which is `DRI_PRIME=1` by default, unless `--no-gpu-offload` is used.
+    ///
+    /// When using `--env KEY=VALUE`, handlers are run with that environment variable set,
+    /// taking precedence over `env_overrides` in handlr.toml.
+    ///
+    /// When using `--wait`, handlr waits for every handler to exit,
+    /// and exits with the exit code of one that failed, if any.
+    /// When using `--print-pid`, the pid of each handler started is printed, one per line.
+    /// Neither applies to apps started over D-Bus, so they are run with their command instead.
+    ///
+    /// When using `--portal`, or if `use_portal_chooser` is set in handlr.toml,
+    /// paths are opened with the desktop portal's "Open With" dialog wherever the selector would be used,
+    /// and when no handler is found. This needs handlr to be built with the `portal` feature.
+    /// The selector is used instead if the portal cannot be used, and neither is used with `--action`.
+    ///
+    /// Files that do not exist are reported all at once without opening anything,
+    /// unless `--force` is used. URLs are always opened.
     Open {
+        #[clap(long)]
+        /// Desktop entry action to open the paths with
+        action: Option<String>,
+        #[clap(long, value_name = "HANDLER")]
+        /// Desktop file or command to open the paths with instead
+        with: Option<GenericHandler>,
+        #[clap(long, short)]
+        /// Open the files in directories instead of the directories themselves
+        recursive: bool,
+        #[clap(long, requires = "recursive")]
+        /// How many directories deep to go, where 1 is only the directory's own files
+        max_depth: Option<usize>,
+        #[clap(long, value_name = "GLOB", requires = "recursive")]
+        /// Only open files in directories whose relative path matches this glob
+        include: Vec<glob::Pattern>,
+        #[clap(long, value_name = "GLOB", requires = "recursive")]
+        /// Skip files in directories whose relative path matches this glob
+        exclude: Vec<glob::Pattern>,
+        #[clap(long, short)]
+        /// Open more files than the safety limit for `--recursive`
+        yes: bool,
+        #[clap(long, conflicts_with = "with")]
+        /// Ask before opening each path with its handler
+        confirm: bool,
+        #[clap(long)]
+        /// Print each command to stderr before running it
+        log_cmd: bool,
+        #[clap(long)]
+        /// Never start a terminal emulator for handlers that need a terminal
+        no_term_wrap: bool,
+        #[clap(long)]
+        /// Do not run handlers that prefer it on the non-default GPU
+        no_gpu_offload: bool,
+        #[clap(long)]
+        /// Run each handler once per path, even if it can open several at once
+        split: bool,
+        #[clap(long, value_name = "KEY=VALUE", value_parser = parse_env)]
+        /// Set an environment variable for the handlers, may be given multiple times
+        env: Vec<(String, String)>,
+        #[clap(long)]
+        /// Wait for the handlers to exit
+        wait: bool,
+        #[clap(long)]
+        /// Print the pid of each handler that is started
+        print_pid: bool,
+        #[clap(long)]
+        /// Open paths with the desktop portal's dialog instead of the selector
+        portal: bool,
+        #[clap(long)]
+        /// Open paths even if they do not exist, i.e. for editors that create them
+        force: bool,
         #[clap(required = true)]
         /// Paths/URLs to open
         paths: Vec<UserPath>,
     },
 
+    /// Pick any handler that could open a path/URL with the selector and open it
+    ///
+    /// Every handler that could open the path is offered: matching regex handlers,
+    /// then the handlers for its mimetype from default apps, added associations and system apps.
+    /// The selector is used even if `enable_selector` is false, and nothing is changed unless `--save` is used.
+    ///
+    /// Cancelling the selector exits quietly with exit code 0.
+    ///
+    /// When using `--portal`, the path is opened with the desktop portal's "Open With" dialog instead,
+    /// like with `use_portal_chooser` in handlr.toml. See `handlr open --help`.
+    /// `--save` then saves the application picked there, if the portal says which one it was.
+    Pick {
+        #[clap(long)]
+        /// Also make the picked handler the default for the path's mimetype
+        save: bool,
+        #[clap(long)]
+        /// Selector command to use instead of `selector` from ~/.config/handlr/handlr.toml
+        selector: Option<String>,
+        #[clap(long, conflicts_with = "selector")]
+        /// Open the path with the desktop portal's dialog instead of the selector
+        portal: bool,
+        /// Path/URL to open
+        path: UserPath,
+    },
+
     /// Set the default handler for mime/extension
     ///
     /// Overwrites currently set handler(s) for the given mime/extension.
@@ -79,12 +307,54 @@ pub enum Cmd {
     ///
     /// File extensions are converted into their respective mimetypes in mimeapps.list.
     ///
+    /// If `expand_wildcards` is set to true in ~/.config/handlr/handlr.toml,
+    /// wildcards are expanded and every matching known mimetype is set individually instead.
+    ///
+    /// If the desktop file cannot be found, a similarly named installed one is suggested
+    /// and nothing is changed, unless `--force` is used.
+    ///
+    /// The handler can also be an absolute path to a desktop file outside of the usual
+    /// applications directories, which is written to mimeapps.list as it is.
+    /// Since other programs only understand desktop file names, `--install` instead links it
+    /// into ~/.local/share/applications and sets it by name. Relative paths also work with `--install`.
+    ///
+    /// With `--glob`, wildcards are expanded this way even if `expand_wildcards` is not set,
+    /// and each mimetype that is set is listed. Adding `--dry-run` only lists them.
+    ///
+    /// If more than `expand_confirm_threshold` mimetypes would change, a summary is shown
+    /// and confirmation is asked for, unless `--yes` is used.
+    /// Without a terminal to ask in, nothing is changed unless `--yes` is used.
+    ///
+    /// With `--sync-equivalents`, the handler is also set for every mimetype in the same set
+    /// of `equivalent_mimes` in ~/.config/handlr/handlr.toml.
+    ///
     /// Currently does not support regex handlers.
     Set {
+        #[clap(long)]
+        /// Set the handler even if its desktop file cannot be found
+        force: bool,
+        #[clap(long)]
+        /// Also set the handler for equivalent mimetypes
+        sync_equivalents: bool,
+        #[clap(long, conflicts_with = "dry_run")]
+        /// Link a desktop file given by its path into ~/.local/share/applications
+        install: bool,
+        #[clap(long)]
+        /// Set every known mimetype a wildcard matches one by one
+        glob: bool,
+        #[clap(long, requires = "glob")]
+        /// List the mimetypes that would be set without changing anything
+        dry_run: bool,
+        #[clap(long, short)]
+        /// Change more than `expand_confirm_threshold` mimetypes without asking
+        yes: bool,
+        #[clap(long)]
+        /// Do not run `post_set_hooks` from handlr.toml
+        no_hooks: bool,
         /// Mimetype or file extension to operate on.
         mime: MimeOrExtension,
         /// Desktop file of handler program
-        handler: Handler,
+        handler: String,
     },
 
     /// Unset the default handler for mime/extension
@@ -93,8 +363,17 @@ pub enum Cmd {
     ///
     /// If multiple default handlers are set, both will be removed.
     ///
+    /// Unsetting a wildcard that was expanded because of `expand_wildcards` also unsets
+    /// every mimetype that was set by that expansion, except for ones that have been set manually since.
+    ///
     /// Currently does not support regex handlers.
+    ///
+    /// Like `set`, `add` and `remove`, this runs `post_set_hooks` from handlr.toml afterwards,
+    /// unless `--no-hooks` is used.
     Unset {
+        #[clap(long)]
+        /// Do not run `post_set_hooks` from handlr.toml
+        no_hooks: bool,
         /// Mimetype or file extension to unset the default handler of
         mime: MimeOrExtension,
     },
@@ -106,9 +385,46 @@ pub enum Cmd {
     /// If multiple handlers are set and `enable_selector` is set to true,
     /// you will be prompted to select one using `selector` from ~/.config/handlr/handlr.toml.
     /// Otherwise, the default handler will be opened.
+    ///
+    /// When using `--wait`, handlr will block until the handler exits and use its exit code as its own.
+    ///
+    /// When using `--detach`, the handler is started in a new session without handlr's terminal,
+    /// so it survives the terminal being closed. Terminal programs get a terminal emulator of their own.
+    ///
+    /// When using `--action`, the given desktop entry action is run instead of the handler's main command.
+    ///
+    /// When using `--with`, the given handler is launched instead, without changing any associations.
+    /// It can be a desktop file or a command using desktop entry field codes like a regex handler's `exec`.
+    /// No mimetype is needed then, so every argument is passed to the handler.
     Launch {
-        /// Mimetype or file extension to launch the handler of
-        mime: MimeOrExtension,
+        #[clap(long, value_name = "HANDLER")]
+        /// Desktop file or command to launch instead
+        with: Option<GenericHandler>,
+        #[clap(long)]
+        /// Wait for the handler to exit
+        wait: bool,
+        #[clap(long, conflicts_with = "wait")]
+        /// Detach the handler from the terminal so it keeps running after it is closed
+        detach: bool,
+        #[clap(long)]
+        /// Never start a terminal emulator for handlers that need a terminal
+        no_term_wrap: bool,
+        #[clap(long)]
+        /// Do not run the handler on the non-default GPU even if it prefers it
+        no_gpu_offload: bool,
+        #[clap(long, value_name = "KEY=VALUE", value_parser = parse_env)]
+        /// Set an environment variable for the handler, may be given multiple times
+        env: Vec<(String, String)>,
+        #[clap(long)]
+        /// Print the pid of the handler once it is started
+        print_pid: bool,
+        #[clap(long)]
+        /// Desktop entry action to launch the handler with
+        action: Option<String>,
+        #[clap(value_name = "MIME", required_unless_present = "with")]
+        /// Mimetype or file extension to launch the handler of,
+        /// or the first argument to pass when using `--with`
+        mime: Option<String>,
         /// Arguments to pass to handler program
         args: Vec<UserPath>,
     },
@@ -116,6 +432,9 @@ pub enum Cmd {
     #[clap(verbatim_doc_comment)]
     /// Get handler for this mime/extension
     ///
+    /// A path or URL may also be given, in which case its mimetype is used.
+    /// Files must exist to be treated as paths, otherwise the argument is treated as a mimetype.
+    ///
     /// If multiple handlers are set and `enable_selector` is set to true,
     /// you will be prompted to select one using `selector` from ~/.config/handlr/handlr.toml.
     /// Otherwise, only the default handler will be printed.
@@ -127,16 +446,85 @@ pub enum Cmd {
     /// {
     ///   "cmd": "helix",
     ///   "handler": "helix.desktop",
-    ///   "name": "Helix"
+    ///   "name": "Helix",
+    ///   "env": {},
+    ///   "icon": "helix"
     /// }
     ///
+    /// The "icon" key is left out if the handler has no icon.
+    /// "env" has the variables the handler is run with from `env_overrides` in handlr.toml.
+    ///
     /// Note that when handlr is not being directly output to a terminal, and the handler is a terminal program,
     /// the "cmd" key in the json output will include the command of the `x-scheme-handler/terminal` handler.
+    ///
+    /// When using `--installed-only`, the first handler whose desktop file and program
+    /// are both installed is shown instead, going through every handler in order,
+    /// from default apps to added associations to system apps.
+    /// The selector is not used in that case.
+    ///
+    /// When using `--verbose`, each source and mimetype checked to find the handler
+    /// is shown instead, in order, with the handler found in the last one.
+    /// Combined with `--json`, this is an array of objects with
+    /// `step`, `mime`, `found` and `handler` keys.
+    ///
+    /// When using `--actions`, the handler's desktop entry actions are listed instead.
+    /// Combined with `--json`, output is in the form:
+    ///
+    /// [
+    ///   {
+    ///     "action": "new-window",
+    ///     "name": "New Window",
+    ///     "exec": "emacsclient --alternate-editor= --create-frame %F"
+    ///   },
+    ///   ...
+    /// ]
     Get {
         #[clap(long)]
         /// Output handler info as json
         json: bool,
-        /// Mimetype to get the handler of
+        #[clap(long)]
+        /// List the handler's desktop entry actions
+        actions: bool,
+        #[clap(long)]
+        /// Skip handlers whose desktop file or program is not installed
+        installed_only: bool,
+        #[clap(long, short, conflicts_with_all = ["actions", "installed_only"])]
+        /// Show every source and mimetype checked to find the handler
+        verbose: bool,
+        /// Mimetype, extension, path or URL to get the handler of
+        mime: MimeExtOrPath,
+    },
+
+    #[clap(verbatim_doc_comment)]
+    /// Show every handler that could open a mime/extension and where it comes from
+    ///
+    /// This lists the handlers from default apps, added associations and system apps
+    /// in the order they are tried, including the mimetype it is an alias of,
+    /// its `equivalent_mimes` and its wildcard, e.g. `text/*` for `text/plain`.
+    /// The regex handlers that match a file with the mimetype's usual extension
+    /// and `fallback_handler` from handlr.toml are listed last.
+    ///
+    /// When using `--json`, output is in the form:
+    ///
+    /// {
+    ///   "mime": "text/plain",
+    ///   "handler": "helix.desktop",
+    ///   "source": "default",
+    ///   "entries": [
+    ///     {
+    ///       "source": "default apps",
+    ///       "matched": "text/plain",
+    ///       "handlers": ["helix.desktop"]
+    ///     },
+    ///     ...
+    ///   ]
+    /// }
+    Info {
+        #[clap(long)]
+        /// Output as json
+        json: bool,
+        #[clap(long)]
+        /// Mimetype or file extension to show the handlers of
         mime: MimeOrExtension,
     },
 
@@ -146,11 +534,33 @@ pub enum Cmd {
     ///
     /// This subcommand adds secondary handlers that coexist with the default
     /// and does not overwrite existing handlers.
+    ///
+    /// When using `--added`, the handler is added to the added associations instead,
+    /// which "open with" menus offer without changing the default.
+    /// Handlers that are already associated with the mimetype are not added again.
+    ///
+    /// If the desktop file cannot be found, a similarly named installed one is suggested
+    /// and nothing is changed, unless `--force` is used.
+    ///
+    /// As with `set`, confirmation is asked for before changing more than
+    /// `expand_confirm_threshold` mimetypes, unless `--yes` is used.
     Add {
+        #[clap(long)]
+        /// Add to the added associations instead of the default apps
+        added: bool,
+        #[clap(long)]
+        /// Add the handler even if its desktop file cannot be found
+        force: bool,
+        #[clap(long, short)]
+        /// Change more than `expand_confirm_threshold` mimetypes without asking
+        yes: bool,
+        #[clap(long)]
+        /// Do not run `post_set_hooks` from handlr.toml
+        no_hooks: bool,
         /// Mimetype to add handler to
         mime: MimeOrExtension,
         /// Desktop file of handler program
-        handler: Handler,
+        handler: String,
     },
 
     /// Remove a given handler from a given mime/extension
@@ -159,13 +569,60 @@ pub enum Cmd {
     ///
     /// Wildcards cannot be used unless removing handlers from mimetypes
     /// that already have wildcards.
+    ///
+    /// When using `--added`, the handler is removed from the added associations instead.
+    ///
+    /// A mimetype is still opened with a handler that is set for its wildcard,
+    /// so when using `--wildcard`, the handler is removed from both,
+    /// e.g. from `video/mp4` and `video/*`.
     Remove {
+        #[clap(long)]
+        /// Remove from the added associations instead of the default apps
+        added: bool,
+        #[clap(long, conflicts_with = "added")]
+        /// Also remove the handler from the wildcard for the mimetype, e.g. `video/*` for `video/mp4`
+        wildcard: bool,
+        #[clap(long)]
+        /// Do not run `post_set_hooks` from handlr.toml
+        no_hooks: bool,
         /// Mimetype to remove handler from
         mime: MimeOrExtension,
         /// Desktop file of handler program to remove
         handler: Handler,
     },
 
+    /// Remove a handler from every mimetype in mimeapps.list
+    ///
+    /// Both the default apps and the added associations are cleaned up,
+    /// and mimetypes that have no handlers left are removed.
+    /// This works even if the handler's desktop file is no longer installed,
+    /// i.e. after uninstalling it.
+    ///
+    /// Each changed mimetype is shown along with its section and remaining handlers.
+    Purge {
+        /// Desktop file of handler program to remove
+        handler: String,
+        #[clap(long)]
+        /// Show what would be removed without changing anything
+        dry_run: bool,
+    },
+
+    /// Replace a handler with another in every mimetype in mimeapps.list
+    ///
+    /// Useful when an application's desktop file is renamed,
+    /// i.e. from firefox.desktop to org.mozilla.firefox.desktop when switching to Flatpak.
+    /// Both the default apps and the added associations are updated,
+    /// even if the old desktop file is no longer installed.
+    RenameHandler {
+        /// Desktop file of the handler to replace
+        old: String,
+        /// Desktop file of the handler to replace it with
+        new: String,
+        #[clap(long)]
+        /// Do not check that the new desktop file exists
+        force: bool,
+    },
+
     #[clap(verbatim_doc_comment)]
     /// Get the mimetype of a given file/URL
     ///
@@ -184,6 +641,18 @@ pub enum Cmd {
     ///   },
     /// ...
     /// ]
+    ///
+    /// When using `--jsonl`, each path is printed as soon as its mimetype is known,
+    /// as a json object on its own line:
+    ///
+    /// {"path":"README.md","mime":"text/markdown"}
+    ///
+    /// With `--hypothetical`, each path is only looked at by name, so it does not need to exist.
+    /// Names that could be more than one mimetype list each of them instead of failing:
+    ///
+    /// {"path":"a.v2.tar.zst","mimes":["application/x-zstd-compressed-tar"]}
+    ///
+    /// Adding `--details` shows the glob from the mime database that matched each name.
     Mime {
         #[clap(required = true)]
         /// File paths/URLs to get the mimetype of
@@ -191,6 +660,169 @@ pub enum Cmd {
         #[clap(long)]
         /// Output mimetype info as json
         json: bool,
+        #[clap(long, conflicts_with = "json")]
+        /// Output mimetype info as json lines, one path per line
+        jsonl: bool,
+        #[clap(long, visible_alias = "no-stat")]
+        /// Go by names alone, without touching the filesystem
+        hypothetical: bool,
+        #[clap(long, requires = "hypothetical")]
+        /// Show the glob that matched each name
+        details: bool,
+    },
+
+    /// Rebuild the cache of installed desktop entries
+    ///
+    /// Scans desktop entries and saves the resulting associations to
+    /// $XDG_CACHE_HOME/handlr/system_apps.msgpack.
+    ///
+    /// handlr uses the cache instead of scanning desktop entries as long as
+    /// no desktop entry is newer than it, and falls back to scanning otherwise.
+    UpdateDb,
+
+    #[clap(verbatim_doc_comment)]
+    /// Check mimeapps.list and handlr.toml for common problems
+    ///
+    /// Reports handlers whose desktop files cannot be found or whose programs are not installed,
+    /// invalid mimetypes, duplicate handlers, regex handlers that match everything and shadow later ones,
+    /// a missing terminal emulator when a handler needs one,
+    /// and wildcards with different handlers that match some of the same mimetypes, like `video/*` and `*/mp4`.
+    ///
+    /// Each problem has a severity and a suggested fix.
+    /// Exits with a non-zero code if any problem has error severity.
+    ///
+    /// When using `--regex`, only regex handlers are checked: that their regexes compile,
+    /// that their programs are installed, and that no regex is used by more than one handler.
+    ///
+    /// When using `--wildcards`, only wildcards in mimeapps.list are checked for overlaps.
+    ///
+    /// When using `--fix`, handlers in mimeapps.list whose desktop files cannot be found or read are listed,
+    /// and removed after asking for confirmation, or right away with `--yes`.
+    /// Declining exits with code 4, like a cancelled selection.
+    ///
+    /// When using `--json`, output is in the form:
+    ///
+    /// [
+    ///   {
+    ///     "severity": "error",
+    ///     "problem": "/home/user/.config/mimeapps.list:3: nvim.desktop cannot be used: ...",
+    ///     "fix": "Install nvim.desktop or remove it from the line, e.g. with `handlr unset text/plain`"
+    ///   },
+    ///   ...
+    /// ]
+    #[clap(alias = "check")]
+    Doctor {
+        #[clap(long, conflicts_with = "fix")]
+        /// Output the report as json
+        json: bool,
+        #[clap(long, conflicts_with = "fix")]
+        /// Only check regex handlers from handlr.toml
+        regex: bool,
+        #[clap(long, conflicts_with_all = ["fix", "regex"])]
+        /// Only check for wildcards in mimeapps.list that match the same mimetypes
+        wildcards: bool,
+        #[clap(long)]
+        /// Remove handlers that cannot be used from mimeapps.list
+        fix: bool,
+        #[clap(long, short, requires = "fix")]
+        /// Remove without asking for confirmation
+        yes: bool,
+    },
+
+    #[clap(verbatim_doc_comment)]
+    /// Show whether handlr.toml, mimeapps.list and the installed desktop entries could be loaded
+    ///
+    /// If one of them cannot be loaded, handlr warns about it and carries on without it,
+    /// so this shows where each one was loaded from and how much it contains,
+    /// i.e. how many regex handlers handlr.toml has.
+    /// Exits with a non-zero code if any of them could not be loaded.
+    ///
+    /// When using `--json`, output is in the form:
+    ///
+    /// [
+    ///   {
+    ///     "source": "handlr.toml",
+    ///     "path": "/home/user/.config/handlr/handlr.toml",
+    ///     "loaded": false,
+    ///     "error": "Bad TOML data: ...",
+    ///     "counts": { "host_handlers": 0, "regex_handlers": 0 }
+    ///   },
+    ///   ...
+    /// ]
+    Status {
+        #[clap(long)]
+        /// Output the status as json
+        json: bool,
+    },
+
+    /// Open files/URLs, then open them again whenever mimeapps.list gives them a different handler
+    ///
+    /// handlr keeps running, watching ~/.config/mimeapps.list for changes.
+    /// Only the paths whose handler changed are opened again, with their new handler.
+    /// If the selector is cancelled, those paths keep the handler they had.
+    Watch {
+        #[clap(required = true)]
+        /// File paths/URLs to open
+        paths: Vec<UserPath>,
+    },
+
+    #[clap(verbatim_doc_comment)]
+    /// Show or clear the handlers last picked with the selector
+    ///
+    /// Selections are only recorded if `remember_selection` is set to true in ~/.config/handlr/handlr.toml,
+    /// in which case the last selection for a mimetype is offered first by the selector.
+    ///
+    /// When using `--json`, output is in the form:
+    ///
+    /// {
+    ///   "image/png": {
+    ///     "handler": "org.gnome.Loupe.desktop",
+    ///     "time": 1700000000
+    ///   },
+    ///   ...
+    /// }
+    ///
+    /// Where "time" is when the handler was selected, in seconds since the Unix epoch.
+    Recent {
+        #[clap(long)]
+        /// Forget every recorded selection
+        clear: bool,
+        #[clap(long, conflicts_with = "clear")]
+        /// Output recorded selections as json
+        json: bool,
+    },
+
+    /// Show the end of handlr's log
+    ///
+    /// Everything handlr warns about, along with what it did to find handlers,
+    /// is logged to ~/.cache/handlr/handlr.<date>.log, starting a new file each day.
+    /// Logs older than `log_retention_days` days handlr was used on are removed.
+    ///
+    /// When using `--follow`, new entries are printed as they are logged until handlr is stopped.
+    Log {
+        #[clap(long, short = 'n', default_value_t = 50)]
+        /// How many lines to show
+        lines: usize,
+        #[clap(long, short)]
+        /// Keep printing new entries as they are logged
+        follow: bool,
+        #[clap(long, conflicts_with_all = ["lines", "follow"])]
+        /// Empty the current log
+        clear: bool,
+    },
+
+    /// Print a shell completion script
+    ///
+    /// For example, for zsh:
+    ///
+    /// handlr completions zsh > ~/.zfunc/_handlr
+    ///
+    /// The scripts in the repository's assets/completions
+    /// also complete mimetypes and desktop files.
+    #[clap(verbatim_doc_comment)]
+    Completions {
+        /// Shell to generate the script for
+        shell: clap_complete::Shell,
     },
 
     #[clap(hide = true)]
@@ -207,5 +839,41 @@ pub enum Cmd {
         #[clap(short)]
         /// Autocomplete for mimetypes/file extensions
         mimes: bool,
+        #[clap(long, short)]
+        /// Autocomplete for mimetypes with an association, along with their handler
+        configured: bool,
     },
 }
+
+impl Cmd {
+    /// Whether the command may change mimeapps.list,
+    /// so it needs to hold `MimeApps::lock` from reading it until saving it
+    pub fn changes_mimeapps(&self) -> bool {
+        match self {
+            Cmd::Set { dry_run, .. } => !dry_run,
+            Cmd::Add { .. }
+            | Cmd::Unset { .. }
+            | Cmd::Remove { .. }
+            | Cmd::RenameHandler { .. } => true,
+            Cmd::Purge { dry_run, .. } => !dry_run,
+            Cmd::Pick { save, .. } => *save,
+            Cmd::Doctor { fix, .. } | Cmd::List { fix, .. } => *fix,
+            _ => false,
+        }
+    }
+}
+
+/// Parse `--env KEY=VALUE`, where the value may be empty or contain `=`
+fn parse_env(env: &str) -> Result<(String, String), String> {
+    match env.split_once('=') {
+        Some((key, value)) if !key.is_empty() => {
+            Ok((key.to_owned(), value.to_owned()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got '{}'", env)),
+    }
+}
+
+/// Parse `list --filter`, which ignores case
+fn parse_filter(filter: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(filter).case_insensitive(true).build()
+}
@@ -4,6 +4,10 @@ static CUSTOM_MIMES: &[&str] = &[
     "inode/directory",
     "x-scheme-handler/http",
     "x-scheme-handler/https",
+    "x-scheme-handler/magnet",
+    "x-scheme-handler/mailto",
+    "x-scheme-handler/sms",
+    "x-scheme-handler/tel",
     "x-scheme-handler/terminal",
 ];
 
@@ -1,24 +1,54 @@
-use crate::{Config, Error, ErrorKind, Result};
-use aho_corasick::AhoCorasick;
+use crate::{Config, EntryProblem, Error, ErrorKind, NotFoundKind, Result};
+use freedesktop_entry_parser::AttrSelector;
 use mime::Mime;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     convert::TryFrom,
     ffi::OsString,
     io::IsTerminal,
     path::{Path, PathBuf},
-    process::{Command, Stdio},
+    process::{Child, Command, Stdio},
     str::FromStr,
 };
+use tabled::Tabled;
 
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct DesktopEntry {
     pub(crate) name: String,
+    pub(crate) generic_name: Option<String>,
     pub(crate) exec: String,
     pub(crate) file_name: OsString,
     pub(crate) terminal: bool,
     pub(crate) mimes: Vec<Mime>,
     pub(crate) categories: HashMap<String, ()>,
+    pub(crate) actions: Vec<DesktopAction>,
+    /// Extra environment variables to run the command with
+    pub(crate) env: BTreeMap<String, String>,
+    /// Directory to run the command in, from the `Path` key
+    pub(crate) working_dir: Option<PathBuf>,
+    /// Icon name or path, from the `Icon` key
+    pub(crate) icon: Option<String>,
+    /// Whether the entry should be left out of menus, from the `NoDisplay` key
+    pub(crate) no_display: bool,
+    /// Whether the entry counts as deleted, from the `Hidden` key
+    pub(crate) hidden: bool,
+    /// Whether the app can be started over D-Bus, from the `DBusActivatable` key
+    pub(crate) dbus_activatable: bool,
+    /// Whether the app should run on the non-default GPU, from the `PrefersNonDefaultGPU` key
+    pub(crate) prefers_non_default_gpu: bool,
+}
+
+/// An additional action defined in a `[Desktop Action <id>]` section
+#[derive(Debug, Clone, Default, PartialEq, Eq, Tabled, Serialize)]
+pub struct DesktopAction {
+    #[tabled(rename = "action")]
+    #[serde(rename = "action")]
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) exec: String,
 }
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -27,76 +57,434 @@ pub enum Mode {
     Open,
 }
 
+/// How handlers are run, from the options of `handlr open` and `handlr launch`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecOptions {
+    /// Print each command to stderr before it is run, from `--log-cmd`
+    pub log_cmd: bool,
+    /// Never start a terminal emulator for terminal programs, from `--no-term-wrap`
+    pub no_term_wrap: bool,
+    /// Ignore `PrefersNonDefaultGPU`, from `--no-gpu-offload`
+    pub no_gpu_offload: bool,
+    /// Run handlers once per file even if they take several, from `--split`
+    pub split: bool,
+    /// Extra environment variables in the order they were given, from `--env`
+    ///
+    /// These are set on top of those from the desktop file and `env_overrides`.
+    pub env: Vec<(String, String)>,
+    /// Print the pid of each handler that is started to stdout, from `--print-pid`
+    pub print_pid: bool,
+    /// Never show desktop notifications, like for a guessed terminal emulator, from `--quiet`
+    pub quiet: bool,
+}
+
 impl DesktopEntry {
-    pub fn exec(&self, mode: Mode, arguments: Vec<String>) -> Result<()> {
+    /// Whether the entry should be offered in menus like the selector
+    pub fn is_shown(&self) -> bool {
+        !(self.no_display || self.hidden)
+    }
+    /// The icon to show alongside the entry's name, if it has one
+    ///
+    /// This is either an icon name to look up in the icon theme or an absolute path.
+    pub fn icon(&self) -> Option<&str> {
+        self.icon.as_deref()
+    }
+    /// The name to show in the selector, with the generic name if there is one
+    /// to tell similar apps apart, i.e. "Firefox (Web Browser)"
+    pub fn display_name(&self) -> String {
+        match &self.generic_name {
+            Some(generic_name) => format!("{} ({})", self.name, generic_name),
+            None => self.name.clone(),
+        }
+    }
+    /// Whether the entry is most likely a terminal emulator
+    ///
+    /// Not every terminal emulator has the `TerminalEmulator` category, so it is scored:
+    /// 2 for the category, and 1 each for `terminal` in the program's name ignoring case,
+    /// a `-e` argument in `Exec` and `Terminal` in `Name`. A score of 2 or more counts.
+    pub fn is_terminal_emulator(&self) -> bool {
+        let exec = split_exec(&self.exec).unwrap_or_default();
+        let program = exec.first().map(|p| program_name(p)).unwrap_or_default();
+
+        let score = [
+            (self.categories.contains_key("TerminalEmulator"), 2),
+            (program.to_lowercase().contains("terminal"), 1),
+            (exec.iter().skip(1).any(|arg| arg == "-e"), 1),
+            (self.name.contains("Terminal"), 1),
+        ]
+        .iter()
+        .filter(|(matches, _)| *matches)
+        .map(|(_, score)| score)
+        .sum::<u32>();
+
+        score >= 2
+    }
+    /// Get a copy of this entry that runs the given action's Exec instead
+    pub fn with_action(&self, id: &str) -> Result<DesktopEntry> {
+        let action = self
+            .actions
+            .iter()
+            .find(|action| action.id == id)
+            .ok_or_else(|| {
+                NotFoundKind::Action(
+                    self.file_name.to_string_lossy().into(),
+                    id.to_owned(),
+                )
+            })?;
+
+        let overlay = DesktopEntry {
+            exec: action.exec.clone(),
+            ..Default::default()
+        };
+        Ok(DesktopEntry {
+            // Activating over D-Bus would start the app, not the action
+            dbus_activatable: false,
+            ..self.merge(&overlay)
+        })
+    }
+    /// Combine this entry with `other` laid over it, i.e. from an override desktop file
+    ///
+    /// Fields that `other` sets take precedence, while mimes, categories,
+    /// actions and environment variables are combined, with `other`'s winning on conflicts.
+    /// Flags like `terminal` are set if either sets them,
+    /// since an unset flag cannot be told apart from a missing one.
+    pub fn merge(&self, other: &DesktopEntry) -> DesktopEntry {
+        let pick = |ours: &str, theirs: &str| {
+            if theirs.is_empty() { ours } else { theirs }.to_owned()
+        };
+
+        let mut mimes = self.mimes.clone();
+        mimes.extend(
+            other
+                .mimes
+                .iter()
+                .filter(|mime| !self.mimes.contains(mime))
+                .cloned(),
+        );
+
+        let mut actions = self
+            .actions
+            .iter()
+            .filter(|action| !other.actions.iter().any(|a| a.id == action.id))
+            .cloned()
+            .collect::<Vec<_>>();
+        actions.extend(other.actions.iter().cloned());
+
+        DesktopEntry {
+            name: pick(&self.name, &other.name),
+            generic_name: other
+                .generic_name
+                .clone()
+                .or_else(|| self.generic_name.clone()),
+            exec: pick(&self.exec, &other.exec),
+            file_name: if other.file_name.is_empty() {
+                self.file_name.clone()
+            } else {
+                other.file_name.clone()
+            },
+            terminal: self.terminal || other.terminal,
+            mimes,
+            categories: self
+                .categories
+                .iter()
+                .chain(&other.categories)
+                .map(|(category, ())| (category.clone(), ()))
+                .collect(),
+            actions,
+            env: self
+                .env
+                .iter()
+                .chain(&other.env)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+            working_dir: other
+                .working_dir
+                .clone()
+                .or_else(|| self.working_dir.clone()),
+            icon: other.icon.clone().or_else(|| self.icon.clone()),
+            no_display: self.no_display || other.no_display,
+            hidden: self.hidden || other.hidden,
+            dbus_activatable: self.dbus_activatable || other.dbus_activatable,
+            prefers_non_default_gpu: self.prefers_non_default_gpu
+                || other.prefers_non_default_gpu,
+        }
+    }
+    /// Run the entry with `arguments`, returning the processes that were started
+    ///
+    /// Terminal programs run in the foreground are waited for right away,
+    /// while everything else is left running for the caller to wait for if it wants to,
+    /// so that several apps can be started at once.
+    pub fn exec(
+        &self,
+        mode: Mode,
+        arguments: Vec<String>,
+        wait: bool,
+        detach: bool,
+        options: &ExecOptions,
+    ) -> Result<Vec<Child>> {
+        // Apps activated over D-Bus may already be running, so `--env` and GPU offloading
+        // could not reach them, and there is no process of handlr's own to wait for or print the pid of
+        #[cfg(feature = "dbus")]
+        if self.dbus_activatable
+            && options.env.is_empty()
+            && !self.offloads_gpu(options.no_gpu_offload)
+            && !options.print_pid
+            && !wait
+            && (mode == Mode::Open || arguments.is_empty())
+            && std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some()
+        {
+            match self.dbus_activate(&arguments, options) {
+                Ok(()) => return Ok(Vec::new()),
+                Err(e) => tracing::warn!("{}, running Exec instead", e),
+            }
+        }
+
+        let mut children = Vec::new();
+        for args in self.invocations(mode, arguments, options.split) {
+            children.extend(self.exec_inner(args, wait, detach, options)?);
+        }
+
+        Ok(children)
+    }
+    /// The arguments to run the entry with each time
+    ///
+    /// Files are opened all at once if `Exec` takes several, i.e. with `%F` or `%U`,
+    /// unless `split` is set. Otherwise, the entry is run once per file.
+    fn invocations(
+        &self,
+        mode: Mode,
+        arguments: Vec<String>,
+        split: bool,
+    ) -> Vec<Vec<String>> {
         let supports_multiple =
             self.exec.contains("%F") || self.exec.contains("%U");
-        if arguments.is_empty() {
-            self.exec_inner(vec![])?
-        } else if supports_multiple || mode == Mode::Launch {
-            self.exec_inner(arguments)?;
+        if arguments.is_empty()
+            || mode == Mode::Launch
+            || (supports_multiple && !split)
+        {
+            vec![arguments]
         } else {
-            for arg in arguments {
-                self.exec_inner(vec![arg])?;
-            }
-        };
-
-        Ok(())
+            arguments.into_iter().map(|arg| vec![arg]).collect()
+        }
     }
-    fn exec_inner(&self, args: Vec<String>) -> Result<()> {
-        let mut cmd = {
-            let (cmd, args) = self.get_cmd(args)?;
-            let mut cmd = Command::new(cmd);
-            cmd.args(args);
-            cmd
+    /// Ask the app to open `args`, or just to start if there are none,
+    /// through its `org.freedesktop.Application` D-Bus interface
+    ///
+    /// The app is reached by its desktop file name, as the spec requires
+    /// for D-Bus activatable entries. The session bus starts the app
+    /// if it is not running yet.
+    #[cfg(feature = "dbus")]
+    fn dbus_activate(
+        &self,
+        args: &[String],
+        options: &ExecOptions,
+    ) -> Result<()> {
+        use zbus::{blocking::Connection, zvariant::Value};
+
+        let file_name = self.file_name.to_string_lossy();
+        let app_id = file_name.trim_end_matches(".desktop");
+        let fail = |e: zbus::Error| {
+            Error::from(ErrorKind::DBusActivation(
+                app_id.to_owned(),
+                e.to_string(),
+            ))
         };
 
-        if self.terminal && std::io::stdout().is_terminal() {
-            cmd.spawn()?.wait()?;
-        } else {
-            cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+        let uris = args
+            .iter()
+            .map(|arg| to_uri(arg))
+            .collect::<Result<Vec<_>>>()?;
+        if options.log_cmd {
+            eprintln!(
+                "handlr: activating {} over D-Bus: {}",
+                app_id,
+                shlex::try_join(uris.iter().map(|uri| uri.as_str()))
+                    .unwrap_or_else(|_| uris.join(" "))
+            );
         }
 
-        Ok(())
+        let connection = Connection::session().map_err(fail)?;
+        let path = dbus_object_path(app_id);
+        let platform_data = HashMap::<&str, Value>::new();
+        let reply = if uris.is_empty() {
+            connection.call_method(
+                Some(app_id),
+                path.as_str(),
+                Some("org.freedesktop.Application"),
+                "Activate",
+                &(platform_data,),
+            )
+        } else {
+            connection.call_method(
+                Some(app_id),
+                path.as_str(),
+                Some("org.freedesktop.Application"),
+                "Open",
+                &(uris, platform_data),
+            )
+        };
+        reply.map(|_| ()).map_err(fail)
     }
-    pub fn get_cmd(&self, args: Vec<String>) -> Result<(String, Vec<String>)> {
-        let special =
-            AhoCorasick::new_auto_configured(&["%f", "%F", "%u", "%U"]);
+    /// Run the command, detaching it from handlr's session if `detach` is set
+    /// so that it keeps running after the calling terminal is closed
+    ///
+    /// Returns the process unless it was run in the foreground of the terminal,
+    /// in which case it has already exited.
+    fn exec_inner(
+        &self,
+        args: Vec<String>,
+        wait: bool,
+        detach: bool,
+        options: &ExecOptions,
+    ) -> Result<Option<Child>> {
+        // Detached terminal programs need a terminal emulator of their own
+        let in_terminal = std::io::stdout().is_terminal() && !detach;
 
-        let mut exec = shlex::split(&self.exec).unwrap();
+        let env = self.effective_env(options);
+        let mut cmd = self.command(args, in_terminal, options, &env)?;
 
-        // The desktop entry doesn't contain arguments - we make best effort and append them at
-        // the end
-        if special.is_match(&self.exec) {
-            exec = exec
-                .into_iter()
-                .flat_map(|s| match s.as_str() {
-                    "%f" | "%F" | "%u" | "%U" => args.clone(),
-                    s if special.is_match(s) => vec![{
-                        let mut replaced =
-                            String::with_capacity(s.len() + args.len() * 2);
-                        special.replace_all_with(
-                            s,
-                            &mut replaced,
-                            |_, _, dst| {
-                                dst.push_str(args.clone().join(" ").as_str());
-                                false
-                            },
-                        );
-                        replaced
-                    }],
-                    _ => vec![s],
-                })
-                .collect()
+        if detach {
+            use std::os::unix::process::CommandExt;
+
+            cmd.stdin(Stdio::null());
+            // SAFETY: setsid is async-signal-safe
+            unsafe {
+                cmd.pre_exec(|| {
+                    if libc::setsid() == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        if options.log_cmd {
+            crate::utils::log_cmd(&cmd);
+        }
+        if self.terminal && in_terminal {
+            let mut child = cmd.spawn()?;
+            if options.print_pid {
+                println!("{}", child.id());
+            }
+            if wait {
+                crate::utils::wait_all(vec![child])?;
+            } else {
+                child.wait()?;
+            }
+            Ok(None)
         } else {
-            exec.extend_from_slice(&args);
+            cmd.stdout(Stdio::null());
+            // Errors from a program waited on are still worth seeing
+            if !wait {
+                cmd.stderr(Stdio::null());
+            }
+            let child = cmd.spawn()?;
+            if options.print_pid {
+                println!("{}", child.id());
+            }
+            Ok(Some(child))
+        }
+    }
+    /// The command to run the entry with, ready to be spawned
+    fn command(
+        &self,
+        args: Vec<String>,
+        in_terminal: bool,
+        options: &ExecOptions,
+        env: &BTreeMap<String, String>,
+    ) -> Result<Command> {
+        let (cmd, args) = self.get_cmd_in(args, in_terminal, options)?;
+        let mut cmd = Command::new(cmd);
+        cmd.args(args).envs(env);
+        if let Some(dir) = &self.working_dir {
+            cmd.current_dir(dir);
         }
+        Ok(cmd)
+    }
+    /// The environment variables the command is run with on top of handlr's own
+    ///
+    /// These are `gpu_offload_env` from handlr.toml if the entry has `PrefersNonDefaultGPU=true`,
+    /// unless `--no-gpu-offload` was used, then the entry's own, i.e. from a regex handler's `env`,
+    /// and those set for its desktop file in `env_overrides` in handlr.toml,
+    /// with `$VAR` and `${VAR}` replaced by the variables handlr was run with,
+    /// followed by those given with `--env`.
+    pub fn effective_env(
+        &self,
+        options: &ExecOptions,
+    ) -> BTreeMap<String, String> {
+        let mut env =
+            self.env_for(&crate::CONFIG, options.no_gpu_offload, |var| {
+                std::env::var(var).ok()
+            });
+        env.extend(options.env.iter().cloned());
+        env
+    }
+    /// Like `effective_env`, with settings from `config` and variables looked up with `var`
+    fn env_for(
+        &self,
+        config: &Config,
+        no_gpu_offload: bool,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> BTreeMap<String, String> {
+        let mut env = if self.offloads_gpu(no_gpu_offload) {
+            config.gpu_offload_env.clone()
+        } else {
+            BTreeMap::new()
+        };
+        env.extend(self.env_with(&config.env_overrides, var));
+        env
+    }
+    /// Whether the entry is run on the non-default GPU, see `effective_env`
+    fn offloads_gpu(&self, no_gpu_offload: bool) -> bool {
+        self.prefers_non_default_gpu && !no_gpu_offload
+    }
+    fn env_with(
+        &self,
+        overrides: &BTreeMap<String, BTreeMap<String, String>>,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> BTreeMap<String, String> {
+        let overrides = overrides
+            .get(self.file_name.to_string_lossy().as_ref())
+            .into_iter()
+            .flatten();
+
+        self.env
+            .iter()
+            .chain(overrides)
+            .map(|(key, value)| (key.clone(), expand_vars(value, &var)))
+            .collect()
+    }
+    pub fn get_cmd(
+        &self,
+        args: Vec<String>,
+        options: &ExecOptions,
+    ) -> Result<(String, Vec<String>)> {
+        self.get_cmd_in(args, std::io::stdout().is_terminal(), options)
+    }
+    fn get_cmd_in(
+        &self,
+        args: Vec<String>,
+        in_terminal: bool,
+        options: &ExecOptions,
+    ) -> Result<(String, Vec<String>)> {
+        let mut exec = self.expand_exec(args);
 
         // If the entry expects a terminal (emulator), but this process is not running in one, we
         // launch a new one.
-        if self.terminal && !std::io::stdout().is_terminal() {
-            exec = shlex::split(&Config::terminal()?)
-                .unwrap()
+        if self.needs_terminal_emulator(
+            &exec,
+            in_terminal,
+            options.no_term_wrap,
+            || {
+                terminal_programs(
+                    crate::apps::SystemApps::get_entries()
+                        .into_iter()
+                        .flatten()
+                        .map(|(_, entry)| entry),
+                )
+            },
+        ) {
+            exec = Config::terminal(options.quiet)?
                 .into_iter()
                 .chain(exec)
                 .collect();
@@ -104,23 +492,289 @@ impl DesktopEntry {
 
         Ok((exec.remove(0), exec))
     }
+    /// Whether the expanded `exec` has to be run in a new terminal emulator
+    ///
+    /// Not if handlr is already running in a terminal, if `--no-term-wrap` was used,
+    /// or if `exec` starts one of `terminals` itself, i.e. `wezterm start -- nvim file`,
+    /// which would otherwise be wrapped in a second one.
+    fn needs_terminal_emulator(
+        &self,
+        exec: &[String],
+        in_terminal: bool,
+        no_term_wrap: bool,
+        terminals: impl FnOnce() -> Vec<String>,
+    ) -> bool {
+        if !self.terminal || in_terminal || no_term_wrap {
+            return false;
+        }
+
+        let program = exec.first().map(|program| program_name(program));
+        !program.is_some_and(|program| terminals().contains(&program))
+    }
+    /// Split `Exec` into a command line with the given arguments filled in,
+    /// without wrapping it in a terminal emulator
+    pub fn expand_exec(&self, args: Vec<String>) -> Vec<String> {
+        let mut exec = split_exec(&self.exec).unwrap_or_else(|| {
+            tracing::warn!("unterminated quote in Exec '{}'", self.exec);
+            shlex::split(&self.exec).unwrap_or_default()
+        });
+
+        let mut used_args = false;
+        exec = exec
+            .into_iter()
+            .flat_map(|s| match s.as_str() {
+                "%f" | "%F" | "%u" | "%U" => {
+                    used_args = true;
+                    args.clone()
+                }
+                s => {
+                    let (expanded, used) = expand_field_codes(s, &args);
+                    used_args |= used;
+                    // Arguments that were only field codes, like `%i`, are removed entirely
+                    if expanded.is_empty() && !s.is_empty() {
+                        vec![]
+                    } else {
+                        vec![expanded]
+                    }
+                }
+            })
+            .collect();
+
+        // The desktop entry doesn't contain arguments - we make best effort and append them at
+        // the end
+        if !used_args {
+            exec.extend_from_slice(&args);
+        }
+
+        exec
+    }
+}
+
+/// Replace `$VAR` and `${VAR}` in `value` with the variables from `var`,
+/// leaving unset ones empty like a shell would
+fn expand_vars(value: &str, var: impl Fn(&str) -> Option<String>) -> String {
+    static VARIABLE: Lazy<Regex> = Lazy::new(|| {
+        Regex::new(r"\$(?:\{(\w+)\}|(\w+))").expect("variable regex is valid")
+    });
+
+    VARIABLE
+        .replace_all(value, |captures: &regex::Captures| {
+            let name = captures.get(1).or_else(|| captures.get(2)).unwrap();
+            var(name.as_str()).unwrap_or_default()
+        })
+        .into_owned()
+}
+
+/// The programs that the given terminal emulator entries run
+fn terminal_programs(
+    entries: impl Iterator<Item = DesktopEntry>,
+) -> Vec<String> {
+    entries
+        .filter(DesktopEntry::is_terminal_emulator)
+        .filter_map(|entry| {
+            Some(program_name(split_exec(&entry.exec)?.first()?))
+        })
+        .collect()
+}
+
+/// The file name of a program, so it can be compared whether it is run by path or not
+fn program_name(program: &str) -> String {
+    Path::new(program)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| program.to_owned())
+}
+
+/// Split an `Exec` value into arguments as the desktop entry spec describes
+///
+/// The value is first unescaped like any other string value, so `\\` becomes `\`.
+/// Arguments are then separated by spaces and may be wrapped in double quotes,
+/// within which `"`, `` ` ``, `$` and `\` are escaped with a backslash.
+/// Returns `None` if a quote is never closed.
+pub(crate) fn split_exec(exec: &str) -> Option<Vec<String>> {
+    let mut unescaped = String::with_capacity(exec.len());
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('s' | 'n' | 't' | 'r' | '\\'))) => {
+                chars.next();
+                unescaped.push(match next {
+                    's' => ' ',
+                    'n' => '\n',
+                    't' => '\t',
+                    'r' => '\r',
+                    _ => '\\',
+                });
+            }
+            _ => unescaped.push(c),
+        }
+    }
+
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    // Whether there is an argument to push, even if it is an empty quoted one
+    let mut in_arg = false;
+    let mut quoted = false;
+    let mut chars = unescaped.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_arg = true;
+            }
+            '\\' if quoted => match chars.next()? {
+                c @ ('"' | '`' | '$' | '\\') => arg.push(c),
+                c => {
+                    arg.push('\\');
+                    arg.push(c);
+                }
+            },
+            // Not allowed by the spec, but shells treat it this way
+            '\\' => {
+                arg.extend(chars.next());
+                in_arg = true;
+            }
+            ' ' | '\t' | '\n' if !quoted => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            c => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    if quoted {
+        return None;
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Some(args)
+}
+
+/// Quote arguments into an `Exec` value that `split_exec` turns back into them
+pub(crate) fn join_exec<S: AsRef<str>>(args: &[S]) -> String {
+    args.iter()
+        .map(|arg| {
+            let mut quoted = String::from("\"");
+            for c in arg.as_ref().chars() {
+                match c {
+                    // Escaped once for quoting and again as a string value
+                    '\\' => quoted.push_str("\\\\\\\\"),
+                    '"' | '`' | '$' => {
+                        quoted.push_str("\\\\");
+                        quoted.push(c);
+                    }
+                    c => quoted.push(c),
+                }
+            }
+            quoted.push('"');
+            quoted
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Expand the field codes within a single `Exec` argument
+///
+/// `%f`, `%F`, `%u` and `%U` are replaced by the arguments, `%%` becomes a
+/// literal `%` and any other field code is removed.
+/// Also returns whether the arguments were used.
+fn expand_field_codes(s: &str, args: &[String]) -> (String, bool) {
+    let mut expanded = String::with_capacity(s.len());
+    let mut used_args = false;
+    let mut chars = s.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('f' | 'F' | 'u' | 'U') => {
+                used_args = true;
+                expanded.push_str(&args.join(" "));
+            }
+            Some('%') => expanded.push('%'),
+            Some(_) => {}
+            None => expanded.push('%'),
+        }
+    }
+
+    (expanded, used_args)
+}
+
+/// Get the object path an app exports its D-Bus interfaces at,
+/// i.e. `/org/example/Viewer` for `org.example.Viewer`
+#[cfg(feature = "dbus")]
+fn dbus_object_path(app_id: &str) -> String {
+    format!("/{}", app_id.replace('.', "/").replace('-', "_"))
+}
+
+/// Turn a path or URL given to handlr into the URI that gets sent over D-Bus
+#[cfg(feature = "dbus")]
+fn to_uri(arg: &str) -> Result<String> {
+    use crate::common::UserPath;
+
+    Ok(match UserPath::from_str(arg)? {
+        UserPath::Url { url, .. } => url.into(),
+        UserPath::File { path, .. } => {
+            url::Url::from_file_path(std::env::current_dir()?.join(&path))
+                .map_err(|_| ErrorKind::BadPath(arg.to_owned()))?
+                .into()
+        }
+    })
 }
 
-fn parse_file(path: &Path) -> Option<DesktopEntry> {
-    let raw_entry = freedesktop_entry_parser::parse_entry(path).ok()?;
+/// Parse the desktop entry at `path`, saying what is wrong with it if it cannot be used
+///
+/// Only entries with `Type=Application` can open anything, though a missing Type is tolerated.
+fn parse_file(path: &Path) -> std::result::Result<DesktopEntry, EntryProblem> {
+    let raw_entry = freedesktop_entry_parser::parse_entry(path)
+        .map_err(EntryProblem::ParseFailure)?;
     let section = raw_entry.section("Desktop Entry");
 
+    match section.attr("Type") {
+        Some(kind) if kind.trim() != "Application" => {
+            return Err(EntryProblem::NotAnApplication(kind.trim().to_owned()))
+        }
+        _ => {}
+    }
+
     let mut entry = DesktopEntry {
-        file_name: path.file_name()?.to_owned(),
+        file_name: path.file_name().unwrap_or_default().to_owned(),
         ..Default::default()
     };
 
-    for attr in section.attrs().into_iter().filter(|a| a.has_value()) {
+    let locales = locales();
+
+    for attr in section.attrs().filter(|a| a.has_value()) {
         match attr.name {
             "Name" if entry.name.is_empty() => {
-                entry.name = attr.value.unwrap().into();
+                entry.name =
+                    localized(&section, "Name", &locales).unwrap_or_default();
+            }
+            "GenericName" if entry.generic_name.is_none() => {
+                entry.generic_name =
+                    localized(&section, "GenericName", &locales);
             }
             "Exec" => entry.exec = attr.value.unwrap().into(),
+            // An empty Path is the same as none, not the current directory
+            "Path" => {
+                entry.working_dir = attr
+                    .value
+                    .filter(|path| !path.trim().is_empty())
+                    .map(PathBuf::from)
+            }
+            "Icon" if entry.icon.is_none() => {
+                entry.icon = localized(&section, "Icon", &locales);
+            }
             "MimeType" => {
                 entry.mimes = attr
                     .value
@@ -130,6 +784,14 @@ fn parse_file(path: &Path) -> Option<DesktopEntry> {
                     .collect::<Vec<_>>();
             }
             "Terminal" => entry.terminal = attr.value.unwrap() == "true",
+            "NoDisplay" => entry.no_display = attr.value.unwrap() == "true",
+            "Hidden" => entry.hidden = attr.value.unwrap() == "true",
+            "DBusActivatable" => {
+                entry.dbus_activatable = attr.value.unwrap() == "true"
+            }
+            "PrefersNonDefaultGPU" => {
+                entry.prefers_non_default_gpu = attr.value.unwrap() == "true"
+            }
             "Categories" => {
                 entry.categories = attr
                     .value
@@ -139,21 +801,96 @@ fn parse_file(path: &Path) -> Option<DesktopEntry> {
                     .map(|cat| (cat.to_owned(), ()))
                     .collect();
             }
+            "Actions" => {
+                entry.actions = attr
+                    .value
+                    .unwrap()
+                    .split(';')
+                    .filter(|s| !s.is_empty())
+                    .filter_map(|id| {
+                        let section =
+                            raw_entry.section(format!("Desktop Action {}", id));
+                        Some(DesktopAction {
+                            id: id.to_owned(),
+                            name: localized(&section, "Name", &locales)?,
+                            exec: section.attr("Exec")?.to_owned(),
+                        })
+                    })
+                    .collect();
+            }
             _ => {}
         }
     }
 
-    if !entry.name.is_empty() && !entry.exec.is_empty() {
-        Some(entry)
+    if section.attr("Name").is_none() {
+        Err(EntryProblem::MissingName)
+    } else if entry.name.trim().is_empty() {
+        Err(EntryProblem::EmptyValue("Name"))
+    } else if section.attr("Exec").is_none() {
+        Err(EntryProblem::MissingExec)
+    } else if entry.exec.trim().is_empty() {
+        Err(EntryProblem::EmptyValue("Exec"))
     } else {
-        None
+        Ok(entry)
     }
 }
 
+/// Get the value of a localestring key, preferring the best match for the given locales
+fn localized<T: AsRef<str>>(
+    section: &AttrSelector<T>,
+    key: &str,
+    locales: &[String],
+) -> Option<String> {
+    locales
+        .iter()
+        .find_map(|locale| section.attr_with_param(key, locale))
+        .or_else(|| section.attr(key))
+        .map(|value| value.to_owned())
+}
+
+/// Locale names to look up localized keys with, in order of preference
+pub(crate) fn locales() -> Vec<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .map(|locale| locale_variants(&locale))
+        .unwrap_or_default()
+}
+
+/// Expand a locale like `lang_COUNTRY.ENCODING@MODIFIER` into the names
+/// desktop entries may use for it, as described in the desktop entry spec
+fn locale_variants(locale: &str) -> Vec<String> {
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale, None),
+    };
+    // Encoding is ignored when matching keys
+    let locale = locale.split('.').next().unwrap_or_default();
+    let (lang, country) = match locale.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (locale, None),
+    };
+
+    let mut variants = Vec::with_capacity(4);
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        variants.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        variants.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        variants.push(format!("{}@{}", lang, modifier));
+    }
+    variants.push(lang.to_owned());
+    variants
+}
+
 impl TryFrom<PathBuf> for DesktopEntry {
     type Error = Error;
     fn try_from(path: PathBuf) -> Result<DesktopEntry> {
-        parse_file(&path).ok_or(Error::from(ErrorKind::BadEntry(path)))
+        parse_file(&path)
+            .map_err(|problem| ErrorKind::BadEntry { path, problem }.into())
     }
 }
 
@@ -167,5 +904,597 @@ mod tests {
         assert_eq!(entry.mimes.len(), 2);
         assert_eq!(entry.mimes[0].essence_str(), "audio/mp3");
         assert_eq!(entry.mimes[1].essence_str(), "audio/ogg");
+        assert_eq!(entry.icon(), Some("cmus"));
+    }
+
+    #[test]
+    fn actions() -> Result<()> {
+        let entry = parse_file(Path::new("tests/emacsclient.desktop")).unwrap();
+        assert_eq!(
+            entry.actions,
+            vec![
+                DesktopAction {
+                    id: "new-window".into(),
+                    name: "New Window".into(),
+                    exec: "emacsclient --alternate-editor= --create-frame %F"
+                        .into(),
+                },
+                DesktopAction {
+                    id: "new-instance".into(),
+                    name: "New Instance".into(),
+                    exec: "emacs %F".into(),
+                },
+            ]
+        );
+
+        assert_eq!(entry.with_action("new-instance")?.exec, "emacs %F");
+        assert!(matches!(
+            *entry.with_action("nonexistent").unwrap_err().kind,
+            ErrorKind::NotFound(NotFoundKind::Action(_, _))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge() -> Result<()> {
+        let base = parse_file(Path::new("tests/emacsclient.desktop")).unwrap();
+        let overlay = DesktopEntry {
+            exec: "emacs %F".into(),
+            terminal: true,
+            mimes: vec![
+                Mime::from_str("text/plain")?,
+                Mime::from_str("text/markdown")?,
+            ],
+            categories: vec![("Utility".to_owned(), ())].into_iter().collect(),
+            actions: vec![DesktopAction {
+                id: "new-window".into(),
+                name: "New Frame".into(),
+                exec: "emacs --new-frame %F".into(),
+            }],
+            env: vec![("EMACS_SOCKET".to_owned(), "work".to_owned())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let merged = base.merge(&overlay);
+
+        // What the overlay sets wins, and the rest comes from the base
+        assert_eq!(merged.exec, "emacs %F");
+        assert!(merged.terminal);
+        assert_eq!(merged.name, "Emacs (Client)");
+        assert_eq!(merged.generic_name.as_deref(), Some("Text Editor"));
+        assert_eq!(merged.icon(), Some("emacs"));
+        assert_eq!(merged.file_name, base.file_name);
+        assert_eq!(merged.env, overlay.env);
+
+        // Mimes and categories are combined, without duplicates
+        assert_eq!(merged.mimes.len(), base.mimes.len() + 1);
+        assert_eq!(
+            merged.mimes.last(),
+            Some(&Mime::from_str("text/markdown")?)
+        );
+        let mut categories = merged
+            .categories
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        categories.sort_unstable();
+        assert_eq!(categories, ["Development", "TextEditor", "Utility"]);
+
+        // Actions with the same id are replaced
+        assert_eq!(
+            merged
+                .actions
+                .iter()
+                .map(|a| (a.id.as_str(), a.name.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("new-instance", "New Instance"),
+                ("new-window", "New Frame")
+            ]
+        );
+
+        // Merging an empty entry changes nothing
+        assert_eq!(base.merge(&DesktopEntry::default()), base);
+
+        Ok(())
+    }
+
+    #[test]
+    fn display_name() {
+        let entry = parse_file(Path::new("tests/emacsclient.desktop")).unwrap();
+        assert_eq!(entry.generic_name.as_deref(), Some("Text Editor"));
+        assert_eq!(entry.display_name(), "Emacs (Client) (Text Editor)");
+
+        let entry = parse_file(Path::new("tests/cmus.desktop")).unwrap();
+        assert_eq!(entry.display_name(), entry.name);
+    }
+
+    #[test]
+    fn is_terminal_emulator() {
+        let entry =
+            |name: &str, exec: &str, categories: &[&str]| DesktopEntry {
+                name: name.into(),
+                exec: exec.into(),
+                categories: categories
+                    .iter()
+                    .map(|category| (category.to_string(), ()))
+                    .collect(),
+                ..Default::default()
+            };
+
+        assert!(entry("WezTerm", "wezterm start", &["TerminalEmulator"])
+            .is_terminal_emulator());
+        // Without the category, two weaker hints are enough
+        assert!(entry("Custom Terminal", "/opt/myterminal", &[])
+            .is_terminal_emulator());
+        assert!(entry("st", "st-terminal -e", &[]).is_terminal_emulator());
+        // But one is not
+        assert!(!entry("Foot", "foot -e", &[]).is_terminal_emulator());
+        assert!(!entry("Terminal Notes", "notes %f", &["Utility"])
+            .is_terminal_emulator());
+        assert!(!parse_file(Path::new("tests/emacsclient.desktop"))
+            .unwrap()
+            .is_terminal_emulator());
+    }
+
+    #[test]
+    fn locales() {
+        assert_eq!(
+            locale_variants("de_DE.UTF-8@euro"),
+            vec!["de_DE@euro", "de_DE", "de@euro", "de"]
+        );
+        assert_eq!(locale_variants("de_DE.UTF-8"), vec!["de_DE", "de"]);
+        assert_eq!(locale_variants("C"), vec!["C"]);
+
+        let raw_entry =
+            freedesktop_entry_parser::parse_entry("tests/emacsclient.desktop")
+                .unwrap();
+        let section = raw_entry.section("Desktop Action new-window");
+        assert_eq!(
+            localized(&section, "Name", &locale_variants("de_AT.UTF-8")),
+            Some("Neues Fenster".into())
+        );
+        assert_eq!(
+            localized(&section, "Name", &locale_variants("fr_FR.UTF-8")),
+            Some("New Window".into())
+        );
+    }
+
+    #[test]
+    fn exec_quoting() {
+        let entry = parse_file(Path::new("tests/emacsclient.desktop")).unwrap();
+        assert_eq!(
+            entry.expand_exec(vec!["a b.txt".into(), "c.txt".into()]),
+            vec![
+                "sh",
+                "-c",
+                r#"if [ -n "$*" ]; then exec emacsclient --alternate-editor= --display="$DISPLAY" "$@"; else exec emacsclient --alternate-editor= --create-frame; fi"#,
+                "sh",
+                "a b.txt",
+                "c.txt",
+            ]
+        );
+
+        let split = |exec: &str| split_exec(exec);
+        assert_eq!(
+            split(r#"app  --title="" "a b" "c\sd""#),
+            Some(vec![
+                "app".into(),
+                "--title=".into(),
+                "a b".into(),
+                "c d".into()
+            ])
+        );
+        assert_eq!(split(r#"app "unterminated"#), None);
+
+        let args = ["app", "it's \"quoted\"", r"C:\dir", "$HOME", ""];
+        assert_eq!(
+            split(&join_exec(&args)),
+            Some(args.map(String::from).to_vec())
+        );
+
+        // Regex handlers keep their shell-like quoting
+        let handler =
+            crate::RegexHandler::from_exec("mpv --title='my video' %u");
+        assert_eq!(
+            handler.get_entry().expand_exec(vec!["a.mp4".into()]),
+            vec!["mpv", "--title=my video", "a.mp4"]
+        );
+    }
+
+    #[test]
+    fn percent_escapes() -> Result<()> {
+        let cmd = |exec: &str, args: &[&str]| {
+            DesktopEntry {
+                exec: exec.into(),
+                ..Default::default()
+            }
+            .get_cmd(
+                args.iter().map(|s| s.to_string()).collect(),
+                &ExecOptions::default(),
+            )
+        };
+
+        // As its own argument
+        assert_eq!(
+            cmd("printf %% %f", &["file"])?,
+            ("printf".into(), vec!["%".into(), "file".into()])
+        );
+        // Within an argument
+        assert_eq!(
+            cmd("printf 100%%-%u", &["file"])?,
+            ("printf".into(), vec!["100%-file".into()])
+        );
+        // Escaped field codes are not replaced, so arguments are appended
+        assert_eq!(
+            cmd("printf %%f", &["file"])?,
+            ("printf".into(), vec!["%f".into(), "file".into()])
+        );
+        // Unknown field codes are removed
+        assert_eq!(
+            cmd("app --icon%i %F", &["a", "b"])?,
+            ("app".into(), vec!["--icon".into(), "a".into(), "b".into()])
+        );
+        // Along with the argument, if they are all there is to it
+        assert_eq!(
+            cmd("app %i %c %k %F", &["a"])?,
+            ("app".into(), vec!["a".into()])
+        );
+        // Empty arguments that are quoted on purpose stay
+        assert_eq!(
+            cmd(r#"app "" %f"#, &["a"])?,
+            ("app".into(), vec!["".into(), "a".into()])
+        );
+        // Percent signs in the arguments are left alone
+        assert_eq!(
+            cmd("app --file=%f", &["100%%.txt"])?,
+            ("app".into(), vec!["--file=100%%.txt".into()])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn detach() -> Result<()> {
+        let file = tempfile::NamedTempFile::new()?;
+        let path = file.path();
+        // The session id is the sixth field of /proc/<pid>/stat
+        let entry = DesktopEntry {
+            exec: join_exec(&[
+                "sh",
+                "-c",
+                &format!("cut -d ' ' -f 6 /proc/$$/stat > {}", path.display()),
+            ]),
+            ..Default::default()
+        };
+
+        crate::utils::wait_all(
+            entry
+                .exec_inner(vec![], true, true, &ExecOptions::default())?
+                .into_iter()
+                .collect(),
+        )?;
+        let session = std::fs::read_to_string(path)?;
+
+        assert_ne!(session.trim().parse::<i32>().unwrap(), unsafe {
+            libc::getsid(0)
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn dbus_activatable() -> Result<()> {
+        let entry =
+            parse_file(Path::new("tests/org.handlr.Viewer.desktop")).unwrap();
+        assert!(entry.dbus_activatable);
+        assert!(
+            !parse_file(Path::new("tests/cmus.desktop"))
+                .unwrap()
+                .dbus_activatable
+        );
+
+        Ok(())
+    }
+
+    #[cfg(feature = "dbus")]
+    #[test]
+    fn dbus_addresses() -> Result<()> {
+        assert_eq!(
+            dbus_object_path("org.handlr.Image-Viewer"),
+            "/org/handlr/Image_Viewer"
+        );
+        assert_eq!(to_uri("https://example.com/")?, "https://example.com/");
+        assert_eq!(
+            to_uri("tests/rust.vim")?,
+            url::Url::from_file_path(
+                std::env::current_dir()?.join("tests/rust.vim")
+            )
+            .unwrap()
+            .as_str()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invocations() {
+        let invocations = |exec: &str, mode: Mode, split: bool| {
+            DesktopEntry {
+                exec: exec.into(),
+                ..Default::default()
+            }
+            .invocations(
+                mode,
+                vec!["a.mp4".into(), "b.mp4".into()],
+                split,
+            )
+        };
+        let together = vec![vec!["a.mp4".to_owned(), "b.mp4".to_owned()]];
+        let apart = vec![vec!["a.mp4".to_owned()], vec!["b.mp4".to_owned()]];
+
+        assert_eq!(invocations("mpv %F", Mode::Open, false), together);
+        assert_eq!(invocations("mpv %f", Mode::Open, false), apart);
+        // `open --split`
+        assert_eq!(invocations("mpv %F", Mode::Open, true), apart);
+        assert_eq!(invocations("mpv %U", Mode::Open, true), apart);
+        // Launching passes every argument as it is
+        assert_eq!(invocations("mpv %F", Mode::Launch, true), together);
+    }
+
+    #[test]
+    fn terminal_wrapping() {
+        let terminals = || {
+            terminal_programs(
+                [
+                    "tests/org.wezfurlong.wezterm.desktop",
+                    "tests/emacsclient.desktop",
+                ]
+                .iter()
+                .filter_map(|path| parse_file(Path::new(path)).ok()),
+            )
+        };
+        assert_eq!(terminals(), ["wezterm"]);
+
+        let entry = |exec: &str| DesktopEntry {
+            exec: exec.into(),
+            terminal: true,
+            ..Default::default()
+        };
+        let needs_terminal_emulator = |exec: &str, in_terminal: bool| {
+            let entry = entry(exec);
+            let exec = entry.expand_exec(vec!["notes.md".into()]);
+            entry.needs_terminal_emulator(&exec, in_terminal, false, terminals)
+        };
+
+        assert!(needs_terminal_emulator("nvim %f", false));
+        assert!(!needs_terminal_emulator("nvim %f", true));
+        // Already starting a terminal emulator, so it is not wrapped in another
+        assert!(!needs_terminal_emulator("wezterm start -- nvim %f", false));
+        assert!(!needs_terminal_emulator(
+            "/usr/local/bin/wezterm start -- nvim %f",
+            false
+        ));
+        // Or when `--no-term-wrap` is used
+        let nvim = entry("nvim %f");
+        assert!(!nvim.needs_terminal_emulator(
+            &nvim.expand_exec(vec!["notes.md".into()]),
+            false,
+            true,
+            terminals
+        ));
+    }
+
+    #[test]
+    fn env_overrides() -> Result<()> {
+        let entry = DesktopEntry {
+            exec: "firefox %u".into(),
+            file_name: "firefox.desktop".into(),
+            ..Default::default()
+        };
+        let overrides = vec![
+            (
+                "firefox.desktop".to_owned(),
+                [
+                    ("MOZ_ENABLE_WAYLAND", "1"),
+                    ("PROFILE_DIR", "${HOME}/.mozilla/$PROFILE"),
+                    ("UNSET", "$HANDLR_UNSET"),
+                ]
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            ),
+            (
+                "other.desktop".to_owned(),
+                vec![("WINEPREFIX".to_owned(), "/games".to_owned())]
+                    .into_iter()
+                    .collect(),
+            ),
+        ]
+        .into_iter()
+        .collect();
+        let var = |name: &str| match name {
+            "HOME" => Some("/home/user".to_owned()),
+            "PROFILE" => Some("work".to_owned()),
+            _ => None,
+        };
+
+        let env = entry.env_with(&overrides, var);
+        let cmd = entry.command(
+            vec!["https://example.com".into()],
+            true,
+            &ExecOptions::default(),
+            &env,
+        )?;
+
+        assert_eq!(cmd.get_program(), "firefox");
+        assert_eq!(
+            cmd.get_envs()
+                .map(|(k, v)| (
+                    k.to_string_lossy().into_owned(),
+                    v.map(|v| v.to_string_lossy().into_owned())
+                ))
+                .collect::<Vec<_>>(),
+            [
+                ("MOZ_ENABLE_WAYLAND".to_owned(), Some("1".to_owned())),
+                (
+                    "PROFILE_DIR".to_owned(),
+                    Some("/home/user/.mozilla/work".to_owned())
+                ),
+                ("UNSET".to_owned(), Some(String::new())),
+            ]
+        );
+
+        // Other desktop files do not get them
+        let entry = DesktopEntry {
+            file_name: "imv.desktop".into(),
+            ..entry
+        };
+        assert!(entry.env_with(&overrides, var).is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn gpu_offload() -> Result<()> {
+        let envs = |entry: &DesktopEntry, config: &Config, no_gpu_offload| {
+            let env = entry.env_for(config, no_gpu_offload, |_| None);
+            let cmd = entry.command(
+                Vec::new(),
+                true,
+                &ExecOptions::default(),
+                &env,
+            )?;
+            Ok::<_, Error>(
+                cmd.get_envs()
+                    .map(|(k, v)| {
+                        (
+                            k.to_string_lossy().into_owned(),
+                            v.unwrap_or_default()
+                                .to_string_lossy()
+                                .into_owned(),
+                        )
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let mut config = Config::default();
+
+        let entry = parse_file(Path::new("tests/gpu-offload.desktop")).unwrap();
+        assert!(entry.prefers_non_default_gpu);
+        assert_eq!(
+            envs(&entry, &config, false)?,
+            [("DRI_PRIME".to_owned(), "1".to_owned())]
+        );
+        assert!(envs(&entry, &config, true)?.is_empty());
+
+        // env_overrides take priority, i.e. to pick a specific GPU
+        config.env_overrides = vec![(
+            "gpu-offload.desktop".to_owned(),
+            vec![("DRI_PRIME".to_owned(), "pci-0000_01_00_0".to_owned())]
+                .into_iter()
+                .collect(),
+        )]
+        .into_iter()
+        .collect();
+        config
+            .gpu_offload_env
+            .insert("__NV_PRIME_RENDER_OFFLOAD".to_owned(), "1".to_owned());
+        assert_eq!(
+            envs(&entry, &config, false)?,
+            [
+                ("DRI_PRIME".to_owned(), "pci-0000_01_00_0".to_owned()),
+                ("__NV_PRIME_RENDER_OFFLOAD".to_owned(), "1".to_owned())
+            ]
+        );
+
+        // Entries without the key are left alone
+        let entry = parse_file(Path::new("tests/working-dir.desktop")).unwrap();
+        assert!(!entry.prefers_non_default_gpu);
+        assert!(envs(&entry, &config, false)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn working_dir() -> Result<()> {
+        let entry = parse_file(Path::new("tests/working-dir.desktop")).unwrap();
+        assert_eq!(entry.working_dir, Some(PathBuf::from("/tmp")));
+        assert!(entry.is_shown());
+
+        let entry = parse_file(Path::new("tests/empty-path.desktop")).unwrap();
+        assert_eq!(entry.working_dir, None);
+
+        let entry = parse_file(Path::new("tests/no-display.desktop")).unwrap();
+        assert!(entry.no_display && !entry.hidden && !entry.is_shown());
+
+        let file = tempfile::NamedTempFile::new()?;
+        let path = file.path();
+        let entry = DesktopEntry {
+            exec: join_exec(&[
+                "sh",
+                "-c",
+                &format!("pwd > {}", path.display()),
+            ]),
+            working_dir: Some(PathBuf::from("/")),
+            ..Default::default()
+        };
+
+        crate::utils::wait_all(
+            entry
+                .exec_inner(vec![], true, false, &ExecOptions::default())?
+                .into_iter()
+                .collect(),
+        )?;
+        assert_eq!(std::fs::read_to_string(path)?, "/\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_entries() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let problem = |contents: &str| -> Result<String> {
+            let path = dir.path().join("invalid.desktop");
+            std::fs::write(&path, format!("[Desktop Entry]\n{}", contents))?;
+            Ok(parse_file(&path).unwrap_err().to_string())
+        };
+
+        assert_eq!(
+            [
+                problem("Type=Application\nExec=vim %f\n")?,
+                problem("Name=  \nExec=vim %f\n")?,
+                problem("Name=Vim\n")?,
+                problem("Name=Vim\nExec= \n")?,
+                problem("Type=Link\nName=Vim\nURL=https://vim.org\n")?,
+                problem("Type=Directory\nName=Editors\nExec=vim\n")?,
+            ],
+            [
+                "Name is missing",
+                "Name is empty",
+                "Exec is missing",
+                "Exec is empty",
+                "Type is Link, so it cannot open anything",
+                "Type is Directory, so it cannot open anything",
+            ]
+        );
+
+        // The error says which file is bad and why
+        let missing = PathBuf::from("tests/nonexistent.desktop");
+        let e = DesktopEntry::try_from(missing).unwrap_err();
+        assert!(matches!(
+            *e.kind,
+            ErrorKind::BadEntry {
+                problem: EntryProblem::ParseFailure(_),
+                ..
+            }
+        ));
+        assert!(e.to_string().starts_with(
+            "malformed desktop entry at tests/nonexistent.desktop: it could not be read: "
+        ));
+
+        Ok(())
     }
 }
@@ -1,12 +1,21 @@
 use crate::{
-    common::{DesktopEntry, ExecMode},
-    Error, ErrorKind, RegexHandler, Result,
+    apps::SystemApps,
+    common::{DesktopEntry, ExecMode, ExecOptions},
+    utils, Error, ErrorKind, NotFoundKind, RegexHandler, Result, CONFIG,
 };
+use serde::{Deserialize, Serialize};
 use std::{
-    convert::TryFrom, ffi::OsString, fmt::Display, path::PathBuf, str::FromStr,
+    convert::TryFrom,
+    ffi::OsString,
+    fmt::Display,
+    path::{Path, PathBuf},
+    process::Child,
+    str::FromStr,
 };
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize,
+)]
 pub struct Handler(OsString);
 
 impl Display for Handler {
@@ -26,40 +35,392 @@ impl Handler {
     pub fn assume_valid(name: OsString) -> Self {
         Self(name)
     }
+    /// Find the desktop file for a handler
+    ///
+    /// Handlers are usually the name of a desktop file in $XDG_DATA_DIRS/applications,
+    /// but can also be an absolute path to a desktop file anywhere else.
+    /// `extra_app_dirs` from handlr.toml, i.e. Flatpak's exports, are searched first.
     pub fn get_path(name: &std::ffi::OsStr) -> Option<PathBuf> {
-        let mut path = PathBuf::from("applications");
-        path.push(name);
-        xdg::BaseDirectories::new().ok()?.find_data_file(path)
+        Self::get_path_with(name, &CONFIG.extra_app_dirs)
+    }
+    /// Like `get_path`, searching `extra_app_dirs` before the XDG directories
+    fn get_path_with(
+        name: &std::ffi::OsStr,
+        extra_app_dirs: &[PathBuf],
+    ) -> Option<PathBuf> {
+        let path = Path::new(name);
+        if path.is_absolute() {
+            return path.is_file().then(|| path.to_owned());
+        }
+
+        extra_app_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+            .or_else(|| {
+                xdg::BaseDirectories::new()
+                    .ok()?
+                    .find_data_file(Path::new("applications").join(name))
+            })
     }
     pub fn resolve(name: OsString) -> Result<Self> {
         let path = Self::get_path(&name).ok_or_else(|| {
-            ErrorKind::NotFound(name.to_string_lossy().into())
+            if Path::new(&name).is_absolute() {
+                NotFoundKind::DesktopFilePath(name.clone().into())
+            } else {
+                NotFoundKind::DesktopFile(name.to_string_lossy().into())
+            }
         })?;
         DesktopEntry::try_from(path)?;
         Ok(Self(name))
     }
+    /// Find the installed desktop file that `name` most likely meant, along with its app's name
+    ///
+    /// Every installed entry is loaded, so this is only meant for reporting a handler that
+    /// could not be resolved, not for every lookup.
+    pub fn suggest(name: &str) -> Option<(String, String)> {
+        suggest(name, SystemApps::get_entries().ok()?).map(
+            |(file_name, entry)| {
+                (file_name.to_string_lossy().into(), entry.name)
+            },
+        )
+    }
+    /// Check that the desktop file at `path` can be linked into $XDG_DATA_HOME/applications
+    /// with `install`, returning a handler that refers to it by name like any installed desktop file
+    ///
+    /// Other programs reading mimeapps.list only understand desktop file names, not paths.
+    /// Nothing is linked yet, so the changes can be confirmed first.
+    pub fn installable(path: &Path) -> Result<Self> {
+        let (path, _) = Self::install_link(path)?;
+        Ok(Self(path.file_name().unwrap().to_owned()))
+    }
+    /// Link the desktop file at `path` into $XDG_DATA_HOME/applications,
+    /// see `installable`
+    pub fn install(path: &Path) -> Result<()> {
+        let (path, link) = Self::install_link(path)?;
+        // Installing the same file again changes nothing
+        if std::fs::read_link(&link).ok() != Some(path.clone()) {
+            let link = xdg::BaseDirectories::new()?.place_data_file(
+                Path::new("applications").join(link.file_name().unwrap()),
+            )?;
+            std::os::unix::fs::symlink(path, link)?;
+        }
+
+        Ok(())
+    }
+    /// The absolute path of the desktop file at `path` and where to link it,
+    /// failing if something else is already there
+    fn install_link(path: &Path) -> Result<(PathBuf, PathBuf)> {
+        let path = path
+            .canonicalize()
+            .map_err(|_| NotFoundKind::DesktopFilePath(path.to_owned()))?;
+        Self::resolve(path.clone().into())?;
+        let name = path
+            .file_name()
+            .ok_or_else(|| NotFoundKind::DesktopFilePath(path.clone()))?;
+
+        let link = xdg::BaseDirectories::new()?
+            .get_data_home()
+            .join("applications")
+            .join(name);
+        if link.symlink_metadata().is_ok()
+            && std::fs::read_link(&link).ok().as_deref() != Some(&path)
+        {
+            return Err(ErrorKind::AlreadyInstalled(link).into());
+        }
+
+        Ok((path, link))
+    }
     pub fn get_entry(&self) -> Result<DesktopEntry> {
-        DesktopEntry::try_from(Self::get_path(&self.0).unwrap())
+        let path = Self::get_path(&self.0).ok_or_else(|| {
+            if Path::new(&self.0).is_absolute() {
+                NotFoundKind::DesktopFilePath(self.0.clone().into())
+            } else {
+                NotFoundKind::DesktopFile(self.to_string())
+            }
+        })?;
+        DesktopEntry::try_from(path)
     }
-    pub fn launch(&self, args: Vec<String>) -> Result<()> {
-        self.get_entry()?.exec(ExecMode::Launch, args)
+    /// Get the entry from the first of the given directories that has it,
+    /// instead of searching $XDG_DATA_DIRS
+    pub fn get_entry_in(
+        &self,
+        application_dirs: &[PathBuf],
+    ) -> Result<DesktopEntry> {
+        application_dirs
+            .iter()
+            .map(|dir| dir.join(&self.0))
+            .find(|path| path.exists())
+            .ok_or_else(|| {
+                Error::from(NotFoundKind::DesktopFile(self.to_string()))
+            })
+            .and_then(DesktopEntry::try_from)
     }
-    pub fn open(&self, args: Vec<String>) -> Result<()> {
-        self.get_entry()?.exec(ExecMode::Open, args)
+    /// Get the entry to run, using the given action's Exec if there is one
+    fn get_entry_for(&self, action: Option<&str>) -> Result<DesktopEntry> {
+        let entry = self.get_entry()?;
+        match action {
+            Some(action) => entry.with_action(action),
+            None => Ok(entry),
+        }
+    }
+    pub fn launch(
+        &self,
+        args: Vec<String>,
+        wait: bool,
+        detach: bool,
+        action: Option<&str>,
+        options: &ExecOptions,
+    ) -> Result<()> {
+        let children = self.get_entry_for(action)?.exec(
+            ExecMode::Launch,
+            args,
+            wait,
+            detach,
+            options,
+        )?;
+        if wait {
+            utils::wait_all(children)?;
+        }
+        Ok(())
+    }
+    /// Open `args`, returning the processes that were started
+    ///
+    /// They are left for the caller to wait for if `wait` is set,
+    /// except for terminal programs run in the foreground, see `DesktopEntry::exec`.
+    pub fn open(
+        &self,
+        args: Vec<String>,
+        wait: bool,
+        action: Option<&str>,
+        options: &ExecOptions,
+    ) -> Result<Vec<Child>> {
+        self.get_entry_for(action)?.exec(
+            ExecMode::Open,
+            args,
+            wait,
+            false,
+            options,
+        )
     }
 }
 
-#[derive(PartialEq, Eq, Hash)]
+/// Find the installed entry that a desktop file name that does not exist most likely meant
+///
+/// Entries whose file name contains the given name without `.desktop` are preferred,
+/// followed by entries whose `Name` or `GenericName` contains it, ignoring case.
+/// Shorter file names win ties, since they are closer to the given name.
+fn suggest(
+    name: &str,
+    entries: impl Iterator<Item = (OsString, DesktopEntry)>,
+) -> Option<(OsString, DesktopEntry)> {
+    let query = name.trim_end_matches(".desktop").to_lowercase();
+    if query.is_empty() {
+        return None;
+    }
+
+    entries
+        .filter_map(|(file_name, entry)| {
+            let file_matches =
+                file_name.to_string_lossy().to_lowercase().contains(&query);
+            let name_matches = std::iter::once(&entry.name)
+                .chain(&entry.generic_name)
+                .any(|name| name.to_lowercase().contains(&query));
+
+            let rank = match (file_matches, name_matches) {
+                (true, _) => 0,
+                (false, true) => 1,
+                (false, false) => return None,
+            };
+            Some((rank, file_name.len(), file_name, entry))
+        })
+        .min_by(|a, b| (a.0, a.1, &a.2).cmp(&(b.0, b.1, &b.2)))
+        .map(|(_, _, file_name, entry)| (file_name, entry))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum GenericHandler {
     Handler(Handler),
     RegexHandler(RegexHandler),
 }
 
+impl FromStr for GenericHandler {
+    type Err = Error;
+    /// Desktop files are checked to be installed,
+    /// anything else is treated as a command like a regex handler's `exec`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(if s.ends_with(".desktop") {
+            Self::Handler(Handler::from_str(s)?)
+        } else {
+            Self::RegexHandler(RegexHandler::from_exec(s))
+        })
+    }
+}
+
+impl Display for GenericHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenericHandler::Handler(handler) => handler.fmt(f),
+            GenericHandler::RegexHandler(handler) => {
+                f.write_str(handler.exec())
+            }
+        }
+    }
+}
+
 impl GenericHandler {
-    pub fn open(&self, args: Vec<String>) -> Result<()> {
+    pub fn launch(
+        &self,
+        args: Vec<String>,
+        wait: bool,
+        detach: bool,
+        action: Option<&str>,
+        options: &ExecOptions,
+    ) -> Result<()> {
         match self {
-            GenericHandler::Handler(handler) => handler.open(args),
-            GenericHandler::RegexHandler(handler) => handler.open(args),
+            GenericHandler::Handler(handler) => {
+                handler.launch(args, wait, detach, action, options)
+            }
+            GenericHandler::RegexHandler(handler) => {
+                handler.launch(args, wait, detach, options)
+            }
         }
     }
+    pub fn open(
+        &self,
+        args: Vec<String>,
+        wait: bool,
+        action: Option<&str>,
+        options: &ExecOptions,
+    ) -> Result<Vec<Child>> {
+        match self {
+            GenericHandler::Handler(handler) => {
+                handler.open(args, wait, action, options)
+            }
+            GenericHandler::RegexHandler(handler) => {
+                handler.open(args, wait, options)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extra_app_dirs() {
+        let name = std::ffi::OsStr::new("cmus.desktop");
+
+        assert_eq!(
+            Handler::get_path_with(
+                name,
+                &[PathBuf::from("nonexistent"), PathBuf::from("tests")]
+            ),
+            Some(PathBuf::from("tests/cmus.desktop"))
+        );
+        // Only files count, not directories with the same name
+        assert_eq!(
+            Handler::get_path_with(
+                std::ffi::OsStr::new("shadowing"),
+                &[PathBuf::from("tests")]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn generic_handler_from_str() -> Result<()> {
+        assert_eq!(
+            GenericHandler::from_str("gimp %f")?,
+            GenericHandler::RegexHandler(RegexHandler::from_exec("gimp %f"))
+        );
+        assert!(matches!(
+            *GenericHandler::from_str("nonexistent.desktop")
+                .unwrap_err()
+                .kind,
+            ErrorKind::NotFound(NotFoundKind::DesktopFile(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn absolute_paths() -> Result<()> {
+        let path = std::env::current_dir()?.join("tests/cmus.desktop");
+        let handler = Handler::resolve(path.clone().into())?;
+        assert_eq!(handler.to_string(), path.to_string_lossy());
+        assert_eq!(
+            handler.get_entry()?.exec,
+            DesktopEntry::try_from(path)?.exec
+        );
+
+        // Missing paths are told apart from names that are not installed
+        let missing =
+            std::env::current_dir()?.join("tests/nonexistent.desktop");
+        let error = Handler::resolve(missing.clone().into()).unwrap_err();
+        assert!(matches!(
+            &*error.kind,
+            ErrorKind::NotFound(NotFoundKind::DesktopFilePath(path)) if *path == missing
+        ));
+        assert_eq!(
+            error.to_string(),
+            format!("desktop file '{}' does not exist", missing.display())
+        );
+        assert!(matches!(
+            *Handler::assume_valid(missing.into())
+                .get_entry()
+                .unwrap_err()
+                .kind,
+            ErrorKind::NotFound(NotFoundKind::DesktopFilePath(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn suggestions() -> Result<()> {
+        let entries = || {
+            ["cmus.desktop", "emacsclient.desktop", "working-dir.desktop"]
+                .iter()
+                .map(|name| -> Result<(OsString, DesktopEntry)> {
+                    let path = PathBuf::from("tests").join(name);
+                    Ok((OsString::from(name), DesktopEntry::try_from(path)?))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let suggestion = |name: &str| -> Result<Option<String>> {
+            Ok(suggest(name, entries()?.into_iter())
+                .map(|(file_name, _)| file_name.to_string_lossy().into()))
+        };
+
+        // By file name
+        assert_eq!(
+            suggestion("emacs.desktop")?.as_deref(),
+            Some("emacsclient.desktop")
+        );
+        assert_eq!(
+            suggestion("CMUS.desktop")?.as_deref(),
+            Some("cmus.desktop")
+        );
+        // By name, ignoring case
+        assert_eq!(
+            suggestion("directory.desktop")?.as_deref(),
+            Some("working-dir.desktop")
+        );
+        assert_eq!(
+            suggestion("cmus-remote.desktop")?.as_deref(),
+            Some("cmus.desktop")
+        );
+        // By generic name
+        assert_eq!(
+            suggestion("text editor.desktop")?.as_deref(),
+            Some("emacsclient.desktop")
+        );
+
+        assert_eq!(suggestion("nomacs.desktop")?, None);
+        assert_eq!(suggestion(".desktop")?, None);
+
+        Ok(())
+    }
 }
@@ -1,17 +1,31 @@
-use crate::{Error, ErrorKind, Result};
+use crate::{
+    apps::wildcard_matches, common::UserPath, Error, ErrorKind, Result, CONFIG,
+};
 use mime::Mime;
-use std::{convert::TryFrom, path::Path, str::FromStr};
+use once_cell::sync::Lazy;
+use std::{convert::TryFrom, io::Read, path::Path, str::FromStr};
 use url::Url;
 
+/// The shared-mime-info database, loaded once since reading it takes milliseconds
+static MIME_DB: Lazy<xdg_mime::SharedMimeInfo> =
+    Lazy::new(xdg_mime::SharedMimeInfo::new);
+
+/// The mimes a file name could be going by its name alone, see `MimeType::from_file_name`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameMatch {
+    /// More than one if the name alone is ambiguous, none if no glob matches it
+    pub mimes: Vec<Mime>,
+    /// The glob from the database that matched the name
+    pub glob: Option<String>,
+}
+
 // A mime derived from a path or URL
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MimeType(pub Mime);
 
 impl MimeType {
     fn from_ext(ext: &str) -> Result<Mime> {
-        match &*xdg_mime::SharedMimeInfo::new()
-            .get_mime_types_from_file_name(ext)
-        {
+        match &*MIME_DB.get_mime_types_from_file_name(ext) {
             [m] if m == &mime::APPLICATION_OCTET_STREAM => {
                 Err(Error::from(ErrorKind::Ambiguous(ext.into())))
             }
@@ -19,6 +33,74 @@ impl MimeType {
             [] => unreachable!(),
         }
     }
+
+    /// Get every mime a file name could be from the database's globs alone,
+    /// without touching the filesystem
+    pub fn from_file_name(name: &str) -> NameMatch {
+        let lookup = |name: &str| {
+            match MIME_DB.get_mime_types_from_file_name(name).as_slice() {
+                [m] if m == &mime::APPLICATION_OCTET_STREAM => Vec::new(),
+                mimes => {
+                    let mut mimes = mimes.to_vec();
+                    // Tied globs come back in no particular order
+                    mimes.sort_by(|a, b| a.essence_str().cmp(b.essence_str()));
+                    mimes
+                }
+            }
+        };
+        let mimes = lookup(name);
+
+        // The database does not say which glob matched, but the shortest extension
+        // giving the same mimes is the glob's, i.e. `*.tar.zst` for `a.v2.tar.zst`.
+        // Names without one matched a literal glob, i.e. `Makefile`.
+        let glob = (!mimes.is_empty()).then(|| {
+            name.rmatch_indices('.')
+                .map(|(i, _)| &name[i..])
+                .find(|ext| lookup(ext) == mimes)
+                .map_or_else(|| name.to_owned(), |ext| format!("*{}", ext))
+        });
+
+        NameMatch { mimes, glob }
+    }
+
+    /// Get the mime of a file, checking its contents instead of trusting its extension
+    /// when the extension's mime matches one of `prefer_content`, i.e. `video/*`
+    ///
+    /// The detected mime is only used if it is more specific than the extension's.
+    pub fn from_path_with(
+        path: &Path,
+        prefer_content: &[String],
+    ) -> Result<Self> {
+        let db = &*MIME_DB;
+
+        let mut guess = db.guess_mime_type();
+        guess.file_name(path.to_str().unwrap());
+
+        let mime = match mime_to_option(db, guess.guess().mime_type().clone()) {
+            Some(mime)
+                if prefer_content.iter().any(|pattern| {
+                    wildcard_matches(pattern, mime.essence_str())
+                }) =>
+            {
+                sniff(db, path, &mime).unwrap_or(mime)
+            }
+            Some(mime) => mime,
+            None => {
+                mime_to_option(db, guess.path(path).guess().mime_type().clone())
+                    .ok_or_else(|| ErrorKind::Ambiguous(path.to_owned()))?
+            }
+        };
+
+        Ok(Self(mime))
+    }
+
+    /// Get the standard mime that this mime is an alias of, if any,
+    /// i.e. `application/pdf` for `application/x-pdf`
+    pub fn canonical(&self) -> Mime {
+        MIME_DB
+            .unalias_mime_type(&self.0)
+            .unwrap_or_else(|| self.0.clone())
+    }
 }
 
 impl From<&Url> for MimeType {
@@ -34,22 +116,34 @@ impl From<&Url> for MimeType {
 impl TryFrom<&Path> for MimeType {
     type Error = Error;
     fn try_from(path: &Path) -> Result<Self> {
-        let db = xdg_mime::SharedMimeInfo::new();
+        Self::from_path_with(path, &[])
+    }
+}
 
-        let mut guess = db.guess_mime_type();
-        guess.file_name(path.to_str().unwrap());
+/// Most of a file read to detect its mime from its contents
+const SNIFF_LIMIT: u64 = 64 * 1024;
 
-        let mime = if let Some(mime) =
-            mime_to_option(&db, guess.guess().mime_type().clone())
-        {
-            mime
-        } else {
-            mime_to_option(&db, guess.path(path).guess().mime_type().clone())
-                .ok_or_else(|| ErrorKind::Ambiguous(path.to_owned()))?
-        };
+/// Detect a file's mime from its contents,
+/// if that differs from and is more specific than `by_name`
+fn sniff(
+    db: &xdg_mime::SharedMimeInfo,
+    path: &Path,
+    by_name: &Mime,
+) -> Option<Mime> {
+    let mut data = Vec::new();
+    std::fs::File::open(path)
+        .ok()?
+        .take(SNIFF_LIMIT)
+        .read_to_end(&mut data)
+        .ok()?;
 
-        Ok(Self(mime))
-    }
+    let (sniffed, _) = db.get_mime_type_for_data(&data)?;
+    let sniffed = mime_to_option(db, sniffed)?;
+
+    // i.e. text/plain for a text/csv file is less specific, not a different type
+    (!db.mime_type_equal(&sniffed, by_name)
+        && !db.mime_type_subclass(by_name, &sniffed))
+    .then_some(sniffed)
 }
 
 fn mime_to_option(db: &xdg_mime::SharedMimeInfo, mime: Mime) -> Option<Mime> {
@@ -86,6 +180,31 @@ impl FromStr for MimeOrExtension {
     }
 }
 
+// Mime derived from user input: a path/URL, or anything MimeOrExtension accepts
+#[derive(Debug, Clone)]
+pub struct MimeExtOrPath(pub Mime);
+
+impl FromStr for MimeExtOrPath {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let path = UserPath::from_str(s)?;
+        let mime = match path.to_file_path() {
+            None => path.get_mime()?,
+            // Only existing files, since mimes also look like relative paths
+            Some(file) if file.exists() => {
+                MimeType::from_path_with(
+                    file,
+                    &CONFIG.prefer_content_detection,
+                )?
+                .0
+            }
+            Some(_) => MimeOrExtension::from_str(s)?.0,
+        };
+
+        Ok(Self(mime))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,6 +223,59 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn prefer_content_detection() -> Result<()> {
+        let mime = |path: &str, prefer_content: &[&str]| {
+            MimeType::from_path_with(
+                Path::new(path),
+                &prefer_content
+                    .iter()
+                    .map(|pattern| pattern.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .map(|mime| mime.0.essence_str().to_owned())
+        };
+
+        // Extensions are trusted by default
+        assert_eq!(mime("tests/mislabeled.mp4", &[])?, "video/mp4");
+        assert_eq!(mime("tests/mislabeled.txt", &[])?, "text/plain");
+
+        // Contents are checked for matching mimes
+        assert_eq!(
+            mime("tests/mislabeled.mp4", &["text/*", "video/*"])?,
+            "application/x-matroska"
+        );
+        assert_eq!(
+            mime("tests/mislabeled.txt", &["text/*", "video/*"])?,
+            "application/pdf"
+        );
+        assert_eq!(mime("tests/mislabeled.txt", &["video/*"])?, "text/plain");
+
+        // Contents that are no more specific than the extension are ignored
+        assert_eq!(mime("tests/rust.vim", &["text/*"])?, "text/plain");
+        assert_eq!(mime("tests/no_html_tags.html", &["text/*"])?, "text/html");
+
+        Ok(())
+    }
+
+    #[test]
+    fn user_input_with_paths() -> Result<()> {
+        assert_eq!(
+            MimeExtOrPath::from_str("./tests/rust.vim")?.0,
+            "text/plain"
+        );
+        assert_eq!(
+            MimeExtOrPath::from_str("https://example.com")?.0,
+            "x-scheme-handler/https"
+        );
+        assert_eq!(MimeExtOrPath::from_str(".pdf")?.0, mime::APPLICATION_PDF);
+        assert_eq!(MimeExtOrPath::from_str("image/jpeg")?.0, mime::IMAGE_JPEG);
+
+        "./tests/nonexistent".parse::<MimeExtOrPath>().unwrap_err();
+
+        Ok(())
+    }
+
     #[test]
     fn from_path() -> Result<()> {
         assert_eq!(
@@ -150,4 +322,84 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn compound_extensions() -> Result<()> {
+        // The longest matching glob wins, so `.tar.gz` is not just gzip
+        assert_eq!(
+            ".tar.gz".parse::<MimeOrExtension>()?.0,
+            "application/x-compressed-tar"
+        );
+        assert_eq!(
+            ".tar.bz2".parse::<MimeOrExtension>()?.0,
+            "application/x-bzip-compressed-tar"
+        );
+        assert_eq!(".gz".parse::<MimeOrExtension>()?.0, "application/gzip");
+
+        for (path, mime) in [
+            ("foo.tar.gz", "application/x-compressed-tar"),
+            ("foo.tar.xz", "application/x-xz-compressed-tar"),
+            ("foo.gz", "application/gzip"),
+            (".tar.gz", "application/x-compressed-tar"),
+        ]
+        .iter()
+        {
+            assert_eq!(MimeType::try_from(Path::new(path))?.0, *mime);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn from_file_name() -> Result<()> {
+        let name_match = |name: &str| {
+            let NameMatch { mimes, glob } = MimeType::from_file_name(name);
+            (
+                mimes
+                    .iter()
+                    .map(|mime| mime.essence_str().to_owned())
+                    .collect::<Vec<_>>(),
+                glob,
+            )
+        };
+
+        assert_eq!(
+            name_match("report-final.v2.tar.zst"),
+            (
+                vec!["application/x-zstd-compressed-tar".to_owned()],
+                Some("*.tar.zst".to_owned())
+            )
+        );
+        assert_eq!(
+            name_match("Makefile"),
+            (
+                vec!["text/x-makefile".to_owned()],
+                Some("Makefile".to_owned())
+            )
+        );
+        // Ambiguous names give every candidate instead of an error
+        // What they are depends on the installed mime database
+        let (candidates, glob) = name_match("a.ts");
+        assert!(candidates.len() >= 2, "{:?}", candidates);
+        assert_eq!(glob.as_deref(), Some("*.ts"));
+        assert_eq!(name_match("a.handlr-unknown"), (vec![], None));
+
+        // Nothing is read, so a name that does not exist is no different
+        assert_eq!(name_match("tests/nonexistent.pdf").0, ["application/pdf"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn canonical() -> Result<()> {
+        let canonical = |mime: &str| -> Result<Mime> {
+            Ok(MimeType(Mime::from_str(mime)?).canonical())
+        };
+
+        assert_eq!(canonical("application/x-pdf")?, mime::APPLICATION_PDF);
+        assert_eq!(canonical("application/pdf")?, mime::APPLICATION_PDF);
+        assert_eq!(canonical("text/x-handlr")?, "text/x-handlr");
+
+        Ok(())
+    }
 }
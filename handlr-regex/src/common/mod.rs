@@ -3,11 +3,16 @@ mod desktop_entry;
 mod handler;
 mod mime_types;
 mod path;
+mod portal;
 mod table;
 
 pub use self::db::autocomplete as db_autocomplete;
-pub use desktop_entry::{DesktopEntry, Mode as ExecMode};
+pub(crate) use desktop_entry::{join_exec, locales, split_exec};
+pub use desktop_entry::{
+    DesktopAction, DesktopEntry, ExecOptions, Mode as ExecMode,
+};
 pub use handler::{GenericHandler, Handler};
-pub use mime_types::{MimeOrExtension, MimeType};
-pub use path::{mime_table, UserPath};
+pub use mime_types::{MimeExtOrPath, MimeOrExtension, MimeType, NameMatch};
+pub use path::{mime_table, DirWalk, MimeMode, UserPath};
+pub(crate) use portal::open_with_chooser;
 pub use table::render_table;
@@ -1,56 +1,326 @@
 use mime::Mime;
+use percent_encoding::{AsciiSet, CONTROLS};
 use serde::Serialize;
 use tabled::Tabled;
 use url::Url;
 
-use crate::{common::MimeType, render_table, Error, ErrorKind, Result};
+use crate::{
+    common::{MimeType, NameMatch},
+    render_table, Error, ErrorKind, Result, CONFIG,
+};
 use std::{
-    convert::TryFrom,
+    ffi::OsStr,
     fmt::{Display, Formatter},
-    path::PathBuf,
+    io::Write,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
+/// Schemes whose URIs are just data after the colon, like `mailto:someone@example.com`,
+/// which are always treated as URLs, however messy the data is
+const OPAQUE_SCHEMES: [&str; 4] = ["mailto", "tel", "sms", "magnet"];
+
+/// Characters that are percent-encoded in the data of opaque URIs
+///
+/// `?`, `&`, `=`, `#` and `%` are kept, so queries and existing escapes work as they are.
+const OPAQUE_ENCODE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'<')
+    .add(b'>')
+    .add(b'\\')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// A path or URL given to handlr
+///
+/// Both keep the argument exactly as it was given as `original`,
+/// since the URL or path is normalized, i.e. `HTTPS://Example.com` becomes `https://example.com/`
+/// and `file:///tmp/a%3Fb` becomes `/tmp/a?b`.
 #[derive(Clone)]
 pub enum UserPath {
-    Url(Url),
-    File(PathBuf),
+    Url { url: Url, original: String },
+    File { path: PathBuf, original: String },
 }
 
 impl UserPath {
+    /// A local file that was not given as an argument, i.e. found by expanding a glob
+    pub fn file(path: PathBuf) -> Self {
+        Self::File {
+            original: path.to_string_lossy().into_owned(),
+            path,
+        }
+    }
+
+    /// A URL that was not given as an argument
+    pub fn url(url: Url) -> Self {
+        Self::Url {
+            original: url.to_string(),
+            url,
+        }
+    }
+
+    /// The argument as it was given, before normalization
+    ///
+    /// This is what regex handlers are matched against,
+    /// so query strings, fragments and percent-encoding are kept as they were.
+    /// Paths found by expanding globs or directories have no argument of their own,
+    /// so their path is returned, lossily converted to UTF-8.
+    pub fn original(&self) -> &str {
+        match self {
+            Self::Url { original, .. } | Self::File { original, .. } => {
+                original
+            }
+        }
+    }
+
+    /// Expand a shell glob pattern into the local files it matches, sorted by path
+    pub fn glob_expand(pattern: &str) -> Result<Vec<UserPath>> {
+        Ok(glob::glob(pattern)?
+            .filter_map(|path| path.ok())
+            .map(UserPath::file)
+            .collect())
+    }
+
+    /// Expand paths that do not exist as glob patterns,
+    /// for when handlr is not run from a shell that expands them itself
+    ///
+    /// Invalid patterns and ones that do not match anything are kept as they are.
+    pub fn expand_globs(paths: Vec<UserPath>) -> Vec<UserPath> {
+        let mut expanded = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            match path.to_file_path() {
+                Some(file) if !file.exists() => {
+                    let matches = file
+                        .to_str()
+                        .and_then(|pattern| Self::glob_expand(pattern).ok())
+                        .unwrap_or_default();
+
+                    if matches.is_empty() {
+                        expanded.push(path);
+                    } else {
+                        expanded.extend(matches);
+                    }
+                }
+                _ => expanded.push(path),
+            }
+        }
+
+        expanded
+    }
+
+    /// Fail with every local file in `paths` that does not exist
+    ///
+    /// This is checked before looking for handlers, so a typo does not start an app
+    /// that shows its own error. URLs are never checked.
+    pub fn ensure_exist(paths: &[UserPath]) -> Result<()> {
+        let mut missing = paths
+            .iter()
+            .filter_map(|path| match path {
+                Self::File { path, .. } if !path.exists() => {
+                    Some(Error::from(ErrorKind::NonexistentFile(path.clone())))
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        match missing.len() {
+            0 => Ok(()),
+            1 => Err(missing.remove(0)),
+            _ => Err(ErrorKind::OpenErrors(missing).into()),
+        }
+    }
+
+    /// Whether this is a local file rather than a URL
+    #[inline]
+    pub fn is_local(&self) -> bool {
+        matches!(self, Self::File { .. })
+    }
+
+    /// Get the path of a local file, or `None` for a URL
+    #[inline]
+    pub fn to_file_path(&self) -> Option<&Path> {
+        match self {
+            Self::File { path, .. } => Some(path),
+            Self::Url { .. } => None,
+        }
+    }
+
+    /// Resolve symlinks in a local file's path, so its mime is that of the file it points to
+    ///
+    /// URLs are returned unchanged, and the argument as it was given is kept either way.
+    pub fn canonicalize(&self) -> Result<UserPath> {
+        Ok(match self {
+            Self::File { path, original } => Self::File {
+                path: std::fs::canonicalize(path)?,
+                original: original.clone(),
+            },
+            Self::Url { .. } => self.clone(),
+        })
+    }
+
     pub fn get_mime(&self) -> Result<Mime> {
+        self.get_mime_with(&[])
+    }
+
+    /// Like `get_mime`, but checking the contents of files
+    /// whose extension's mime matches one of `prefer_content`,
+    /// see `prefer_content_detection` in handlr.toml
+    pub fn get_mime_with(&self, prefer_content: &[String]) -> Result<Mime> {
         Ok(match self {
-            Self::Url(url) => Ok(url.into()),
-            Self::File(f) => MimeType::try_from(f.as_path()),
+            Self::Url { url, .. } => Ok(url.into()),
+            Self::File { path, .. } => {
+                MimeType::from_path_with(path, prefer_content)
+            }
         }?
         .0)
     }
+
+    /// Get the mimes this could be from its name alone, without touching the filesystem
+    pub fn get_name_match(&self) -> NameMatch {
+        match self {
+            Self::Url { url, .. } => NameMatch {
+                mimes: vec![MimeType::from(url).0],
+                glob: None,
+            },
+            Self::File { path, .. } => MimeType::from_file_name(
+                &path
+                    .file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy(),
+            ),
+        }
+    }
 }
 
 impl FromStr for UserPath {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let original = s.to_owned();
+        if let Some(url) = parse_opaque(s) {
+            return Ok(Self::Url { url, original });
+        }
+
         let normalized = match url::Url::parse(s) {
             Ok(url) if url.scheme() == "file" => {
                 let path = url.to_file_path().map_err(|_| {
                     Error::from(ErrorKind::BadPath(url.path().to_owned()))
                 })?;
 
-                Self::File(path)
+                // Some programs do not percent-encode file URLs properly,
+                // so a file named like the encoded path is used as a fallback
+                Self::File {
+                    path: existing_or(path, || PathBuf::from(url.path())),
+                    original,
+                }
             }
-            Ok(url) => Self::Url(url),
-            _ => Self::File(PathBuf::from(s)),
+            Ok(url) => Self::Url { url, original },
+            // Likewise, some programs pass percent-encoded paths without a file:// prefix
+            _ => Self::File {
+                path: existing_or(PathBuf::from(s), || {
+                    PathBuf::from(OsStr::from_bytes(
+                        &percent_encoding::percent_decode_str(s)
+                            .collect::<Vec<_>>(),
+                    ))
+                }),
+                original,
+            },
         };
 
         Ok(normalized)
     }
 }
 
+/// Which files to open when opening a directory recursively
+#[derive(Debug, Clone, Default)]
+pub struct DirWalk {
+    /// How many directories deep to go, where 1 is only the directory's own files
+    pub max_depth: Option<usize>,
+    /// Only open files whose path relative to the directory matches one of these
+    pub include: Vec<glob::Pattern>,
+    /// Skip files whose path relative to the directory matches one of these
+    pub exclude: Vec<glob::Pattern>,
+}
+
+impl DirWalk {
+    /// Replace directories with the files in them, sorted by path
+    ///
+    /// Symlinks are followed, but symlink loops are skipped with a warning.
+    pub fn expand(&self, paths: Vec<UserPath>) -> Vec<UserPath> {
+        paths
+            .into_iter()
+            .flat_map(|path| match path {
+                UserPath::File { path: dir, .. } if dir.is_dir() => {
+                    self.files(&dir).into_iter().map(UserPath::file).collect()
+                }
+                path => vec![path],
+            })
+            .collect()
+    }
+
+    fn files(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut walk = walkdir::WalkDir::new(dir)
+            .follow_links(true)
+            .sort_by_file_name();
+        if let Some(max_depth) = self.max_depth {
+            walk = walk.max_depth(max_depth);
+        }
+
+        walk.into_iter()
+            .filter_map(|entry| {
+                entry.map_err(|e| tracing::warn!("skipping {}", e)).ok()
+            })
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                let relative = path.strip_prefix(dir).unwrap_or(path);
+                (self.include.is_empty()
+                    || self.include.iter().any(|p| p.matches_path(relative)))
+                    && !self.exclude.iter().any(|p| p.matches_path(relative))
+            })
+            .collect()
+    }
+}
+
+/// Parse a URI with one of `OPAQUE_SCHEMES`, percent-encoding characters
+/// like spaces that mail clients and the like often leave in them
+fn parse_opaque(s: &str) -> Option<Url> {
+    let (scheme, data) = s.trim().split_once(':')?;
+    if !OPAQUE_SCHEMES
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(scheme))
+    {
+        return None;
+    }
+
+    let data = percent_encoding::utf8_percent_encode(data, OPAQUE_ENCODE);
+    Url::parse(&format!("{}:{}", scheme.to_ascii_lowercase(), data)).ok()
+}
+
+/// Use `path` unless only the fallback exists
+fn existing_or(path: PathBuf, fallback: impl FnOnce() -> PathBuf) -> PathBuf {
+    if path.exists() {
+        return path;
+    }
+
+    let fallback = fallback();
+    if fallback.exists() {
+        fallback
+    } else {
+        path
+    }
+}
+
 impl Display for UserPath {
     fn fmt(&self, fmt: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
-            Self::File(f) => fmt.write_str(&f.to_string_lossy()),
-            Self::Url(u) => fmt.write_str(u.as_ref()),
+            Self::File { path, .. } => fmt.write_str(&path.to_string_lossy()),
+            Self::Url { url, .. } => fmt.write_str(url.as_ref()),
         }
     }
 }
@@ -66,12 +336,80 @@ impl UserPathTable {
     fn new(path: &UserPath) -> Result<Self> {
         Ok(Self {
             path: path.to_string(),
-            mime: path.get_mime()?.essence_str().to_owned(),
+            mime: path
+                .canonicalize()
+                .as_ref()
+                .unwrap_or(path)
+                .get_mime_with(&CONFIG.prefer_content_detection)?
+                .essence_str()
+                .to_owned(),
         })
     }
 }
 
-pub fn mime_table(paths: &[UserPath], output_json: bool) -> Result<()> {
+/// Internal helper struct for a path's possible mimes from `handlr mime --hypothetical`
+#[derive(Tabled, Serialize)]
+struct HypotheticalTable {
+    path: String,
+    #[tabled(display_with("Self::display_mimes", self))]
+    mimes: Vec<String>,
+    /// Only filled in with `--details`
+    #[tabled(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    glob: Option<String>,
+}
+
+impl HypotheticalTable {
+    fn new(path: &UserPath, details: bool) -> Self {
+        let NameMatch { mimes, glob } = path.get_name_match();
+        Self {
+            path: path.to_string(),
+            mimes: mimes.iter().map(|m| m.essence_str().to_owned()).collect(),
+            glob: if details { glob } else { None },
+        }
+    }
+
+    fn display_mimes(&self) -> String {
+        match self.mimes.as_slice() {
+            [] => "(no match)".to_owned(),
+            [mime] => mime.clone(),
+            mimes => format!("{} (ambiguous)", mimes.join(", ")),
+        }
+    }
+}
+
+/// Internal helper struct for showing a HypotheticalTable with its glob
+#[derive(Tabled)]
+struct GlobTable<'a> {
+    #[tabled(inline)]
+    row: &'a HypotheticalTable,
+    glob: String,
+}
+
+/// How `handlr mime` finds the mimetypes of paths
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MimeMode {
+    /// As `handlr open` would, looking at files that exist
+    Detect,
+    /// From names alone, see `UserPath::get_name_match`,
+    /// along with the glob that matched each if `details` is set
+    Hypothetical { details: bool },
+}
+
+pub fn mime_table(
+    paths: &[UserPath],
+    output_json: bool,
+    output_jsonl: bool,
+    mode: MimeMode,
+) -> Result<()> {
+    if let MimeMode::Hypothetical { details } = mode {
+        return hypothetical_table(paths, output_json, output_jsonl, details);
+    }
+
+    if output_jsonl {
+        return write_mime_jsonl(paths, &mut std::io::stdout().lock());
+    }
+
     let rows = paths
         .iter()
         .map(UserPathTable::new)
@@ -87,3 +425,393 @@ pub fn mime_table(paths: &[UserPath], output_json: bool) -> Result<()> {
 
     Ok(())
 }
+
+fn hypothetical_table(
+    paths: &[UserPath],
+    output_json: bool,
+    output_jsonl: bool,
+    details: bool,
+) -> Result<()> {
+    let rows = paths
+        .iter()
+        .map(|path| HypotheticalTable::new(path, details))
+        .collect::<Vec<_>>();
+
+    let table = if output_jsonl {
+        rows.iter()
+            .map(serde_json::to_string)
+            .collect::<serde_json::Result<Vec<_>>>()?
+            .join("\n")
+    } else if output_json {
+        serde_json::to_string(&rows)?
+    } else if details {
+        render_table(
+            &rows
+                .iter()
+                .map(|row| GlobTable {
+                    row,
+                    glob: row.glob.clone().unwrap_or_default(),
+                })
+                .collect(),
+        )
+    } else {
+        render_table(&rows)
+    };
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Write each path's mimetype as a json object on its own line,
+/// as soon as it is known
+fn write_mime_jsonl(paths: &[UserPath], writer: &mut impl Write) -> Result<()> {
+    for path in paths {
+        serde_json::to_writer(&mut *writer, &UserPathTable::new(path)?)?;
+        writeln!(writer)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{join_exec, DesktopEntry};
+
+    #[test]
+    fn local_paths() -> Result<()> {
+        let file = UserPath::from_str("tests/rust.vim")?;
+        assert!(file.is_local());
+        assert_eq!(file.to_file_path(), Some(Path::new("tests/rust.vim")));
+
+        let url = UserPath::from_str("https://example.com")?;
+        assert!(!url.is_local());
+        assert_eq!(url.to_file_path(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn opaque_uris() -> Result<()> {
+        for (arg, url) in [
+            ("mailto:foo bar@baz.com", "mailto:foo%20bar@baz.com"),
+            (
+                "MAILTO:someone@example.com?subject=Hello World&cc=a@b.com",
+                "mailto:someone@example.com?subject=Hello%20World&cc=a@b.com",
+            ),
+            (
+                " mailto:\"Jane Doe\" <jane@example.com>?subject=50%25 off\n",
+                "mailto:%22Jane%20Doe%22%20%3Cjane@example.com%3E?subject=50%25%20off",
+            ),
+            (
+                "mailto:?to=a@b.com&body=line one%0Aline two#sig",
+                "mailto:?to=a@b.com&body=line%20one%0Aline%20two#sig",
+            ),
+            ("tel:+1 (555) 123-4567", "tel:+1%20(555)%20123-4567"),
+            ("sms:+15551234567?body=hi there", "sms:+15551234567?body=hi%20there"),
+            (
+                "magnet:?xt=urn:btih:abc&dn=Some File",
+                "magnet:?xt=urn:btih:abc&dn=Some%20File",
+            ),
+        ] {
+            let path = UserPath::from_str(arg)?;
+            assert!(!path.is_local(), "{}", arg);
+            assert_eq!(path.to_string(), url);
+            assert_eq!(path.original(), arg);
+            assert_eq!(
+                path.get_mime()?.essence_str(),
+                format!("x-scheme-handler/{}", &url[..url.find(':').unwrap()])
+            );
+        }
+
+        // Only known schemes are handled this way
+        assert!(UserPath::from_str("mailto_notes.txt")?.is_local());
+        assert_eq!(
+            UserPath::from_str("https://example.com/a b")?.to_string(),
+            "https://example.com/a%20b"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn canonicalize() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let target = dir.path().join("q4.pdf");
+        let link = dir.path().join("latest-report");
+        std::fs::write(&target, "%PDF-1.4\n")?;
+        std::os::unix::fs::symlink(&target, &link)?;
+
+        let resolved =
+            UserPath::from_str(&link.to_string_lossy())?.canonicalize()?;
+        let mime = resolved.get_mime()?;
+        let target = std::fs::canonicalize(&target)?;
+
+        // The link is identified as what it points to, but keeps how it was given
+        assert_eq!(resolved.to_file_path(), Some(target.as_path()));
+        assert_eq!(mime, "application/pdf");
+        assert_eq!(resolved.original(), link.to_string_lossy());
+
+        let url = UserPath::from_str("https://example.com/a.pdf")?;
+        assert_eq!(url.canonicalize()?.to_string(), url.to_string());
+        assert!(UserPath::from_str("tests/nonexistent.pdf")?
+            .canonicalize()
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nonexistent_files() -> Result<()> {
+        let paths = |args: &[&str]| -> Result<Vec<UserPath>> {
+            args.iter().map(|arg| arg.parse()).collect()
+        };
+
+        assert!(UserPath::ensure_exist(&paths(&[
+            "tests/rust.vim",
+            "https://example.com/typo.pdff"
+        ])?)
+        .is_ok());
+
+        let error = UserPath::ensure_exist(&paths(&[
+            "tests/typo.pdff",
+            "tests/rust.vim",
+        ])?)
+        .unwrap_err();
+        assert!(matches!(*error.kind, ErrorKind::NonexistentFile(_)));
+        assert_eq!(error.exit_code(), 2);
+
+        // Every missing file is reported at once
+        let error = UserPath::ensure_exist(&paths(&[
+            "tests/typo.pdff",
+            "tests/rust.vim",
+            "tests/other.pdff",
+        ])?)
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "'tests/typo.pdff' does not exist, use --force to open it anyway
+'tests/other.pdff' does not exist, use --force to open it anyway"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn original_arguments() -> Result<()> {
+        for arg in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ&list=PL123#t=42",
+            "HTTPS://Example.com",
+            "file:///tmp/a%3Fb",
+            "./a?b",
+        ]
+        .iter()
+        {
+            assert_eq!(UserPath::from_str(arg)?.original(), *arg);
+        }
+
+        // Unlike what is passed to programs, which is normalized
+        assert_eq!(
+            UserPath::from_str("HTTPS://Example.com")?.to_string(),
+            "https://example.com/"
+        );
+        assert_eq!(
+            UserPath::from_str("file:///tmp/a%3Fb")?.to_string(),
+            "/tmp/a?b"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn percent_encoded_paths() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+        let file = |path: &UserPath| {
+            path.to_file_path()
+                .unwrap_or_else(|| panic!("{} is not a file", path))
+                .to_owned()
+        };
+
+        std::fs::create_dir_all(dir.join("My Documents"))?;
+        let resume = dir.join("My Documents/résumé.pdf");
+        std::fs::write(&resume, "resume")?;
+        let literal = dir.join("100%20.txt");
+        std::fs::write(&literal, "literal")?;
+        let tricky = dir.join("it's \"$HOME\" & more.txt");
+        std::fs::write(&tricky, "tricky")?;
+
+        let encoded = format!(
+            "file://{}/My%20Documents/r%C3%A9sum%C3%A9.pdf",
+            dir.display()
+        );
+        assert_eq!(file(&encoded.parse()?), resume);
+        // Encoded paths without file:// are decoded if only the decoded file exists
+        assert_eq!(file(&encoded["file://".len()..].parse()?), resume);
+        // Files with names that look encoded are found either way
+        assert_eq!(
+            file(&format!("file://{}/100%20.txt", dir.display()).parse()?),
+            literal
+        );
+        assert_eq!(file(&literal.display().to_string().parse()?), literal);
+
+        // Each path reaches the program as a single argument
+        let copy = dir.join("copy");
+        let entry = DesktopEntry {
+            exec: join_exec(&[
+                "sh",
+                "-c",
+                &format!("cat \"$@\" > {}", copy.display()),
+                "sh",
+                "%F",
+            ]),
+            ..Default::default()
+        };
+        for (path, contents) in [(resume, "resume"), (tricky, "tricky")].iter()
+        {
+            crate::utils::wait_all(entry.exec(
+                crate::common::ExecMode::Launch,
+                vec![UserPath::file(path.clone()).to_string()],
+                true,
+                false,
+                &crate::common::ExecOptions::default(),
+            )?)?;
+            assert_eq!(std::fs::read_to_string(&copy)?, *contents);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn dir_walk() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+        for file in ["b.png", "a.txt", "sub/c.txt", "sub/deeper/d.png"].iter() {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap())?;
+            std::fs::write(path, "")?;
+        }
+        std::os::unix::fs::symlink(dir, dir.join("sub/loop"))?;
+
+        let walk = |walk: DirWalk| -> Vec<String> {
+            walk.expand(vec![
+                UserPath::file(dir.to_owned()),
+                "https://example.com".parse().unwrap(),
+            ])
+            .iter()
+            .map(|path| {
+                path.to_string()
+                    .trim_start_matches(&format!("{}/", dir.display()))
+                    .to_owned()
+            })
+            .collect()
+        };
+        let patterns = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|p| glob::Pattern::new(p).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            walk(DirWalk::default()),
+            [
+                "a.txt",
+                "b.png",
+                "sub/c.txt",
+                "sub/deeper/d.png",
+                "https://example.com/"
+            ]
+        );
+        assert_eq!(
+            walk(DirWalk {
+                max_depth: Some(1),
+                ..Default::default()
+            }),
+            ["a.txt", "b.png", "https://example.com/"]
+        );
+        assert_eq!(
+            walk(DirWalk {
+                include: patterns(&["*.png"]),
+                exclude: patterns(&["sub/deeper/*"]),
+                ..Default::default()
+            }),
+            ["b.png", "https://example.com/"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn glob_expand() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let dir = dir.path();
+        for file in ["b.pdf", "a.pdf", "c.txt", "[literal].pdf"].iter() {
+            std::fs::write(dir.join(file), "")?;
+        }
+        let names = |paths: Vec<UserPath>| -> Vec<String> {
+            paths
+                .iter()
+                .map(|path| {
+                    path.to_string()
+                        .trim_start_matches(&format!("{}/", dir.display()))
+                        .to_owned()
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            names(UserPath::glob_expand(&format!("{}/*.pdf", dir.display()))?),
+            ["[literal].pdf", "a.pdf", "b.pdf"]
+        );
+
+        let paths = [
+            format!("{}/?.pdf", dir.display()),
+            format!("{}/[literal].pdf", dir.display()),
+            format!("{}/*.png", dir.display()),
+            format!("{}/[.pdf", dir.display()),
+            "https://example.com".into(),
+        ]
+        .iter()
+        .map(|path| path.parse())
+        .collect::<Result<Vec<_>>>()?;
+
+        assert_eq!(
+            names(UserPath::expand_globs(paths)),
+            [
+                "a.pdf",
+                "b.pdf",
+                // Existing files are not treated as patterns
+                "[literal].pdf",
+                // Neither are patterns that do not match anything
+                "*.png",
+                "[.pdf",
+                "https://example.com/"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn mime_jsonl() -> Result<()> {
+        let paths = ["tests/p.html", "tests/cat", "https://duckduckgo.com/"]
+            .iter()
+            .map(|path| UserPath::from_str(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut output = Vec::new();
+        write_mime_jsonl(&paths, &mut output)?;
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            r#"{"path":"tests/p.html","mime":"text/html"}
+{"path":"tests/cat","mime":"application/x-shellscript"}
+{"path":"https://duckduckgo.com/","mime":"x-scheme-handler/https"}
+"#
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{common::Handler, Error, ErrorKind, Result, UserPath};
+
+/// Open `path` with the application the user picks in the desktop portal's "Open With" dialog,
+/// returning the chosen application if the portal recorded it
+///
+/// The portal opens the path itself, so the chosen application can be one handlr does not know about,
+/// i.e. a Flatpak app whose desktop file is not in `$XDG_DATA_DIRS`.
+/// The choice is read back from the portal's permission store,
+/// where it is kept for `content_type` so the dialog can offer it first next time.
+/// Closing the dialog cancels the selection, like with the selector.
+#[cfg(feature = "portal")]
+pub fn open_with_chooser(
+    path: &UserPath,
+    content_type: &str,
+) -> Result<Option<Handler>> {
+    use std::collections::HashMap;
+    use zbus::{
+        blocking::{Connection, Proxy},
+        zvariant::{Fd, OwnedObjectPath, OwnedValue, Value},
+    };
+
+    let connection = Connection::session().map_err(portal_error)?;
+    let token = format!("handlr{}", std::process::id());
+    let sender = connection
+        .unique_name()
+        .ok_or_else(|| portal_error("not connected to the session bus"))?;
+
+    // Listen for the response before asking, so it cannot be missed
+    let request = Proxy::new(
+        &connection,
+        "org.freedesktop.portal.Desktop",
+        request_path(sender, &token),
+        "org.freedesktop.portal.Request",
+    )
+    .map_err(portal_error)?;
+    let mut responses =
+        request.receive_signal("Response").map_err(portal_error)?;
+
+    let open_uri = Proxy::new(
+        &connection,
+        "org.freedesktop.portal.Desktop",
+        "/org/freedesktop/portal/desktop",
+        "org.freedesktop.portal.OpenURI",
+    )
+    .map_err(portal_error)?;
+    let mut options = HashMap::<&str, Value>::new();
+    options.insert("handle_token", token.as_str().into());
+    options.insert("ask", true.into());
+    let _: OwnedObjectPath = match path {
+        UserPath::File { path, .. } => {
+            let file = std::fs::File::open(path)?;
+            open_uri.call("OpenFile", &("", Fd::from(&file), options))
+        }
+        UserPath::Url { url, .. } => {
+            open_uri.call("OpenURI", &("", url.as_str(), options))
+        }
+    }
+    .map_err(portal_error)?;
+
+    let response = responses
+        .next()
+        .ok_or_else(|| portal_error("the portal did not respond"))?;
+    let (code, _): (u32, HashMap<String, OwnedValue>) =
+        response.body().deserialize().map_err(portal_error)?;
+    match code {
+        0 => {}
+        1 => return Err(Error::from(ErrorKind::Cancelled)),
+        code => {
+            return Err(portal_error(format!("failed with response {}", code)))
+        }
+    }
+
+    let store = Proxy::new(
+        &connection,
+        "org.freedesktop.impl.portal.PermissionStore",
+        "/org/freedesktop/impl/portal/PermissionStore",
+        "org.freedesktop.impl.portal.PermissionStore",
+    )
+    .map_err(portal_error)?;
+    match store.call::<_, _, (HashMap<String, Vec<String>>, OwnedValue)>(
+        "Lookup",
+        &("desktop-used-apps", content_type),
+    ) {
+        Ok((permissions, _)) => Ok(chosen_handler(&permissions)),
+        Err(e) => {
+            tracing::debug!("no choice recorded for {}: {}", content_type, e);
+            Ok(None)
+        }
+    }
+}
+
+/// Without the `portal` feature there is no D-Bus client, so the selector is always used
+#[cfg(not(feature = "portal"))]
+pub fn open_with_chooser(
+    _path: &UserPath,
+    _content_type: &str,
+) -> Result<Option<Handler>> {
+    Err(portal_error("handlr was built without the portal feature"))
+}
+
+/// The object path the portal uses for a request made with `token`
+///
+/// This is the unique name of the connection without its leading colon
+/// and with dots replaced, followed by the token.
+#[cfg(feature = "portal")]
+fn request_path(sender: &str, token: &str) -> String {
+    format!(
+        "/org/freedesktop/portal/desktop/request/{}/{}",
+        sender.trim_start_matches(':').replace('.', "_"),
+        token
+    )
+}
+
+/// Get the application chosen last from the portal's `desktop-used-apps` permissions
+///
+/// handlr is not sandboxed, so its permissions are under the empty app id,
+/// as the chosen app id followed by how many times it was chosen.
+#[cfg(feature = "portal")]
+fn chosen_handler(
+    permissions: &std::collections::HashMap<String, Vec<String>>,
+) -> Option<Handler> {
+    permissions
+        .get("")
+        .and_then(|permission| permission.first())
+        .map(|app_id| {
+            Handler::assume_valid(format!("{}.desktop", app_id).into())
+        })
+}
+
+fn portal_error(reason: impl ToString) -> Error {
+    Error::from(ErrorKind::Portal(reason.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "portal")]
+    #[test]
+    fn portal_choice() {
+        assert_eq!(
+            request_path(":1.42", "handlr7"),
+            "/org/freedesktop/portal/desktop/request/1_42/handlr7"
+        );
+
+        // Apps the portal knows about but handlr does not are kept
+        let permissions = vec![
+            (
+                "".to_owned(),
+                vec!["org.gnome.TextEditor".into(), "3".into()],
+            ),
+            ("org.example.Sandboxed".to_owned(), vec!["vim".into()]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            chosen_handler(&permissions),
+            Some(Handler::assume_valid("org.gnome.TextEditor.desktop".into()))
+        );
+        assert_eq!(chosen_handler(&Default::default()), None);
+    }
+
+    #[cfg(not(feature = "portal"))]
+    #[test]
+    fn portal_disabled() -> Result<()> {
+        let e =
+            open_with_chooser(&"https://example.com".parse()?, "").unwrap_err();
+        assert!(matches!(*e.kind, ErrorKind::Portal(_)));
+
+        Ok(())
+    }
+}
@@ -1,23 +1,141 @@
 use crate::{
     apps::{ConfigHandler, SystemApps},
-    common::Handler,
+    common::{split_exec, DesktopEntry, Handler},
     Error, ErrorKind, Result,
 };
 use mime::Mime;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{collections::BTreeMap, path::PathBuf, str::FromStr};
 
 pub static CONFIG: Lazy<Config> = Lazy::new(Config::load);
 
+/// A handler to offer in the selector
+///
+/// Each field can be used in `selector_format` by its name in braces, i.e. `{name}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorCandidate {
+    pub name: String,
+    pub handler: String,
+    pub exec: String,
+    pub mime: String,
+}
+
+impl SelectorCandidate {
+    const FIELDS: [&'static str; 4] = ["name", "handler", "exec", "mime"];
+
+    fn field(&self, field: &str) -> &str {
+        match field {
+            "name" => &self.name,
+            "handler" => &self.handler,
+            "exec" => &self.exec,
+            "mime" => &self.mime,
+            _ => unreachable!(),
+        }
+    }
+
+    fn render(&self, format: &str) -> String {
+        Self::FIELDS.iter().fold(format.to_owned(), |line, field| {
+            line.replace(&format!("{{{}}}", field), self.field(field))
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub enable_selector: bool,
     pub selector: String,
+    /// How each handler is shown to the selector, see `SelectorCandidate`
+    pub selector_format: String,
+    pub expand_wildcards: bool,
+    /// Most mimes `handlr set` and `handlr add` change without confirmation,
+    /// see `MimeApps::confirm_changes`
+    pub expand_confirm_threshold: usize,
+    /// Offer the handler last picked with the selector first, see `RecentSelections`
+    pub remember_selection: bool,
+    /// Open paths with the desktop portal's "Open With" dialog instead of picking handlers with `selector`,
+    /// see `MimeApps::use_portal`
+    pub use_portal_chooser: bool,
+    /// Mimes, possibly with wildcards, for which files are identified by their contents
+    /// instead of their extension, see `MimeType::from_path_with`
     #[serde(skip_serializing_if = "Vec::is_empty")]
-    pub handlers: Vec<ConfigHandler>,
+    pub prefer_content_detection: Vec<String>,
+    /// Sets of mimes that are different names for the same thing,
+    /// like `text/markdown` and `text/x-markdown`, see `MimeApps::equivalents`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub equivalent_mimes: Vec<Vec<String>>,
+    /// Desktop file to use for mimes that have no other handler
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallback_handler: Option<String>,
+    /// Commands to run after `set`, `add`, `unset` and `remove` change mimeapps.list,
+    /// see `apps::run_post_set_hooks`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub post_set_hooks: Vec<String>,
+    /// Write the log file as a json object per line instead of as text, see `logs::init`
+    pub json_log: bool,
+    /// How many days of log files to keep, see `logs::prune`
+    pub log_retention_days: usize,
+    /// Directories to look for desktop files in before the XDG `applications` directories,
+    /// like Flatpak's exports, see `Handler::get_path`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_app_dirs: Vec<PathBuf>,
+    /// Command to run terminal programs in when no `x-scheme-handler/terminal` handler is set,
+    /// for terminal emulators without a desktop entry, see `Config::terminal`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terminal_command: Option<String>,
     term_exec_args: Option<String>,
+    // Options written as tables come last, since no plain value can follow a table in TOML
+    /// Extra environment variables for each desktop file, by desktop file name,
+    /// see `DesktopEntry::effective_env`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub env_overrides: BTreeMap<String, BTreeMap<String, String>>,
+    /// Environment variables to run desktop entries with `PrefersNonDefaultGPU=true` with,
+    /// see `DesktopEntry::effective_env`
+    pub gpu_offload_env: BTreeMap<String, String>,
+    /// Handlers for http and https URLs by host glob, consulted before regex handlers,
+    /// see `HostApps`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, String>,
+    #[serde(
+        skip_serializing_if = "Vec::is_empty",
+        deserialize_with = "deserialize_handlers"
+    )]
+    pub handlers: Vec<ConfigHandler>,
+    /// Why handlr.toml could not be loaded, if the defaults are used instead
+    #[serde(skip)]
+    pub load_error: Option<String>,
+}
+
+/// A regex handler from handlr.toml, or whatever was there instead
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum MaybeConfigHandler {
+    Valid(ConfigHandler),
+    Invalid(serde_json::Value),
+}
+
+/// Deserialize regex handlers, skipping malformed ones with a warning
+/// instead of failing to load the whole config
+fn deserialize_handlers<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<ConfigHandler>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Vec::<MaybeConfigHandler>::deserialize(deserializer)?
+        .into_iter()
+        .filter_map(|handler| match handler {
+            MaybeConfigHandler::Valid(handler) => Some(handler),
+            MaybeConfigHandler::Invalid(value) => {
+                tracing::warn!(
+                    "skipping regex handler {}: it needs an `exec` string and a `regexes` list",
+                    value
+                );
+                None
+            }
+        })
+        .collect())
 }
 
 impl Default for Config {
@@ -25,102 +143,495 @@ impl Default for Config {
         Config {
             enable_selector: false,
             selector: "rofi -dmenu -i -p 'Open With: '".into(),
+            selector_format: "{name}".into(),
+            expand_wildcards: false,
+            expand_confirm_threshold: 25,
+            remember_selection: false,
+            use_portal_chooser: false,
+            prefer_content_detection: Vec::new(),
+            equivalent_mimes: Vec::new(),
+            env_overrides: BTreeMap::new(),
+            fallback_handler: None,
+            post_set_hooks: Vec::new(),
+            json_log: false,
+            log_retention_days: 7,
             handlers: Vec::new(),
+            hosts: BTreeMap::new(),
+            extra_app_dirs: Vec::new(),
+            terminal_command: None,
             // Required for many xterm-compatible terminal emulators
             // Unfortunately, messes up emulators that don't accept it
             term_exec_args: Some("-e".into()),
+            // Mesa's way, since NVIDIA's variables break OpenGL without its driver
+            gpu_offload_env: vec![("DRI_PRIME".to_owned(), "1".to_owned())]
+                .into_iter()
+                .collect(),
+            load_error: None,
         }
     }
 }
 
 impl Config {
-    pub fn terminal() -> Result<String> {
+    /// Get the command line to run programs that need a terminal in,
+    /// with `term_exec_args` appended
+    ///
+    /// The terminal emulator is, in order of priority:
+    /// the `x-scheme-handler/terminal` handler, the `terminal_command` option,
+    /// `$TERMINAL`, and finally a guess from the installed desktop entries,
+    /// which the user is told about with a notification unless `quiet` is set.
+    pub fn terminal(quiet: bool) -> Result<Vec<String>> {
         let terminal_entry = crate::apps::APPS
             .get_handler(&Mime::from_str("x-scheme-handler/terminal").unwrap())
             .ok()
             .and_then(|h| h.get_entry().ok());
 
-        terminal_entry
-            .or_else(|| {
-                let entry = SystemApps::get_entries()
-                    .ok()?
-                    .find(|(_handler, entry)| {
-                        entry.categories.contains_key("TerminalEmulator")
-                    })?;
-
-                crate::utils::notify(
-                    "handlr",
-                    &format!(
-                        "Guessed terminal emulator: {}.\n\nIf this is wrong, use `handlr set x-scheme-handler/terminal` to update it.",
-                        entry.0.to_string_lossy()
-                    )
-                ).ok()?;
-
-                let mut apps = (*crate::apps::APPS).clone();
-                apps.set_handler(
-                    Mime::from_str("x-scheme-handler/terminal").unwrap(),
-                    Handler::assume_valid(entry.0),
-                );
-                apps.save().ok()?;
+        CONFIG.terminal_from(
+            terminal_entry,
+            std::env::var("TERMINAL").ok(),
+            || Self::guess_terminal(quiet),
+        )
+    }
 
-                Some(entry.1)
-            })
-            .map(|e| {
-                let mut exec = e.exec;
+    /// Like `terminal`, but with the `x-scheme-handler/terminal` entry,
+    /// `$TERMINAL` and the guess given
+    fn terminal_from(
+        &self,
+        terminal_entry: Option<DesktopEntry>,
+        env_terminal: Option<String>,
+        guess: impl FnOnce() -> Option<DesktopEntry>,
+    ) -> Result<Vec<String>> {
+        // Empty commands are skipped like unset ones
+        let split =
+            |command: &str| shlex::split(command).filter(|c| !c.is_empty());
 
-                if let Some(opts) = &CONFIG.term_exec_args {
-                    exec.push(' ');
-                    exec.push_str(opts)
+        terminal_entry
+            .and_then(|e| split_exec(&e.exec))
+            .or_else(|| split(self.terminal_command.as_deref()?))
+            .or_else(|| split(&env_terminal?))
+            .or_else(|| split_exec(&guess()?.exec))
+            .and_then(|mut exec| {
+                if let Some(opts) = &self.term_exec_args {
+                    exec.extend(shlex::split(opts)?);
                 }
 
-                exec
+                Some(exec)
             })
             .ok_or(Error::from(ErrorKind::NoTerminal))
     }
+
+    /// Find a terminal emulator among the installed desktop entries
+    /// and make it the `x-scheme-handler/terminal` handler, telling the user unless `quiet` is set
+    fn guess_terminal(quiet: bool) -> Option<DesktopEntry> {
+        let entry = SystemApps::get_entries()
+            .ok()?
+            .find(|(_handler, entry)| entry.is_terminal_emulator())?;
+
+        if !quiet {
+            crate::utils::notify(
+                "handlr",
+                &format!(
+                    "Guessed terminal emulator: {}.\n\nIf this is wrong, use `handlr set x-scheme-handler/terminal` to update it.",
+                    entry.0.to_string_lossy()
+                )
+            )
+            // Not being able to tell the user is no reason to fail
+            .ok();
+        }
+
+        let mut apps = (*crate::apps::APPS).clone();
+        apps.set_handler(
+            Mime::from_str("x-scheme-handler/terminal").unwrap(),
+            Handler::assume_valid(entry.0),
+        );
+        apps.save().ok()?;
+
+        Some(entry.1)
+    }
+    /// Prompt with the selector to pick one of the candidates, returning its index
+    ///
+    /// Each candidate is shown using `selector_format`.
+    /// Output that is not exactly one of the shown lines is matched
+    /// against `selector_format` and its `{handler}` or `{name}` field is used,
+    /// so selectors may change the rest of the line.
+    pub fn select_candidate(
+        &self,
+        candidates: &[SelectorCandidate],
+    ) -> Result<usize> {
+        self.select_candidate_with(&self.selector, candidates)
+    }
+
+    /// Like `select_candidate`, but with the given selector command instead of `selector`
+    pub fn select_candidate_with(
+        &self,
+        selector: &str,
+        candidates: &[SelectorCandidate],
+    ) -> Result<usize> {
+        let lines = candidates
+            .iter()
+            .map(|candidate| candidate.render(&self.selector_format))
+            .collect::<Vec<_>>();
+
+        let output = select_with(selector, lines.iter().cloned())?;
+
+        if let Some(i) = lines.iter().position(|line| *line == output) {
+            return Ok(i);
+        }
+
+        self.parse_selection(&output)
+            .and_then(|(field, value)| {
+                candidates
+                    .iter()
+                    .position(|candidate| candidate.field(field) == value)
+            })
+            .ok_or_else(|| Error::from(ErrorKind::UnknownSelection(output)))
+    }
+
+    /// Pick out the most specific field from a line of selector output
+    fn parse_selection<'a>(
+        &self,
+        output: &'a str,
+    ) -> Option<(&'static str, &'a str)> {
+        let placeholders = regex::Regex::new(r"\{(name|handler|exec|mime)\}")
+            .expect("placeholder regex is valid");
+
+        let mut pattern = String::from("^");
+        let mut last = 0;
+        for placeholder in placeholders.find_iter(&self.selector_format) {
+            pattern.push_str(&regex::escape(
+                &self.selector_format[last..placeholder.start()],
+            ));
+            // Later uses of the same field would be a duplicate group name
+            let field = &placeholder.as_str()[1..placeholder.len() - 1];
+            if pattern.contains(&format!("(?P<{}>", field)) {
+                pattern.push_str("(?:.*?)");
+            } else {
+                pattern.push_str(&format!("(?P<{}>.*?)", field));
+            }
+            last = placeholder.end();
+        }
+        pattern.push_str(&regex::escape(&self.selector_format[last..]));
+        pattern.push('$');
+
+        let captures = regex::Regex::new(&pattern).ok()?.captures(output)?;
+        ["handler", "name"]
+            .iter()
+            .find_map(|field| Some((*field, captures.name(field)?.as_str())))
+    }
+    /// Where handlr.toml is, $XDG_CONFIG_HOME/handlr/handlr.toml
+    pub fn path() -> Result<PathBuf> {
+        Ok(xdg::BaseDirectories::with_prefix("handlr")?
+            .get_config_file("handlr.toml"))
+    }
+    /// Load handlr.toml, creating it if it does not exist,
+    /// and apply `apply_env_overrides` on top of it
+    ///
+    /// If it cannot be loaded, a warning is shown and the defaults are used instead,
+    /// with the reason kept in `load_error` for `handlr status`.
     pub fn load() -> Self {
-        confy::load("handlr").unwrap()
+        let path = Self::path();
+        let loaded = path
+            .as_ref()
+            .map_err(|e| e.to_string())
+            .and_then(|path| confy::load_path(path).map_err(|e| e.to_string()));
+        let mut config = loaded.unwrap_or_else(|e| {
+            tracing::warn!(
+                "could not load {}, using the default settings instead: {}",
+                path.map_or_else(
+                    |_| "handlr.toml".to_owned(),
+                    |path| path.display().to_string()
+                ),
+                e
+            );
+            Self {
+                load_error: Some(e),
+                ..Self::default()
+            }
+        });
+        config.apply_env_overrides();
+        config
+    }
+
+    /// Override settings from environment variables,
+    /// for when handlr is run where flags cannot be passed
+    ///
+    /// `HANDLR_SELECTOR` overrides `selector` and `HANDLR_ENABLE_SELECTOR` overrides `enable_selector`.
+    /// Flags like `pick --selector` still take priority over these.
+    pub fn apply_env_overrides(&mut self) {
+        self.apply_overrides(|var| std::env::var(var).ok())
+    }
+
+    /// Like `apply_env_overrides`, but looking variables up with `var`
+    fn apply_overrides(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(selector) = var("HANDLR_SELECTOR") {
+            self.selector = selector;
+        }
+
+        if let Some(enable) = var("HANDLR_ENABLE_SELECTOR") {
+            match enable.to_lowercase().as_str() {
+                "1" | "true" | "yes" => self.enable_selector = true,
+                "0" | "false" | "no" | "" => self.enable_selector = false,
+                _ => tracing::warn!(
+                    "ignoring HANDLR_ENABLE_SELECTOR={}: expected 1 or 0",
+                    enable
+                ),
+            }
+        }
     }
 
     pub fn select<O: Iterator<Item = String>>(
         &self,
-        mut opts: O,
+        opts: O,
     ) -> Result<String> {
-        use itertools::Itertools;
-        use std::{
-            io::prelude::*,
-            process::{Command, Stdio},
-        };
+        select_with(&self.selector, opts)
+    }
+}
+
+/// Prompt with the given selector command to pick one of the options
+fn select_with<O: Iterator<Item = String>>(
+    selector: &str,
+    mut opts: O,
+) -> Result<String> {
+    use itertools::Itertools;
+    use std::{
+        io::prelude::*,
+        process::{Command, Stdio},
+    };
+
+    let process = {
+        let mut split = shlex::split(selector).unwrap();
+        let (cmd, args) = (split.remove(0), split);
+        Command::new(cmd)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?
+    };
+
+    let output = {
+        process
+            .stdin
+            .ok_or_else(|| ErrorKind::Selector(selector.to_owned()))?
+            .write_all(opts.join("\n").as_bytes())?;
+
+        let mut output = String::with_capacity(24);
+
+        process
+            .stdout
+            .ok_or_else(|| ErrorKind::Selector(selector.to_owned()))?
+            .read_to_string(&mut output)?;
+
+        output.trim_end().to_owned()
+    };
+
+    if output.is_empty() {
+        Err(Error::from(ErrorKind::Cancelled))
+    } else {
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates() -> Vec<SelectorCandidate> {
+        ["helix", "nvim"]
+            .iter()
+            .map(|name| SelectorCandidate {
+                name: name.to_uppercase(),
+                handler: format!("{}.desktop", name),
+                exec: format!("{} %F", name),
+                mime: "text/plain".into(),
+            })
+            .collect()
+    }
+
+    fn config(selector: &str, selector_format: &str) -> Config {
+        Config {
+            selector: selector.into(),
+            selector_format: selector_format.into(),
+            ..Default::default()
+        }
+    }
 
-        let process = {
-            let mut split = shlex::split(&self.selector).unwrap();
-            let (cmd, args) = (split.remove(0), split);
-            Command::new(cmd)
-                .args(args)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()?
+    #[test]
+    fn selector_format() -> Result<()> {
+        // The default format stays compatible with dmenu-style selectors
+        assert_eq!(
+            config("sed -n 2p", "{name}").select_candidate(&candidates())?,
+            1
+        );
+
+        let format = "{name}\t{handler}\t{exec}";
+        assert_eq!(
+            candidates()[1].render(format),
+            "NVIM\tnvim.desktop\tnvim %F"
+        );
+        assert_eq!(
+            config("sed -n 2p", format).select_candidate(&candidates())?,
+            1
+        );
+        assert_eq!(
+            config("sed -n 1p", format).select_candidate(&candidates())?,
+            0
+        );
+
+        // Other fields may be changed by the selector as long as the handler matches
+        assert_eq!(
+            config(r#"sh -c 'cat >/dev/null; printf "Renamed\tnvim.desktop\tnvim"'"#, format)
+                .select_candidate(&candidates())?,
+            1
+        );
+
+        assert!(matches!(
+            *config("sh -c 'cat >/dev/null; echo neither'", format)
+                .select_candidate(&candidates())
+                .unwrap_err()
+                .kind,
+            ErrorKind::UnknownSelection(_)
+        ));
+        assert!(matches!(
+            *config("sh -c 'cat >/dev/null'", format)
+                .select_candidate(&candidates())
+                .unwrap_err()
+                .kind,
+            ErrorKind::Cancelled
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn env_overrides() {
+        let overridden = |vars: &[(&str, &str)]| {
+            let mut config = Config::default();
+            config.apply_overrides(|var| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            });
+            config
         };
 
-        let output = {
-            process
-                .stdin
-                .ok_or_else(|| ErrorKind::Selector(self.selector.clone()))?
-                .write_all(opts.join("\n").as_bytes())?;
+        let config = overridden(&[]);
+        assert_eq!(config.selector, Config::default().selector);
+        assert!(!config.enable_selector);
 
-            let mut output = String::with_capacity(24);
+        let config = overridden(&[
+            ("HANDLR_SELECTOR", "wofi --dmenu"),
+            ("HANDLR_ENABLE_SELECTOR", "1"),
+        ]);
+        assert_eq!(config.selector, "wofi --dmenu");
+        assert!(config.enable_selector);
 
-            process
-                .stdout
-                .ok_or_else(|| ErrorKind::Selector(self.selector.clone()))?
-                .read_to_string(&mut output)?;
+        // Unrecognized values leave the setting alone
+        assert!(
+            !overridden(&[("HANDLR_ENABLE_SELECTOR", "maybe")]).enable_selector
+        );
+    }
 
-            output.trim_end().to_owned()
+    #[test]
+    fn terminal_fallbacks() -> Result<()> {
+        let entry = |exec: &str| DesktopEntry {
+            exec: exec.into(),
+            ..Default::default()
+        };
+        let no_guess = || None;
+        let terminal = |config: &serde_json::Value,
+                        terminal_entry: Option<DesktopEntry>,
+                        env_terminal: Option<&str>|
+         -> Result<Vec<String>> {
+            let config: Config = serde_json::from_value(config.clone())?;
+            config.terminal_from(
+                terminal_entry,
+                env_terminal.map(String::from),
+                || Some(entry("xterm")),
+            )
         };
+        let both =
+            serde_json::json!({ "terminal_command": "st -f 'Mono:size=12'" });
 
-        if output.is_empty() {
-            Err(Error::from(ErrorKind::Cancelled))
-        } else {
-            Ok(output)
-        }
+        // x-scheme-handler/terminal comes first
+        assert_eq!(
+            terminal(&both, Some(entry("foot")), Some("alacritty"))?,
+            ["foot", "-e"]
+        );
+        // Then terminal_command, without any desktop entries
+        assert_eq!(
+            terminal(&both, None, Some("alacritty"))?,
+            ["st", "-f", "Mono:size=12", "-e"]
+        );
+        // Then $TERMINAL
+        let none = serde_json::json!({});
+        assert_eq!(terminal(&none, None, Some("st"))?, ["st", "-e"]);
+        // Then the guess
+        assert_eq!(terminal(&none, None, Some(""))?, ["xterm", "-e"]);
+        assert_eq!(terminal(&none, None, None)?, ["xterm", "-e"]);
+
+        // term_exec_args can be turned off for raw commands too
+        let no_args = serde_json::json!({ "term_exec_args": "" });
+        assert_eq!(terminal(&no_args, None, Some("st"))?, ["st"]);
+
+        let error = Config::default()
+            .terminal_from(None, None, no_guess)
+            .unwrap_err();
+        assert!(matches!(*error.kind, ErrorKind::NoTerminal));
+
+        Ok(())
+    }
+
+    #[test]
+    fn default_round_trip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("handlr.toml");
+        let mut config = Config::default();
+        // Tables written for options that are set must come last too
+        config
+            .hosts
+            .insert("github.com".into(), "firefox.desktop".into());
+        config.terminal_command = Some("foot".into());
+
+        // confy writes the default config on the first run
+        confy::store_path(&path, &config)?;
+        let loaded = confy::load_path::<Config>(&path)?;
+
+        assert_eq!(loaded.gpu_offload_env, config.gpu_offload_env);
+        assert_eq!(loaded.hosts, config.hosts);
+        assert_eq!(loaded.env_overrides, config.env_overrides);
+        assert_eq!(loaded.terminal_command, config.terminal_command);
+        assert_eq!(loaded.term_exec_args, config.term_exec_args);
+        assert_eq!(loaded.log_retention_days, config.log_retention_days);
+
+        Ok(())
+    }
+
+    #[test]
+    fn lenient_handlers() -> Result<()> {
+        let config: Config = confy::load_path("tests/handlr_bad_regex.toml")?;
+
+        // The rest of the config still works
+        assert!(config.enable_selector);
+        assert_eq!(config.selector, "fzf");
+
+        // Malformed handlers are dropped when loading
+        assert_eq!(
+            config.handlers.iter().map(|h| h.exec()).collect::<Vec<_>>(),
+            vec!["freetube %u", "broken %u"]
+        );
+
+        // Handlers with invalid regexes are dropped when compiling
+        let invalid = config.handlers[1].invalid_regexes();
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].0, "(unclosed");
+
+        let regex_apps = crate::apps::RegexApps::from_config(&config);
+        assert!(regex_apps
+            .get_handler(&"https://youtu.be/dQw4w9WgXcQ".parse()?)
+            .is_ok());
+        assert!(regex_apps
+            .get_handler(&"gemini://example.com".parse()?)
+            .is_err());
+
+        Ok(())
     }
 }
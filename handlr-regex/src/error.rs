@@ -1,3 +1,6 @@
+use crate::utils;
+use std::io::IsTerminal;
+
 #[derive(Debug, thiserror::Error)]
 #[error(transparent)]
 pub struct Error {
@@ -15,38 +18,307 @@ where
     }
 }
 
+impl Error {
+    /// Exit code handlr uses when failing with this error
+    ///
+    /// These are part of the CLI's interface for scripts and should not change
+    pub fn exit_code(&self) -> i32 {
+        match *self.kind {
+            ErrorKind::NotFound(_) | ErrorKind::NonexistentFile(_) => 2,
+            ErrorKind::BadEntry { .. } => 3,
+            ErrorKind::Cancelled | ErrorKind::SelectionsCancelled(_) => 4,
+            ErrorKind::Io(_) => 5,
+            ErrorKind::ChildExit(code) => code,
+            ErrorKind::HandlerFailed(_, ref e) => e.exit_code(),
+            // Unless a handler waited on failed, whose exit code is used
+            ErrorKind::OpenErrors(ref errors) => {
+                errors.iter().find_map(|e| e.child_exit_code()).unwrap_or(1)
+            }
+            _ => 1,
+        }
+    }
+
+    /// The exit code of the handler this error is from, if it is one
+    fn child_exit_code(&self) -> Option<i32> {
+        match *self.kind {
+            ErrorKind::ChildExit(code) => Some(code),
+            ErrorKind::HandlerFailed(_, ref e) => e.child_exit_code(),
+            _ => None,
+        }
+    }
+
+    /// Name of the kind of error for `--errors-json` output
+    ///
+    /// Like exit codes, these are part of the CLI's interface for scripts
+    pub fn kind_name(&self) -> &'static str {
+        match *self.kind {
+            ErrorKind::Io(_) => "io",
+            ErrorKind::Xdg(_) => "xdg",
+            ErrorKind::Config(_) => "config",
+            ErrorKind::NotFound(_) => "not_found",
+            ErrorKind::NonexistentFile(_) => "nonexistent_file",
+            ErrorKind::Ambiguous(_) => "ambiguous",
+            ErrorKind::BadMimeType(_) | ErrorKind::InvalidMime(_) => "bad_mime",
+            ErrorKind::BadEntry { .. } => "bad_entry",
+            ErrorKind::BadRegex(_) => "bad_regex",
+            ErrorKind::BadGlob(_) => "bad_glob",
+            ErrorKind::Selector(_) | ErrorKind::UnknownSelection(_) => {
+                "selector"
+            }
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::SelectionsCancelled(_) => "selections_cancelled",
+            ErrorKind::TooManyPaths(_, _) => "too_many_paths",
+            ErrorKind::TooManyChanges(_, _) => "too_many_changes",
+            ErrorKind::Unhealthy(_) => "unhealthy",
+            ErrorKind::NotLoaded(_, _) => "not_loaded",
+            ErrorKind::LoadFailed(_) => "load_failed",
+            ErrorKind::DBusActivation(_, _) => "dbus_activation",
+            ErrorKind::Portal(_) => "portal",
+            ErrorKind::HandlerFailed(_, ref e) => e.kind_name(),
+            ErrorKind::ChildExit(_) => "child_exit",
+            ErrorKind::OpenErrors(_) => "open_errors",
+            ErrorKind::NoTerminal => "no_terminal",
+            ErrorKind::BadPath(_) => "bad_path",
+            ErrorKind::AlreadyInstalled(_) => "already_installed",
+            ErrorKind::SerdeJson(_) => "json",
+            ErrorKind::Watch(_) => "watch",
+            ErrorKind::CacheEncode(_) | ErrorKind::CacheDecode(_) => "cache",
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ErrorKind {
-    #[error(transparent)]
-    ParseApps(#[from] pest::error::Error<crate::apps::MimeappsRule>),
     #[error(transparent)]
     Io(#[from] std::io::Error),
     #[error(transparent)]
     Xdg(#[from] xdg::BaseDirectoriesError),
     #[error(transparent)]
     Config(#[from] confy::ConfyError),
-    #[error("no handlers found for '{0}'")]
-    NotFound(String),
+    #[error(transparent)]
+    NotFound(#[from] NotFoundKind),
+    #[error("'{0}' does not exist, use --force to open it anyway")]
+    NonexistentFile(std::path::PathBuf),
     #[error("could not figure out the mime type of '{0}'")]
     Ambiguous(std::path::PathBuf),
     #[error(transparent)]
     BadMimeType(#[from] mime::FromStrError),
     #[error("bad mime: {0}")]
     InvalidMime(mime::Mime),
-    #[error("malformed desktop entry at {0}")]
-    BadEntry(std::path::PathBuf),
+    #[error("malformed desktop entry at {}: {problem}", .path.display())]
+    BadEntry {
+        path: std::path::PathBuf,
+        problem: EntryProblem,
+    },
     #[error(transparent)]
     BadRegex(#[from] regex::Error),
+    #[error(transparent)]
+    BadGlob(#[from] glob::PatternError),
     #[error("error spawning selector process '{0}'")]
     Selector(String),
     #[error("selection cancelled")]
     Cancelled,
+    #[error(
+        "selection cancelled for {}, so those paths were not opened",
+        join_mimes(.0)
+    )]
+    SelectionsCancelled(Vec<mime::Mime>),
+    #[error(
+        "refusing to open {0} files, which is more than {1}, without --yes"
+    )]
+    TooManyPaths(usize, usize),
+    #[error(
+        "refusing to change {0} mimetypes, which is more than {1}, without --yes"
+    )]
+    TooManyChanges(usize, usize),
+    #[error("found {0} problem(s) with error severity")]
+    Unhealthy(usize),
+    #[error("refusing to overwrite {}, since it could not be read: {1}", .0.display())]
+    NotLoaded(std::path::PathBuf, String),
+    #[error("{0} config source(s) could not be loaded")]
+    LoadFailed(usize),
+    #[error("selector output '{0}' does not match any handler")]
+    UnknownSelection(String),
+    #[error("could not activate {0} over D-Bus: {1}")]
+    DBusActivation(String, String),
+    #[error("could not use the desktop portal: {0}")]
+    Portal(String),
+    #[error("could not open with {0}: {1}")]
+    HandlerFailed(String, Error),
+    #[error("handler exited with status {0}")]
+    ChildExit(i32),
+    #[error("{}", join_errors(.0))]
+    OpenErrors(Vec<Error>),
     #[error("Please specify the default terminal with handlr set x-scheme-handler/terminal")]
     NoTerminal,
     #[error("Bad path: {0}")]
     BadPath(String),
+    #[error("'{}' already exists, so it was not replaced", .0.display())]
+    AlreadyInstalled(std::path::PathBuf),
     #[error(transparent)]
     SerdeJson(#[from] serde_json::Error),
+    #[error(transparent)]
+    Watch(#[from] notify::Error),
+    #[error(transparent)]
+    CacheEncode(#[from] rmp_serde::encode::Error),
+    #[error(transparent)]
+    CacheDecode(#[from] rmp_serde::decode::Error),
+}
+
+/// What could not be found when handlr reports `ErrorKind::NotFound`
+#[derive(Debug, thiserror::Error)]
+pub enum NotFoundKind {
+    #[error("no handlers found for '{0}'")]
+    Mime(mime::Mime),
+    #[error("desktop file '{0}' not found in any applications directory")]
+    DesktopFile(String),
+    #[error("desktop file '{}' does not exist", .0.display())]
+    DesktopFilePath(std::path::PathBuf),
+    #[error("desktop file '{0}' not found; did you mean '{1}' ({2})?")]
+    SimilarDesktopFile(String, String, String),
+    #[error("no handlers found for path '{0}'")]
+    Path(String),
+    #[error("'{0}' has no action '{1}'")]
+    Action(String, String),
+}
+
+/// Why a desktop entry cannot be used when handlr reports `ErrorKind::BadEntry`
+#[derive(Debug, thiserror::Error)]
+pub enum EntryProblem {
+    #[error("Name is missing")]
+    MissingName,
+    #[error("Exec is missing")]
+    MissingExec,
+    #[error("{0} is empty")]
+    EmptyValue(&'static str),
+    #[error("Type is {0}, so it cannot open anything")]
+    NotAnApplication(String),
+    #[error("it could not be read: {0}")]
+    ParseFailure(std::io::Error),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Show each of several errors on its own line
+fn join_errors(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// List mimes on one line
+fn join_mimes(mimes: &[mime::Mime]) -> String {
+    mimes
+        .iter()
+        .map(|mime| mime.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Report an error to the user and exit with its exit code
+///
+/// Errors are printed to stderr as json if `json` is set,
+/// or shown as notifications when not running in a terminal
+/// unless notifications were turned off with `quiet`, from `--quiet`.
+pub fn handle(error: Error, json: bool, quiet: bool) -> ! {
+    match *error.kind {
+        // Nothing worth reporting
+        ErrorKind::Cancelled | ErrorKind::ChildExit(_) => {}
+        _ if json => eprintln!(
+            "{}",
+            serde_json::json!({
+                "error": error.to_string(),
+                "kind": error.kind_name(),
+            })
+        ),
+        _ if std::io::stdout().is_terminal() || quiet => {
+            eprintln!("{}", error)
+        }
+        _ => {
+            if utils::notify("handlr error", &error.to_string()).is_err() {
+                eprintln!("{}", error)
+            }
+        }
+    }
+
+    std::process::exit(error.exit_code())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{apps::MimeApps, common::DesktopEntry, Handler};
+    use std::{convert::TryFrom, path::PathBuf, str::FromStr};
+
+    #[test]
+    fn exit_codes() {
+        let missing_handler = MimeApps::default()
+            .get_handler(&mime::Mime::from_str("video/mp4").unwrap())
+            .unwrap_err();
+        assert!(matches!(
+            *missing_handler.kind,
+            ErrorKind::NotFound(NotFoundKind::Mime(_))
+        ));
+        assert_eq!(missing_handler.exit_code(), 2);
+
+        let missing_desktop_file =
+            Handler::resolve("nonexistent.desktop".into()).unwrap_err();
+        assert!(matches!(
+            *missing_desktop_file.kind,
+            ErrorKind::NotFound(NotFoundKind::DesktopFile(_))
+        ));
+        assert_eq!(missing_desktop_file.exit_code(), 2);
+
+        let bad_entry =
+            DesktopEntry::try_from(PathBuf::from("tests/empty.txt"))
+                .unwrap_err();
+        assert_eq!(bad_entry.exit_code(), 3);
+
+        assert_eq!(Error::from(ErrorKind::Cancelled).exit_code(), 4);
+        let skipped = Error::from(ErrorKind::SelectionsCancelled(vec![
+            mime::Mime::from_str("application/pdf").unwrap(),
+            mime::Mime::from_str("image/png").unwrap(),
+        ]));
+        assert_eq!(skipped.exit_code(), 4);
+        assert_eq!(
+            skipped.to_string(),
+            "selection cancelled for application/pdf, image/png, so those paths were not opened"
+        );
+
+        let io = Error::from(std::io::Error::from(
+            std::io::ErrorKind::PermissionDenied,
+        ));
+        assert_eq!(io.exit_code(), 5);
+
+        assert_eq!(Error::from(ErrorKind::ChildExit(42)).exit_code(), 42);
+
+        // Failing to open any path is 1, even if only one failed
+        let open_errors =
+            Error::from(ErrorKind::OpenErrors(vec![missing_handler]));
+        assert_eq!(open_errors.exit_code(), 1);
+        let child_failed =
+            Error::from(ErrorKind::OpenErrors(vec![Error::from(
+                ErrorKind::HandlerFailed(
+                    "nvim.desktop".into(),
+                    Error::from(ErrorKind::ChildExit(42)),
+                ),
+            )]));
+        assert_eq!(child_failed.exit_code(), 42);
+        assert_eq!(Error::from(ErrorKind::NoTerminal).exit_code(), 1);
+    }
+
+    #[test]
+    fn kind_names() {
+        let missing_handler = MimeApps::default()
+            .get_handler(&mime::Mime::from_str("video/mp4").unwrap())
+            .unwrap_err();
+        assert_eq!(missing_handler.kind_name(), "not_found");
+        assert_eq!(Error::from(ErrorKind::Cancelled).kind_name(), "cancelled");
+        assert_eq!(
+            Error::from(ErrorKind::BadPath("x".into())).kind_name(),
+            "bad_path"
+        );
+    }
+}
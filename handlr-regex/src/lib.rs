@@ -1,8 +1,35 @@
+//! Resolution logic behind the `handlr` command line tool
+//!
+//! [`MimeApps`] holds the associations from mimeapps.list,
+//! along with installed desktop entries and regex handlers,
+//! and can work out how a path would be opened without opening it.
+//!
+//! ```no_run
+//! use handlr_regex::{Config, MimeApps};
+//! use std::path::{Path, PathBuf};
+//!
+//! # fn main() -> handlr_regex::Result<()> {
+//! let apps = MimeApps::from_paths(
+//!     Path::new("/home/user/.config/mimeapps.list"),
+//!     &[PathBuf::from("/usr/share/applications")],
+//!     &Config::default(),
+//! )?;
+//!
+//! let resolution = apps.resolve(&"https://example.com".parse()?)?;
+//! println!("{} {:?}", resolution.program, resolution.args);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`MimeApps::read`] instead reads everything from the usual XDG locations,
+//! giving the same answers as the `handlr` binary.
+
 pub mod apps;
 pub mod cli;
 pub mod common;
 pub mod config;
 pub mod error;
+pub mod logs;
 pub mod utils;
 
 pub use apps::*;
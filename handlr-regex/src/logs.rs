@@ -0,0 +1,220 @@
+use crate::{Result, CONFIG};
+use once_cell::sync::Lazy;
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt,
+    Layer,
+};
+
+/// Log files are named `handlr.<date>.log`, one for each day handlr is used
+const LOG_PREFIX: &str = "handlr";
+const LOG_SUFFIX: &str = "log";
+
+/// How often `handlr log --follow` checks for new entries
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The directory log files are kept in, $XDG_CACHE_HOME/handlr
+///
+/// Both the log writer and `handlr log` use this, so they always agree.
+pub fn log_dir() -> Result<PathBuf> {
+    Ok(xdg::BaseDirectories::with_prefix("handlr")?
+        .create_cache_directory("")?)
+}
+
+/// Show warnings on stderr, while the log file also gets what handlr did,
+/// as a json object per line if `json_log` is set in handlr.toml
+///
+/// handlr.toml is loaded first, since it decides the format of the log file,
+/// so problems loading it are only shown on stderr.
+pub fn init() {
+    let stderr = || {
+        fmt::layer()
+            .with_writer(std::io::stderr)
+            .without_time()
+            .with_target(false)
+            .with_filter(LevelFilter::WARN)
+    };
+
+    tracing::subscriber::with_default(
+        tracing_subscriber::registry().with(stderr()),
+        || Lazy::force(&CONFIG),
+    );
+
+    let log_file = appender().ok().map(|appender| {
+        let layer = fmt::layer()
+            .with_writer(appender)
+            .with_ansi(false)
+            .with_target(false);
+        if CONFIG.json_log {
+            layer.json().with_filter(LevelFilter::INFO).boxed()
+        } else {
+            layer.with_filter(LevelFilter::INFO).boxed()
+        }
+    });
+    tracing_subscriber::registry()
+        .with(stderr())
+        .with(log_file)
+        .init();
+}
+
+/// Writer for today's log file, starting a new file each day
+pub fn appender() -> Result<RollingFileAppender> {
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix(LOG_PREFIX)
+        .filename_suffix(LOG_SUFFIX)
+        .build(log_dir()?)
+        .map_err(|e| std::io::Error::other(e).into())
+}
+
+/// Every log file in `dir`, oldest first
+fn log_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = std::fs::read_dir(dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.file_name().is_some_and(|name| {
+                let name = name.to_string_lossy();
+                name.starts_with(&format!("{}.", LOG_PREFIX))
+                    && name.ends_with(&format!(".{}", LOG_SUFFIX))
+            })
+        })
+        .collect::<Vec<_>>();
+    // Dates in the names sort the same as the days they are from
+    files.sort();
+    Ok(files)
+}
+
+/// The log file being written to, if anything has been logged yet
+pub fn current_log() -> Result<Option<PathBuf>> {
+    Ok(log_files(&log_dir()?)?.pop())
+}
+
+/// Remove all but the log files from the last `retention_days` days handlr was used
+pub fn prune(retention_days: usize) -> Result<()> {
+    prune_in(&log_dir()?, retention_days)
+}
+
+fn prune_in(dir: &Path, retention_days: usize) -> Result<()> {
+    let files = log_files(dir)?;
+    let old = files.len().saturating_sub(retention_days.max(1));
+    for file in &files[..old] {
+        std::fs::remove_file(file)?;
+    }
+    Ok(())
+}
+
+/// Print the last `lines` lines of the current log,
+/// then keep printing new entries as they are written if `follow` is set
+pub fn print(lines: usize, follow: bool) -> Result<()> {
+    let Some(mut path) = current_log()? else {
+        println!("Nothing has been logged yet");
+        return Ok(());
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    let mut file = File::open(&path)?;
+    for line in last_lines(BufReader::new(&mut file), lines)? {
+        writeln!(stdout, "{}", line)?;
+    }
+
+    if !follow {
+        return Ok(());
+    }
+
+    let mut pos = file.stream_position()?;
+    loop {
+        stdout.flush()?;
+        std::thread::sleep(FOLLOW_INTERVAL);
+
+        // Carry on in the next day's file once it is started
+        if let Some(newest) = current_log()? {
+            if newest != path {
+                path = newest;
+                pos = 0;
+            }
+        }
+
+        let mut file = File::open(&path)?;
+        // Start over if the log was cleared in the meantime
+        if file.metadata()?.len() < pos {
+            pos = 0;
+        }
+        file.seek(SeekFrom::Start(pos))?;
+        pos += std::io::copy(&mut file, &mut stdout)?;
+    }
+}
+
+/// The last `count` lines read from `reader`
+fn last_lines(reader: impl BufRead, count: usize) -> Result<Vec<String>> {
+    let mut lines = std::collections::VecDeque::with_capacity(count);
+    for line in reader.lines() {
+        if lines.len() == count {
+            lines.pop_front();
+        }
+        if count > 0 {
+            lines.push_back(line?);
+        }
+    }
+    Ok(lines.into())
+}
+
+/// Empty the current log
+pub fn clear() -> Result<()> {
+    if let Some(path) = current_log()? {
+        File::create(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_old_logs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for name in [
+            "handlr.2026-10-14.log",
+            "handlr.2026-10-12.log",
+            "handlr.2026-10-16.log",
+            "handlr.2026-10-15.log",
+            "recent.json",
+        ] {
+            std::fs::write(dir.path().join(name), "")?;
+        }
+
+        prune_in(dir.path(), 2)?;
+        let kept = std::fs::read_dir(dir.path())?
+            .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+            .collect::<Result<std::collections::BTreeSet<_>>>()?;
+
+        // Only the newest logs are kept, and nothing else is touched
+        assert_eq!(
+            kept.into_iter().collect::<Vec<_>>(),
+            [
+                "handlr.2026-10-15.log",
+                "handlr.2026-10-16.log",
+                "recent.json"
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn last_lines() -> Result<()> {
+        let log = "one\ntwo\nthree\n".as_bytes();
+
+        assert_eq!(super::last_lines(log, 2)?, ["two", "three"]);
+        assert_eq!(super::last_lines(log, 5)?, ["one", "two", "three"]);
+        assert!(super::last_lines(log, 0)?.is_empty());
+
+        Ok(())
+    }
+}
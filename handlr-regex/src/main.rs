@@ -1,78 +1,522 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use handlr_regex::{
-    apps::{self, APPS},
-    cli::Cmd,
-    common::{self, mime_table},
+    apps::{self, Picked, APPS},
+    cli::{Cli, Cmd},
+    common::{
+        self, mime_table, DirWalk, ExecOptions, GenericHandler, Handler,
+        MimeOrExtension, UserPath,
+    },
     config::CONFIG,
-    error::{ErrorKind, Result},
-    utils,
+    error::{self, ErrorKind, NotFoundKind, Result},
+    logs,
 };
-use once_cell::sync::Lazy;
-use std::io::IsTerminal;
+
+/// Most files `open --recursive` opens without `--yes`
+const RECURSIVE_OPEN_LIMIT: usize = 100;
+
+/// Get the handler to write to mimeapps.list,
+/// checking that its desktop file exists unless `force` is set
+///
+/// A similarly named installed desktop file is suggested if it does not.
+fn parse_handler(name: String, force: bool) -> Result<Handler> {
+    if force {
+        return Ok(Handler::assume_valid(name.into()));
+    }
+
+    Handler::resolve(name.clone().into()).map_err(|e| {
+        if !matches!(*e.kind, ErrorKind::NotFound(NotFoundKind::DesktopFile(_)))
+        {
+            return e;
+        }
+        match Handler::suggest(&name) {
+            Some((file_name, app_name)) => {
+                NotFoundKind::SimilarDesktopFile(name, file_name, app_name)
+                    .into()
+            }
+            None => e,
+        }
+    })
+}
+
+/// Run `post_set_hooks` for each mime changed since `before`, unless `no_hooks` is set
+fn run_hooks(apps: &apps::MimeApps, before: &apps::MimeApps, no_hooks: bool) {
+    if no_hooks {
+        return;
+    }
+    let changes = apps
+        .changes_since(before)
+        .into_iter()
+        .map(|mime| {
+            let handler = apps
+                .get_all_handlers(&mime)
+                .into_iter()
+                .next()
+                .map(|(handler, _)| handler);
+            (mime, handler)
+        })
+        .collect::<Vec<_>>();
+    apps::run_post_set_hooks(&CONFIG.post_set_hooks, &changes);
+}
 
 fn main() -> Result<()> {
-    // create config if it doesn't exist
-    Lazy::force(&CONFIG);
+    // Also creates the config if it doesn't exist
+    logs::init();
+    // Not being able to clean up old logs is no reason to fail
+    logs::prune(CONFIG.log_retention_days).ok();
 
-    let mut apps = (*APPS).clone();
+    let Cli {
+        quiet,
+        errors_json,
+        command,
+    } = Cli::parse();
 
     let res = || -> Result<()> {
-        match Cmd::parse() {
-            Cmd::Set { mime, handler } => {
-                apps.set_handler(mime.0, handler);
-                apps.save()?;
+        // Held until handlr exits, so concurrent changes are not lost
+        let _lock = if command.changes_mimeapps() {
+            Some(apps::MimeApps::lock()?)
+        } else {
+            None
+        };
+        let mut apps = (*APPS).clone();
+
+        match command {
+            Cmd::Set {
+                mime,
+                handler,
+                force,
+                sync_equivalents,
+                install,
+                glob,
+                dry_run,
+                yes,
+                no_hooks,
+            } => {
+                let (handler, install) = if install {
+                    let path = std::path::PathBuf::from(handler);
+                    (Handler::installable(&path)?, Some(path))
+                } else {
+                    (parse_handler(handler, force)?, None)
+                };
+                let before = apps.clone();
+                let mimes = if glob {
+                    let mimes = apps.set_handler_glob(mime.0.clone(), handler);
+                    if mimes.is_empty() {
+                        println!("No known mimetypes match {}", mime.0);
+                        return Ok(());
+                    }
+                    mimes.iter().for_each(|mime| println!("{}", mime));
+                    mimes
+                } else {
+                    apps.set_handler(mime.0.clone(), handler);
+                    vec![mime.0]
+                };
+                if sync_equivalents {
+                    for mime in &mimes {
+                        apps.sync_equivalents(mime);
+                    }
+                }
+
+                if dry_run {
+                    println!("Nothing was changed, since --dry-run was used");
+                } else {
+                    apps.confirm_changes(&before, yes)?;
+                    if let Some(path) = install {
+                        Handler::install(&path)?;
+                    }
+                    apps.save()?;
+                    run_hooks(&apps, &before, no_hooks);
+                }
             }
-            Cmd::Add { mime, handler } => {
-                apps.add_handler(mime.0, handler);
-                apps.save()?;
+            Cmd::Add {
+                mime,
+                handler,
+                added,
+                force,
+                yes,
+                no_hooks,
+            } => {
+                let handler = parse_handler(handler, force)?;
+                let before = apps.clone();
+                let changed = if added {
+                    apps.add_association(mime.0, handler)
+                } else {
+                    apps.add_handler(mime.0, handler)
+                };
+                if changed {
+                    apps.confirm_changes(&before, yes)?;
+                    apps.save()?;
+                    run_hooks(&apps, &before, no_hooks);
+                }
             }
-            Cmd::Launch { mime, args } => {
-                apps.get_handler(&mime.0)?.launch(
+            Cmd::Launch {
+                mime,
+                args,
+                wait,
+                detach,
+                no_term_wrap,
+                no_gpu_offload,
+                env,
+                print_pid,
+                action,
+                with,
+            } => {
+                let options = ExecOptions {
+                    no_term_wrap,
+                    no_gpu_offload,
+                    env,
+                    print_pid,
+                    quiet,
+                    ..Default::default()
+                };
+                let (handler, args) = match with {
+                    // There is no mimetype to look up, so it is an argument too
+                    Some(handler) => {
+                        let first = mime
+                            .map(|arg| arg.parse::<UserPath>())
+                            .transpose()?;
+                        (handler, first.into_iter().chain(args).collect())
+                    }
+                    None => {
+                        let mime = mime
+                            .expect("clap requires a mime without --with")
+                            .parse::<MimeOrExtension>()?;
+                        (
+                            GenericHandler::Handler(apps.get_handler(&mime.0)?),
+                            args,
+                        )
+                    }
+                };
+                handler.launch(
                     args.into_iter().map(|a| a.to_string()).collect(),
+                    wait,
+                    detach,
+                    action.as_deref(),
+                    &options,
                 )?;
             }
-            Cmd::Get { mime, json } => {
-                apps.show_handler(&mime.0, json)?;
+            Cmd::Get {
+                mime,
+                json,
+                actions,
+                installed_only,
+                verbose,
+            } => {
+                if verbose {
+                    apps.show_lookup(&mime.0, json)?;
+                } else {
+                    apps.show_handler(
+                        &mime.0,
+                        json,
+                        actions,
+                        installed_only,
+                        quiet,
+                    )?;
+                }
             }
-            Cmd::Open { paths } => apps.open_paths(&paths)?,
-            Cmd::Mime { paths, json } => {
-                mime_table(&paths, json)?;
+            Cmd::Open {
+                paths,
+                action,
+                with,
+                recursive,
+                max_depth,
+                include,
+                exclude,
+                yes,
+                confirm,
+                log_cmd,
+                no_term_wrap,
+                no_gpu_offload,
+                split,
+                env,
+                wait,
+                print_pid,
+                portal,
+                force,
+            } => {
+                apps.use_portal(portal);
+                let options = ExecOptions {
+                    log_cmd,
+                    no_term_wrap,
+                    no_gpu_offload,
+                    split,
+                    env,
+                    print_pid,
+                    quiet,
+                };
+                let paths = UserPath::expand_globs(paths);
+                let paths = if recursive {
+                    let paths = DirWalk {
+                        max_depth,
+                        include,
+                        exclude,
+                    }
+                    .expand(paths);
+
+                    if paths.len() > RECURSIVE_OPEN_LIMIT && !yes {
+                        return Err(ErrorKind::TooManyPaths(
+                            paths.len(),
+                            RECURSIVE_OPEN_LIMIT,
+                        )
+                        .into());
+                    }
+                    paths
+                } else {
+                    paths
+                };
+
+                if !force {
+                    UserPath::ensure_exist(&paths)?;
+                }
+
+                match with {
+                    Some(handler) => {
+                        let children = handler.open(
+                            paths.iter().map(|p| p.to_string()).collect(),
+                            wait,
+                            action.as_deref(),
+                            &options,
+                        )?;
+                        if wait {
+                            handlr_regex::utils::wait_all(children)?;
+                        }
+                    }
+                    None => apps.open_paths(
+                        &paths,
+                        action.as_deref(),
+                        confirm,
+                        wait,
+                        &options,
+                    )?,
+                }
             }
-            Cmd::List { all, json } => {
-                apps.print(all, json)?;
+            Cmd::Pick {
+                path,
+                save,
+                selector,
+                portal,
+            } => {
+                apps.use_portal(portal);
+                let picked = match apps.pick(&path, selector.as_deref()) {
+                    Err(e) if matches!(*e.kind, ErrorKind::Cancelled) => {
+                        return Ok(())
+                    }
+                    res => res?,
+                };
+
+                if save {
+                    match &picked {
+                        Picked::Handler(GenericHandler::Handler(handler))
+                        | Picked::Portal(Some(handler)) => {
+                            apps.set_handler(
+                                path.get_mime_with(
+                                    &CONFIG.prefer_content_detection,
+                                )?,
+                                handler.clone(),
+                            );
+                            apps.save()?;
+                        }
+                        Picked::Handler(GenericHandler::RegexHandler(_)) => {
+                            tracing::warn!(
+                                "regex handlers cannot be saved to mimeapps.list"
+                            )
+                        }
+                        Picked::Portal(None) => tracing::warn!(
+                            "the portal did not say which application was picked, so it cannot be saved"
+                        ),
+                    }
+                }
+
+                // The portal opens the path itself
+                if let Picked::Handler(handler) = picked {
+                    handler.open(
+                        vec![path.to_string()],
+                        false,
+                        None,
+                        &ExecOptions {
+                            quiet,
+                            ..Default::default()
+                        },
+                    )?;
+                }
+            }
+            Cmd::Info { json, mime } => {
+                apps.show_mime_info(&mime.0, json)?;
+            }
+            Cmd::Mime {
+                paths,
+                json,
+                jsonl,
+                hypothetical,
+                details,
+            } => {
+                let mode = if hypothetical {
+                    common::MimeMode::Hypothetical { details }
+                } else {
+                    common::MimeMode::Detect
+                };
+                mime_table(&paths, json, jsonl, mode)?;
             }
-            Cmd::Unset { mime } => {
+            Cmd::List {
+                all,
+                added,
+                system_only,
+                count,
+                sort_by,
+                filter,
+                orphans,
+                fix,
+                category,
+                json,
+                jsonl,
+                tsv,
+            } => {
+                let rows = apps::ListRows { sort_by, filter };
+                let format = if json {
+                    apps::ListFormat::Json
+                } else if jsonl {
+                    apps::ListFormat::Jsonl
+                } else if tsv {
+                    apps::ListFormat::Tsv
+                } else {
+                    apps::ListFormat::Table
+                };
+                if let Some(category) = category {
+                    apps.print_category(&category, format)?;
+                } else if orphans {
+                    apps.print_orphans(json, fix)?;
+                } else if system_only {
+                    apps.print_system_only(count, &rows, format)?;
+                } else {
+                    apps.print(all, added, count, &rows, format)?;
+                }
+            }
+            Cmd::Unset { mime, no_hooks } => {
+                let before = apps.clone();
                 apps.unset_handler(&mime.0)?;
+                run_hooks(&apps, &before, no_hooks);
+            }
+            Cmd::Purge { handler, dry_run } => {
+                apps.purge(&Handler::assume_valid(handler.into()), dry_run)?;
+            }
+            Cmd::RenameHandler { old, new, force } => {
+                let old = Handler::assume_valid(old.into());
+                let new = parse_handler(new, force)?;
+                let renamed = apps.rename_handler(&old, &new)?;
+                if renamed == 0 {
+                    println!("{} is not in mimeapps.list", old);
+                } else {
+                    apps.save()?;
+                    println!(
+                        "Replaced {} with {} in {} association(s)",
+                        old, new, renamed
+                    );
+                }
+            }
+            Cmd::Remove {
+                mime,
+                handler,
+                added,
+                wildcard,
+                no_hooks,
+            } => {
+                let before = apps.clone();
+                if added {
+                    apps.remove_association(&mime.0, &handler)?;
+                } else {
+                    apps.remove_handler(mime.0, handler, wildcard)?;
+                }
+                run_hooks(&apps, &before, no_hooks);
+            }
+            Cmd::Doctor { fix: true, yes, .. } => apps::fix(&mut apps, yes)?,
+            Cmd::Doctor {
+                json,
+                regex,
+                wildcards,
+                ..
+            } => {
+                let findings = if regex {
+                    apps::diagnose_regex(&CONFIG)
+                } else if wildcards {
+                    apps::diagnose_wildcards(&apps)
+                } else {
+                    apps::diagnose(&apps, &CONFIG)?
+                };
+                apps::print_findings(&findings, json)?;
+
+                let errors = findings
+                    .iter()
+                    .filter(|f| f.severity == apps::Severity::Error)
+                    .count();
+                if errors > 0 {
+                    return Err(ErrorKind::Unhealthy(errors).into());
+                }
+            }
+            Cmd::Status { json } => {
+                apps::print_status(&apps::status(&apps, &CONFIG)?, json)?;
+            }
+            Cmd::Watch { paths } => {
+                apps::watch(
+                    &UserPath::expand_globs(paths),
+                    &ExecOptions {
+                        quiet,
+                        ..Default::default()
+                    },
+                )?;
             }
-            Cmd::Remove { mime, handler } => {
-                apps.remove_handler(mime.0, handler)?;
+            Cmd::Recent { clear, json } => {
+                if clear {
+                    apps::RecentSelections::clear()?;
+                } else {
+                    apps::RecentSelections::load().print(json)?;
+                }
+            }
+            Cmd::Log {
+                lines,
+                follow,
+                clear,
+            } => {
+                if clear {
+                    logs::clear()?;
+                } else {
+                    logs::print(lines, follow)?;
+                }
+            }
+            Cmd::UpdateDb => {
+                let system_apps = apps::SystemApps::update_cache()?;
+                println!(
+                    "Indexed {} desktop entries for {} mimetypes",
+                    system_apps.entry_count(),
+                    system_apps.0.len()
+                );
+            }
+            Cmd::Completions { shell } => {
+                clap_complete::generate(
+                    shell,
+                    &mut Cli::command(),
+                    "handlr",
+                    &mut std::io::stdout(),
+                );
             }
             Cmd::Autocomplete {
                 desktop_files,
                 mimes,
+                configured,
             } => {
                 if desktop_files {
                     apps::MimeApps::list_handlers()?;
                 } else if mimes {
                     common::db_autocomplete()?;
+                } else if configured {
+                    apps.list_configured_mimes()?;
                 }
             }
         }
         Ok(())
     }();
 
-    match (res, std::io::stdout().is_terminal()) {
-        (Err(e), _) if matches!(*e.kind, ErrorKind::Cancelled) => {
-            std::process::exit(1);
-        }
-        (Err(e), true) => {
-            eprintln!("{}", e);
-            std::process::exit(1);
-        }
-        (Err(e), false) => {
-            utils::notify("handlr error", &e.to_string())?;
-            std::process::exit(1);
-        }
-        _ => Ok(()),
+    if let Err(e) = res {
+        error::handle(e, errors_json, quiet);
     }
+
+    Ok(())
 }
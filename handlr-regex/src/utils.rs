@@ -1,7 +1,180 @@
 use crate::Result;
+use std::{
+    fs::File,
+    io::{BufRead, Write},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// Wait for every child to exit, failing with the exit code of the first one that failed
+pub fn wait_all(children: Vec<std::process::Child>) -> Result<()> {
+    let mut failed = None;
+    for mut child in children {
+        let status = child.wait()?;
+        if !status.success() && failed.is_none() {
+            // Processes killed by a signal have no exit code
+            failed = Some(status.code().unwrap_or(1));
+        }
+    }
+
+    match failed {
+        Some(code) => Err(crate::ErrorKind::ChildExit(code).into()),
+        None => Ok(()),
+    }
+}
+
+/// Print a command about to be run to stderr, for `--log-cmd`
+pub fn log_cmd(cmd: &std::process::Command) {
+    let argv = std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>();
+    let line = shlex::try_join(argv.iter().map(|arg| arg.as_ref()))
+        .unwrap_or_else(|_| argv.join(" "));
+    eprintln!("handlr: running: {}", line);
+}
+
+/// Show a desktop notification, for callers to skip when `--quiet` was given
+///
+/// Fails if `notify-send` could not be run, i.e. when it is not installed.
 pub fn notify(title: &str, msg: &str) -> Result<()> {
     std::process::Command::new("notify-send")
         .args(["-t", "10000", title, msg])
         .spawn()?;
     Ok(())
 }
+
+/// Whether a program can be run, either by path or by searching $PATH
+pub fn is_installed(program: &str) -> bool {
+    if program.contains('/') {
+        return std::path::Path::new(program).exists();
+    }
+
+    std::env::var_os("PATH").is_some_and(|path| {
+        std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+    })
+}
+
+/// Take an exclusive advisory lock on the file at `path`, creating it if needed
+///
+/// Blocks until no other process holds the lock,
+/// which is released when the returned file is dropped.
+pub(crate) fn lock_file(path: &Path) -> Result<File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let lock = File::create(path)?;
+    // SAFETY: the descriptor stays open until the lock is released
+    if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(lock)
+}
+
+/// Replace the file at `path` with `contents` all at once
+///
+/// The contents are written to `temp_path(path)`, synced to disk and renamed over `path`,
+/// so it is never left half-written, even if handlr is killed while saving.
+/// Symlinks are followed so the file they point to is replaced instead,
+/// and the file keeps its permissions.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let tmp = temp_path(&path);
+
+    let res = (|| -> Result<()> {
+        let mut f = File::create(&tmp)?;
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            f.set_permissions(metadata.permissions())?;
+        }
+        f.write_all(contents)?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    })();
+
+    if res.is_err() {
+        std::fs::remove_file(&tmp).ok();
+    }
+    res
+}
+
+/// The temporary file `write_atomic` writes to before renaming it over `path`
+///
+/// It is in the same directory, since renaming only replaces files atomically
+/// within a filesystem, and named after the process so concurrent writes do not clash.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Ask a yes/no question on the terminal, defaulting to no
+pub fn confirm(prompt: &str) -> Result<bool> {
+    confirm_with(prompt, std::io::stdin().lock(), std::io::stderr())
+}
+
+fn confirm_with(
+    prompt: &str,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<bool> {
+    write!(output, "{} [y/N] ", prompt)?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_writes() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("mimeapps.list");
+        let link = dir.path().join("link.list");
+
+        assert_eq!(
+            temp_path(&path),
+            dir.path()
+                .join(format!("mimeapps.list.{}.tmp", std::process::id()))
+        );
+
+        write_atomic(&path, b"first")?;
+        std::os::unix::fs::symlink(&path, &link)?;
+        write_atomic(&link, b"second")?;
+
+        // Writing through the link replaces its target, not the link
+        assert_eq!(std::fs::read_to_string(&path)?, "second");
+        assert!(std::fs::symlink_metadata(&link)?.file_type().is_symlink());
+        // And nothing is left behind
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn confirm() -> Result<()> {
+        for (answer, expected) in [
+            ("y\n", true),
+            ("YES\n", true),
+            ("n\n", false),
+            ("\n", false),
+            ("", false),
+        ] {
+            let mut output = Vec::new();
+            assert_eq!(
+                confirm_with("Open?", answer.as_bytes(), &mut output)?,
+                expected,
+                "{:?}",
+                answer
+            );
+            assert_eq!(output, b"Open? [y/N] ");
+        }
+
+        Ok(())
+    }
+}
@@ -1,9 +1,10 @@
 use clap::{CommandFactory, Parser};
-use handlr_regex::Cmd;
+use clap_complete::Shell;
+use handlr_regex::Cli;
 use std::{
     env,
     error::Error,
-    fs::remove_file,
+    fs::{create_dir_all, remove_file},
     path::{Path, PathBuf},
 };
 
@@ -12,6 +13,11 @@ type DynResult = Result<(), Box<dyn Error>>;
 fn main() -> DynResult {
     match Task::parse() {
         Task::Mangen => mangen()?,
+        Task::Completions => completions()?,
+        Task::Dist => {
+            mangen()?;
+            completions()?;
+        }
     }
 
     Ok(())
@@ -23,7 +29,7 @@ fn mangen() -> DynResult {
     eprintln!("Generating man pages");
 
     let out_dir = assets_dir().join("manual/man1");
-    let cmd = Cmd::command().name("handlr");
+    let cmd = Cli::command().name("handlr");
 
     clap_mangen::generate_to(cmd, &out_dir)?;
 
@@ -33,10 +39,37 @@ fn mangen() -> DynResult {
     Ok(())
 }
 
+/// Action for `cargo xtask completions`
+/// Generate shell completion scripts
+///
+/// These are kept apart from the handwritten ones in assets/completions,
+/// which also complete mimetypes and desktop files.
+fn completions() -> DynResult {
+    eprintln!("Generating shell completions");
+
+    let out_dir = assets_dir().join("completions/generated");
+    create_dir_all(&out_dir)?;
+
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        clap_complete::generate_to(
+            shell,
+            &mut Cli::command(),
+            "handlr",
+            &out_dir,
+        )?;
+    }
+
+    Ok(())
+}
+
 #[derive(Parser, Clone, Copy, Debug)]
 enum Task {
     /// generate man page
     Mangen,
+    /// generate shell completions
+    Completions,
+    /// generate man pages and shell completions
+    Dist,
 }
 
 // Project root